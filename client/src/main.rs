@@ -1,27 +1,76 @@
 use clap::Parser;
-use shared::HANDSHAKE_MSG;
+use rustls::{ClientConnection, StreamOwned};
+use shared::tls::{client_config, ReadWrite};
+use shared::{GAME_SEPARATOR, HANDSHAKE_MSG, RESUME_PREFIX};
+use std::path::PathBuf;
 use std::{
-    io::{Read as _, Write},
+    io::{Read, Write},
     net::TcpStream,
 };
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 struct Args {
-    #[arg(short('n'), long, help = "Team name")]
-    team: String,
+    #[arg(
+        short('n'),
+        long,
+        required_unless_present = "resume",
+        help = "Team name"
+    )]
+    team: Option<String>,
 
     #[arg(short, long, default_value_t = 8080, help = "Port of the server.")]
     port: u16,
 
     #[arg(short, long, default_value_t = String::from("127.0.0.1"), help = "Address of the server.")]
     address: String,
+
+    #[arg(
+        short,
+        long,
+        help = "Game to join (the default game of the server if not set)."
+    )]
+    game: Option<String>,
+
+    #[arg(
+        short,
+        long,
+        conflicts_with = "team",
+        help = "Session token of a player to resume instead of joining a team."
+    )]
+    resume: Option<String>,
+
+    #[arg(long, requires = "ca", help = "Connect with TLS.")]
+    tls: bool,
+
+    #[arg(
+        long,
+        requires = "tls",
+        help = "PEM file of the certificates trusted to sign the one of the server."
+    )]
+    ca: Option<PathBuf>,
+
+    #[arg(
+        long,
+        help = "Name the certificate of the server is checked against (the address if not set)."
+    )]
+    server_name: Option<String>,
+}
+
+fn connect(args: &Args) -> Result<Box<dyn ReadWrite>, Box<dyn std::error::Error>> {
+    let stream = TcpStream::connect(format!("{}:{}", args.address, args.port))?;
+    let Some(ca) = &args.ca else {
+        return Ok(Box::new(stream));
+    };
+    let (config, name) = client_config(ca, args.server_name.as_ref().unwrap_or(&args.address))?;
+    let connection = ClientConnection::new(config, name)?;
+    Ok(Box::new(StreamOwned::new(connection, stream)))
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args = Args::parse();
 
-    let mut stream = TcpStream::connect(format!("{}:{}", args.address, args.port))?;
+    let mut stream = connect(&args)?;
     eprintln!("Connected to server");
 
     let mut buffer = [0; 1024];
@@ -33,7 +82,13 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     }
 
     // TODO validate team
-    stream.write_all(args.team.as_bytes())?;
+    let handshake = match (&args.resume, &args.team, &args.game) {
+        (Some(token), _, _) => format!("{RESUME_PREFIX}{token}"),
+        (None, Some(team), Some(game)) => format!("{team}{GAME_SEPARATOR}{game}"),
+        (None, Some(team), None) => team.clone(),
+        (None, None, _) => unreachable!("clap requires a team without a token"),
+    };
+    stream.write_all(format!("{handshake}\n").as_bytes())?;
     let bytes_read = stream.read(&mut buffer).unwrap();
     let response = String::from_utf8_lossy(&buffer[..bytes_read]);
     println!("Server response: {}", response);
@@ -3,15 +3,20 @@ mod torus;
 
 use clap::Parser;
 use clap::ValueEnum;
+use rustls::pki_types::ServerName;
 use serde_json::from_str;
-use shared::{GFXData, GFX_PORT};
+use shared::tls::{client_config, AsyncReadWrite};
+use shared::{GFXData, DEFAULT_GAME, GFX_PORT, GFX_SHUTDOWN_MSG};
 use std::error::Error;
 use std::fmt::Debug;
-use tokio::io::{AsyncBufReadExt, BufReader};
+use std::path::PathBuf;
+use std::pin::Pin;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
 use tokio::net::TcpStream;
 use tokio::sync::mpsc;
 use tokio::sync::mpsc::UnboundedReceiver;
 use tokio::time::Duration;
+use tokio_rustls::TlsConnector;
 
 enum Message {
     Disconnect(Box<dyn Error + Send>),
@@ -29,6 +34,47 @@ struct Args {
 
     #[arg(short, long, value_enum, default_value_t = Engine::Torus, help = "Engine used for rendering.")]
     engine: Engine,
+
+    #[arg(short, long, default_value_t = String::from(DEFAULT_GAME), help = "Game to watch.")]
+    game: String,
+
+    #[arg(long, requires = "ca", help = "Connect with TLS.")]
+    tls: bool,
+
+    #[arg(
+        long,
+        requires = "tls",
+        help = "PEM file of the certificates trusted to sign the one of the server."
+    )]
+    ca: Option<PathBuf>,
+
+    #[arg(
+        long,
+        help = "Name the certificate of the server is checked against (the address if not set)."
+    )]
+    server_name: Option<String>,
+}
+
+/// The TLS connector trusting the certificates of `--ca`, if the server port uses TLS.
+fn tls_connector(
+    args: &Args,
+) -> Result<Option<(TlsConnector, ServerName<'static>)>, Box<dyn Error>> {
+    let Some(ca) = &args.ca else {
+        return Ok(None);
+    };
+    let (config, name) = client_config(ca, args.server_name.as_ref().unwrap_or(&args.address))?;
+    Ok(Some((TlsConnector::from(config), name)))
+}
+
+async fn connect(
+    args: &Args,
+    tls: &Option<(TlsConnector, ServerName<'static>)>,
+) -> std::io::Result<Pin<Box<dyn AsyncReadWrite + Send>>> {
+    let stream = TcpStream::connect(format!("{}:{}", args.address, args.port)).await?;
+    match tls {
+        Some((connector, name)) => Ok(Box::pin(connector.connect(name.clone(), stream).await?)),
+        None => Ok(Box::pin(stream)),
+    }
 }
 
 #[derive(ValueEnum, Clone, Debug, PartialEq, Eq)]
@@ -52,18 +98,30 @@ impl Engine {
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args = Args::parse();
+    let tls = tls_connector(&args)?;
 
     let (data_tx, data_rx) = mpsc::unbounded_channel();
 
     tokio::spawn(async move {
         loop {
-            match TcpStream::connect(format!("{}:{}", args.address, args.port)).await {
+            match connect(&args, &tls).await {
                 Ok(stream) => {
                     eprintln!("Connected to server");
-                    let reader = BufReader::new(stream);
+                    let (reader, mut writer) = tokio::io::split(stream);
+                    if let Err(e) = writer
+                        .write_all(format!("{}\n", args.game).as_bytes())
+                        .await
+                    {
+                        eprintln!("Failed to subscribe to game {}: {}", args.game, e);
+                    }
+                    let reader = BufReader::new(reader);
                     let mut lines = reader.lines();
 
                     while let Ok(Some(line)) = lines.next_line().await {
+                        if line == GFX_SHUTDOWN_MSG.trim_end() {
+                            eprintln!("{line}");
+                            break;
+                        }
                         match from_str::<GFXData>(&line) {
                             Ok(new_state) => match data_tx.send(Message::State(new_state)) {
                                 Err(se) => {
@@ -0,0 +1,12 @@
+#!/bin/sh
+# Generates the test CA and the certificate of the server it signs, for the name "localhost".
+set -e
+cd "$(dirname "$0")"
+openssl req -x509 -newkey rsa:2048 -nodes -days 36500 -subj "/CN=Zappy test CA" \
+    -addext "basicConstraints=critical,CA:TRUE" -addext "keyUsage=critical,keyCertSign" \
+    -keyout ca.key -out ca.pem
+openssl req -newkey rsa:2048 -nodes -subj "/CN=localhost" -keyout key.pem -out server.csr
+printf "subjectAltName=DNS:localhost\nbasicConstraints=CA:FALSE\nextendedKeyUsage=serverAuth\n" > server.ext
+openssl x509 -req -in server.csr -CA ca.pem -CAkey ca.key -CAcreateserial -days 36500 \
+    -extfile server.ext -out cert.pem
+rm -f ca.key ca.srl server.csr server.ext
@@ -0,0 +1,131 @@
+use crate::game_engine::GameEngine;
+use crate::history::{History, MatchRecord};
+use crate::ratings::Ratings;
+use crate::report::GameReport;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+/// Where the results of the games are kept: ratings, history and reports.
+#[derive(Clone)]
+pub struct Archive {
+    ratings: Arc<Mutex<Ratings>>,
+    history: Arc<Mutex<History>>,
+    /// Directory of the game reports, none are written if not set.
+    report_dir: Option<PathBuf>,
+}
+
+impl Archive {
+    pub fn new(ratings: Ratings, history: History, report_dir: Option<PathBuf>) -> Self {
+        Self {
+            ratings: Arc::new(Mutex::new(ratings)),
+            history: Arc::new(Mutex::new(history)),
+            report_dir,
+        }
+    }
+
+    pub fn ratings(&self) -> &Mutex<Ratings> {
+        &self.ratings
+    }
+
+    pub fn history(&self) -> &Mutex<History> {
+        &self.history
+    }
+
+    /// Rates, stores and reports a game that reached an outcome. The files and the database are
+    /// written on a blocking thread, for a slow disk not to hold the other games.
+    pub async fn record_game_over(&self, name: &str, started_at: &str, engine: &GameEngine) {
+        for line in engine.stats_report() {
+            log::info!("{name}: {line}");
+        }
+        let scores = engine.team_scores();
+        let winner = engine.winner();
+        let mut record = MatchRecord::new(name, started_at.to_string(), engine);
+        let report = self.report(name, started_at, engine);
+        let archive = self.clone();
+        let game = name.to_string();
+        let recorded = tokio::task::spawn_blocking(move || {
+            archive
+                .ratings
+                .blocking_lock()
+                .record(&scores, winner.as_deref());
+            record.report = report
+                .and_then(|report| archive.write_report(&report))
+                .map(|path| path.display().to_string());
+            match archive.history.blocking_lock().insert(&record) {
+                Ok(id) => log::info!("Game \"{game}\" stored in the history as #{id}"),
+                Err(e) => log::error!("Failed to store game \"{game}\" in the history: {e}"),
+            }
+        })
+        .await;
+        if let Err(e) = recorded {
+            log::error!("Failed to record game \"{name}\": {e}");
+        }
+    }
+
+    /// The report of a game if a report directory is set.
+    pub fn report(&self, name: &str, started_at: &str, engine: &GameEngine) -> Option<GameReport> {
+        self.report_dir
+            .as_ref()
+            .map(|_| GameReport::new(name, started_at, engine))
+    }
+
+    /// Writes a report in the report directory, returns its path. Blocks on the file system.
+    pub fn write_report(&self, report: &GameReport) -> Option<PathBuf> {
+        let dir = self.report_dir.as_ref()?;
+        let name = &report.game;
+        match report.write(dir) {
+            Ok(path) => {
+                log::info!("Report of game \"{name}\" written to {}", path.display());
+                Some(path)
+            }
+            Err(e) => {
+                log::error!("Failed to write the report of game \"{name}\": {e}");
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod archive_tests {
+    use super::*;
+    use crate::args::ServerArgs;
+    use crate::history::MatchFilter;
+    use clap::Parser;
+
+    #[tokio::test]
+    async fn records_game_over_off_the_runtime() {
+        // Given
+        let dir = std::env::temp_dir().join(format!("zappy-archive-{}", std::process::id()));
+        let archive = Archive::new(
+            Ratings::default(),
+            History::open(None).unwrap(),
+            Some(dir.clone()),
+        );
+        let args =
+            ServerArgs::try_parse_from(["server", "-x", "5", "-y", "4", "-n", "anton", "axel"])
+                .unwrap();
+        let mut engine = GameEngine::new(&args);
+        engine.add_player(1, "axel".to_string()).unwrap();
+
+        // When
+        archive
+            .record_game_over("ladder", &crate::history::now(), &engine)
+            .await;
+        let records = archive
+            .history()
+            .lock()
+            .await
+            .query(&MatchFilter::default())
+            .unwrap();
+        let rating = archive.ratings().lock().await.get("axel").cloned();
+        let report = records[0].report.clone();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        // Then
+        assert_eq!(records.len(), 1);
+        assert!(report.is_some_and(|path| path.starts_with(dir.to_str().unwrap())));
+        assert_eq!(rating.map(|rating| rating.wins), Some(1));
+    }
+}
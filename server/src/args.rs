@@ -1,21 +1,51 @@
+use crate::bot::Difficulty;
+use crate::connection::DEFAULT_MAX_LINE_LENGTH;
+use crate::gym::GymMode;
+use crate::listener::ListenAddress;
+use crate::outbox::SlowClientPolicy;
+use crate::rules::{ResolutionPolicy, VisionPolicy};
 use clap::Parser;
 use derive_builder::Builder;
-use shared::{MAX_PLAYERS_IN_TEAM, MAX_TEAMS};
+use shared::map::Map;
+use shared::{ADMIN_PORT, GFX_PORT, MAX_MAP_SIZE, MAX_PLAYERS_IN_TEAM, MAX_TEAMS};
+use std::path::PathBuf;
 
 // TODO: more default values
 
-#[derive(Parser, Debug, Builder, Default)]
+const DEFAULT_SEND_QUEUE: usize = 256;
+
+#[derive(Parser, Debug, Builder, Default, Clone)]
 #[builder(setter(into))]
 #[command(version, about, long_about = None)]
 pub(crate) struct ServerArgs {
     #[arg(short, long, help = "Port number", default_value_t = 8080)]
     pub(crate) port: u16,
 
-    #[arg(short('x'), long, value_parser = validate_dimension, help = "World width")]
-    pub(crate) width: usize,
+    #[arg(
+        short('x'),
+        long,
+        value_parser = validate_dimension,
+        required_unless_present = "map",
+        help = "World width"
+    )]
+    pub(crate) width: Option<usize>,
+
+    #[arg(
+        short('y'),
+        long,
+        value_parser = validate_dimension,
+        required_unless_present = "map",
+        help = "World height"
+    )]
+    pub(crate) height: Option<usize>,
 
-    #[arg(short('y'), long, value_parser = validate_dimension, help = "World height")]
-    pub(crate) height: usize,
+    #[arg(
+        long,
+        value_parser = read_map,
+        help = "Text file of the starting map, replacing -x, -y and the random resources and eggs"
+    )]
+    #[builder(default)]
+    pub(crate) map: Option<Map>,
 
     #[arg(
         short,
@@ -38,18 +68,377 @@ pub(crate) struct ServerArgs {
         short,
         long,
         help = "List of team names",
-        required = true,
+        required_unless_present = "tournament",
         num_args = 1..=MAX_TEAMS
     )]
     pub(crate) names: Vec<String>,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = ResolutionPolicy::default(),
+        help = "Order in which the actions resolving during the same frame are applied"
+    )]
+    #[builder(default)]
+    pub(crate) resolution: ResolutionPolicy,
+
+    #[arg(
+        long,
+        help = "Maximum distance in cells at which a broadcast can be heard"
+    )]
+    #[builder(default)]
+    pub(crate) hearing_radius: Option<usize>,
+
+    #[arg(
+        long,
+        help = "Report the distance to the sender alongside the broadcast direction"
+    )]
+    #[builder(default)]
+    pub(crate) broadcast_distance: bool,
+
+    #[arg(
+        long,
+        default_value_t = 0.,
+        value_parser = validate_probability,
+        help = "Probability per cell of distance that a broadcast character is garbled"
+    )]
+    #[builder(default)]
+    pub(crate) garble_rate: f64,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = VisionPolicy::default(),
+        help = "Cells reported by the see command when the field of view wraps around the map"
+    )]
+    #[builder(default)]
+    pub(crate) vision: VisionPolicy,
+
+    #[arg(long, help = "The game ends after this frame even if no team won")]
+    #[builder(default)]
+    pub(crate) max_frames: Option<u64>,
+
+    #[arg(long, help = "Seed of the game random generator (random if not set)")]
+    #[builder(default)]
+    pub(crate) seed: Option<u64>,
+
+    #[arg(
+        long,
+        help = "Play the tournament described in this JSON file, with the other arguments as rules"
+    )]
+    #[builder(default)]
+    pub(crate) tournament: Option<PathBuf>,
+
+    #[arg(
+        long,
+        help = "JSON file keeping the team ratings across games (in memory only if not set)"
+    )]
+    #[builder(default)]
+    pub(crate) ratings: Option<PathBuf>,
+
+    #[arg(
+        long,
+        help = "SQLite database storing the finished games (in memory only if not set)"
+    )]
+    #[builder(default)]
+    pub(crate) history: Option<PathBuf>,
+
+    #[arg(
+        long,
+        help = "Directory where a JSON and a Markdown report of every game are written"
+    )]
+    #[builder(default)]
+    pub(crate) report_dir: Option<PathBuf>,
+
+    #[arg(
+        long,
+        help = "World metrics file of every game, CSV if its extension is .csv and JSON lines otherwise"
+    )]
+    #[builder(default)]
+    pub(crate) metrics: Option<PathBuf>,
+
+    #[arg(
+        long,
+        default_value_t = 100,
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "Frames between two samples of the world, for the metrics and the game reports"
+    )]
+    #[builder(default = "100")]
+    pub(crate) metrics_interval: u64,
+
+    #[arg(
+        long,
+        help = "Fill the free slots of the bot teams with bots of this difficulty"
+    )]
+    #[builder(default)]
+    pub(crate) bots: Option<Difficulty>,
+
+    #[arg(
+        long,
+        num_args = 1..,
+        requires = "bots",
+        help = "Teams played by the bots (all if not set)"
+    )]
+    #[builder(default)]
+    pub(crate) bot_teams: Vec<String>,
+
+    #[arg(
+        long,
+        default_value_t = 1,
+        help = "Free slots of every bot team left to the human players"
+    )]
+    #[builder(default = "1")]
+    pub(crate) human_slots: u16,
+
+    #[arg(
+        long,
+        value_name = "stdio|SOCKET",
+        help = "Run the game as a reinforcement-learning environment driven with JSON lines instead of serving clients"
+    )]
+    #[builder(default)]
+    pub(crate) gym: Option<GymMode>,
+
+    #[arg(
+        long,
+        default_value_t = DEFAULT_MAX_LINE_LENGTH,
+        value_parser = validate_line_length,
+        help = "Longest line accepted from player and admin clients, in bytes"
+    )]
+    #[builder(default = "DEFAULT_MAX_LINE_LENGTH")]
+    pub(crate) max_line_length: usize,
+
+    #[arg(
+        long,
+        default_value_t = DEFAULT_SEND_QUEUE,
+        value_parser = validate_send_queue,
+        help = "Messages waiting to be sent to a player before it is considered too slow"
+    )]
+    #[builder(default = "DEFAULT_SEND_QUEUE")]
+    pub(crate) send_queue: usize,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = SlowClientPolicy::Disconnect,
+        help = "What happens to the messages of a player whose send queue is full"
+    )]
+    #[builder(default)]
+    pub(crate) slow_clients: SlowClientPolicy,
+
+    #[arg(
+        long,
+        default_value_t = 0,
+        help = "Seconds a disconnected player waits for a resume with its session token (0 disables the sessions)"
+    )]
+    #[builder(default)]
+    pub(crate) resume_grace: u64,
+
+    #[arg(
+        long,
+        default_value_t = 10,
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "Seconds a client has to send each line of the handshake"
+    )]
+    #[builder(default = "10")]
+    pub(crate) handshake_timeout: u64,
+
+    #[arg(
+        long,
+        default_value_t = 10,
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "Seconds a client of a TLS port has to negotiate TLS"
+    )]
+    #[builder(default = "10")]
+    pub(crate) tls_timeout: u64,
+
+    #[arg(
+        long,
+        default_value_t = 300,
+        help = "Seconds without any line from a player or admin client before it is disconnected (0 keeps them)"
+    )]
+    #[builder(default = "300")]
+    pub(crate) idle_timeout: u64,
+
+    #[arg(
+        long,
+        default_value_t = 1024,
+        help = "Open connections on the player, gfx and admin ports together"
+    )]
+    #[builder(default = "1024")]
+    pub(crate) max_connections: usize,
+
+    #[arg(
+        long,
+        default_value_t = 32,
+        help = "Open connections of a single address"
+    )]
+    #[builder(default = "32")]
+    pub(crate) max_connections_per_ip: usize,
+
+    #[arg(
+        long,
+        default_value_t = 10,
+        value_parser = clap::value_parser!(u32).range(1..),
+        help = "Connections per second of a single address, more gets it banned"
+    )]
+    #[builder(default = "10")]
+    pub(crate) connection_rate: u32,
+
+    #[arg(
+        long,
+        default_value_t = 200,
+        value_parser = clap::value_parser!(u32).range(1..),
+        help = "Connections per second of all the addresses"
+    )]
+    #[builder(default = "200")]
+    pub(crate) global_connection_rate: u32,
+
+    #[arg(
+        long,
+        value_parser = clap::value_parser!(u32).range(1..),
+        help = "Lines per second of a player or admin connection, more gets its address banned (no limit if not set)"
+    )]
+    #[builder(default)]
+    pub(crate) command_rate: Option<u32>,
+
+    #[arg(
+        long,
+        default_value_t = 600,
+        help = "Seconds an address flooding the server is banned"
+    )]
+    #[builder(default = "600")]
+    pub(crate) ban_duration: u64,
+
+    #[arg(
+        long,
+        help = "JSON file keeping the bans across server restarts (in memory only if not set)"
+    )]
+    #[builder(default)]
+    pub(crate) bans: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "ADDRESS",
+        help = "Address of the player port: host:port, [ipv6]:port or unix:<path>, port 0 being any free port (127.0.0.1 and -p if not set)"
+    )]
+    #[builder(default)]
+    pub(crate) listen: Option<ListenAddress>,
+
+    #[arg(
+        long,
+        value_name = "ADDRESS",
+        help = "Address of the admin port, like --listen (127.0.0.1:4444 if not set)"
+    )]
+    #[builder(default)]
+    pub(crate) admin_listen: Option<ListenAddress>,
+
+    #[arg(
+        long,
+        value_name = "ADDRESS",
+        help = "Address of the gfx port, like --listen (127.0.0.1:4343 if not set)"
+    )]
+    #[builder(default)]
+    pub(crate) gfx_listen: Option<ListenAddress>,
+
+    #[arg(
+        long,
+        help = "Require TLS on the player port, with the certificate of the admin port"
+    )]
+    #[builder(default)]
+    pub(crate) tls: bool,
+
+    #[arg(
+        long,
+        help = "Require TLS on the gfx port, with the certificate of the admin port"
+    )]
+    #[builder(default)]
+    pub(crate) gfx_tls: bool,
+
+    #[arg(
+        long,
+        default_value_t = 5,
+        help = "Seconds the connections have to close once the server is stopping"
+    )]
+    #[builder(default = "5")]
+    pub(crate) shutdown_timeout: u64,
+
+    #[arg(
+        long,
+        help = "JSON file where the state of every game is written when the server stops"
+    )]
+    #[builder(default)]
+    pub(crate) snapshot: Option<PathBuf>,
+}
+
+impl ServerArgs {
+    pub(crate) fn client_address(&self) -> ListenAddress {
+        self.listen
+            .clone()
+            .unwrap_or(ListenAddress::tcp([127, 0, 0, 1], self.port))
+    }
+
+    pub(crate) fn admin_address(&self) -> ListenAddress {
+        self.admin_listen
+            .clone()
+            .unwrap_or(ListenAddress::tcp([127, 0, 0, 1], ADMIN_PORT))
+    }
+
+    pub(crate) fn gfx_address(&self) -> ListenAddress {
+        self.gfx_listen
+            .clone()
+            .unwrap_or(ListenAddress::tcp([127, 0, 0, 1], GFX_PORT))
+    }
+
+    /// Width and height of the world: the ones of the map file if any, `-x` and `-y` otherwise.
+    pub(crate) fn dimensions(&self) -> (usize, usize) {
+        match &self.map {
+            Some(map) => (*map.width(), *map.height()),
+            None => (
+                self.width.unwrap_or_default(),
+                self.height.unwrap_or_default(),
+            ),
+        }
+    }
 }
 
 fn validate_dimension(s: &str) -> Result<usize, String> {
     let dimension: usize = s.parse().map_err(|_| "Not a valid number")?;
-    if (2..=100).contains(&dimension) {
+    check_dimension(dimension)
+}
+
+fn check_dimension(dimension: usize) -> Result<usize, String> {
+    if (2..=MAX_MAP_SIZE).contains(&dimension) {
         Ok(dimension)
     } else {
-        Err(format!("Grid dimensions must be between 2 and 100"))
+        Err(format!(
+            "Grid dimensions must be between 2 and {MAX_MAP_SIZE}"
+        ))
+    }
+}
+
+fn read_map(s: &str) -> Result<Map, String> {
+    let text = std::fs::read_to_string(s).map_err(|e| format!("Can't read {s}: {e}"))?;
+    let map = Map::from_text(&text)?;
+    check_dimension(*map.width())?;
+    check_dimension(*map.height())?;
+    Ok(map)
+}
+
+fn validate_line_length(s: &str) -> Result<usize, String> {
+    let length: usize = s.parse().map_err(|_| "Not a valid number")?;
+    if length >= 16 {
+        Ok(length)
+    } else {
+        Err("The maximum line length must be at least 16".to_string())
+    }
+}
+
+fn validate_send_queue(s: &str) -> Result<usize, String> {
+    let capacity: usize = s.parse().map_err(|_| "Not a valid number")?;
+    if capacity >= 1 {
+        Ok(capacity)
+    } else {
+        Err("The send queue must hold at least 1 message".to_string())
     }
 }
 
@@ -63,3 +452,12 @@ fn validate_clients(s: &str) -> Result<u16, String> {
         ))
     }
 }
+
+fn validate_probability(s: &str) -> Result<f64, String> {
+    let probability: f64 = s.parse().map_err(|_| "Not a valid number")?;
+    if (0. ..=1.).contains(&probability) {
+        Ok(probability)
+    } else {
+        Err("Probability must be between 0 and 1".to_string())
+    }
+}
@@ -0,0 +1,507 @@
+use crate::game_engine::GameEngine;
+use crate::game_manager::Game;
+use clap::ValueEnum;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use shared::commands::PlayerCmd;
+use shared::player::Player;
+use shared::resource::{Resource, Stone, StoneSet};
+use shared::{vision, ServerCommandToClient, ServerResponse, LIFE_TICKS, LIVES_START};
+use std::collections::VecDeque;
+use std::iter;
+
+/// Life under which a bot looks for food before anything else.
+const HUNGRY_LIFE: u64 = 5 * LIFE_TICKS;
+/// Commands between two `Inventory` of a bot.
+const INVENTORY_REFRESH: usize = 8;
+/// Broadcast by a hard bot having the stones to level up, followed by its team and level.
+const RALLY_CALL: &str = "rally";
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Difficulty {
+    /// Only looks for food.
+    Easy,
+    /// Also collects stones and levels up with the players that happen to be on its cell.
+    Medium,
+    /// Also calls its teammates to level up together and answers their calls.
+    Hard,
+}
+
+/// Server-hosted players filling the free slots of some teams.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Bots {
+    pub(crate) difficulty: Difficulty,
+    /// Every team if empty.
+    pub(crate) teams: Vec<String>,
+    /// Free slots of every bot team the bots leave to the human players.
+    pub(crate) human_slots: u16,
+}
+
+impl Bots {
+    /// Adds a bot to the game for every free slot of the bot teams, but the ones left to the
+    /// human players.
+    pub(crate) fn fill(&self, game: &Game, engine: &mut GameEngine) {
+        let teams: Vec<String> = engine
+            .teams()
+            .iter()
+            .filter(|(name, _)| self.teams.is_empty() || self.teams.contains(name))
+            .flat_map(|(name, team)| {
+                let slots = team.remaining_members().saturating_sub(self.human_slots);
+                iter::repeat_n(name.clone(), slots as usize)
+            })
+            .collect();
+        for team in teams {
+            let Some(id) = game.ids.allocate() else {
+                log::warn!("No id left for a bot");
+                return;
+            };
+            if let Err(e) = engine.add_player(id, team.clone()) {
+                log::error!("Failed to add a bot to team {team}: {e}");
+                game.ids.release(id);
+                return;
+            }
+            log::info!("Bot {id} ({:?}) joined team {team}", self.difficulty);
+            let seed = engine.seed() ^ id as u64;
+            tokio::spawn(bot_routine(id, team, game.clone(), self.difficulty, seed));
+        }
+    }
+}
+
+/// Plays the already added player `id` until it dies or the game stops.
+async fn bot_routine(id: u16, team: String, game: Game, difficulty: Difficulty, seed: u64) {
+    let mut rx = game.connect(id).await;
+    let mut brain = Brain::new(difficulty, team, seed);
+    'bot: loop {
+        let command = brain.next_command();
+        let queued = command.clone();
+        if game
+            .engine
+            .call(move |engine| engine.take_command(&id, queued))
+            .await
+            .and_then(|taken| taken)
+            .is_err()
+        {
+            break;
+        }
+        loop {
+            match rx.recv().await {
+                Some(ServerCommandToClient::SendMessage(response)) => {
+                    match brain.on_response(&command, response) {
+                        Reaction::Answered => break,
+                        Reaction::Waiting => {}
+                        Reaction::Dead => break 'bot,
+                    }
+                }
+                Some(ServerCommandToClient::Shutdown) | None => break 'bot,
+            }
+        }
+    }
+    game.player_senders.lock().await.remove(&id);
+    let _ = game
+        .engine
+        .call(move |engine| engine.remove_player(id))
+        .await;
+    game.ids.release(id);
+    log::debug!("Bot {id} stopped");
+}
+
+#[derive(Debug, PartialEq)]
+enum Reaction {
+    /// The command has been answered, the next one can be sent.
+    Answered,
+    /// The command hasn't been answered yet.
+    Waiting,
+    Dead,
+}
+
+/// Decisions of a bot, taken from the responses it receives like any client.
+/// A bot sends one command at a time and waits for its answer.
+struct Brain {
+    difficulty: Difficulty,
+    team: String,
+    level: u8,
+    life: u64,
+    stones: StoneSet,
+    /// Last `See`, used by a single decision.
+    vision: Option<Vec<String>>,
+    commands_since_inventory: usize,
+    plan: VecDeque<PlayerCmd>,
+    /// Direction of a teammate of the same level calling for an incantation.
+    rally: Option<u8>,
+    /// Whether the bot takes part in an incantation, until its new level is received.
+    incantating: bool,
+    rng: StdRng,
+}
+
+impl Brain {
+    fn new(difficulty: Difficulty, team: String, seed: u64) -> Self {
+        Self {
+            difficulty,
+            team,
+            level: 1,
+            life: LIFE_TICKS * LIVES_START,
+            stones: [0; Stone::SIZE],
+            vision: None,
+            commands_since_inventory: INVENTORY_REFRESH,
+            plan: VecDeque::new(),
+            rally: None,
+            incantating: false,
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    fn next_command(&mut self) -> PlayerCmd {
+        if let Some(command) = self.plan.pop_front() {
+            return command;
+        }
+        if self.commands_since_inventory >= INVENTORY_REFRESH {
+            return PlayerCmd::Inventory;
+        }
+        let Some(cells) = self.vision.take() else {
+            return PlayerCmd::See;
+        };
+        let plan = self.plan_for(&cells).unwrap_or_else(|| self.wander());
+        self.plan = plan.into();
+        self.plan.pop_front().unwrap_or(PlayerCmd::See)
+    }
+
+    fn plan_for(&mut self, cells: &[String]) -> Option<Vec<PlayerCmd>> {
+        if self.difficulty == Difficulty::Easy || self.life < HUNGRY_LIFE {
+            return path_to(cells, Resource::Nourriture);
+        }
+        if self.difficulty == Difficulty::Hard {
+            match self.rally.take() {
+                Some(0) => return Some(vec![PlayerCmd::Inventory]),
+                Some(direction) => return Some(follow(direction)),
+                None => {}
+            }
+        }
+        let (stones, players) = Player::level_requirements(self.level)?;
+        let missing: Vec<Resource> = (0..Stone::SIZE)
+            .filter(|&i| self.stones[i] < stones[i])
+            .map(|i| Resource::try_from(i).unwrap())
+            .collect();
+        if missing.is_empty() {
+            let players_here = cells.first().map_or(0, |cell| {
+                cell.split_whitespace()
+                    .filter(|item| *item == "player")
+                    .count()
+            }) + 1;
+            if players_here >= *players {
+                let mut plan: Vec<PlayerCmd> = stones
+                    .iter()
+                    .enumerate()
+                    .flat_map(|(i, &count)| {
+                        let name = Resource::try_from(i).unwrap().as_str().to_string();
+                        iter::repeat_n(PlayerCmd::Put(name), count)
+                    })
+                    .collect();
+                plan.push(PlayerCmd::Incantation);
+                return Some(plan);
+            }
+            if self.difficulty == Difficulty::Hard {
+                return Some(vec![PlayerCmd::Broadcast(self.rally_call())]);
+            }
+            return None;
+        }
+        missing
+            .into_iter()
+            .chain(iter::once(Resource::Nourriture))
+            .find_map(|resource| path_to(cells, resource))
+    }
+
+    fn wander(&mut self) -> Vec<PlayerCmd> {
+        match self.rng.gen_range(0..4) {
+            0 => vec![PlayerCmd::Left, PlayerCmd::Move],
+            1 => vec![PlayerCmd::Right, PlayerCmd::Move],
+            _ => vec![PlayerCmd::Move],
+        }
+    }
+
+    fn rally_call(&self) -> String {
+        format!("{RALLY_CALL} {} {}", self.team, self.level)
+    }
+
+    fn on_response(&mut self, command: &PlayerCmd, response: ServerResponse) -> Reaction {
+        match response {
+            ServerResponse::Mort => return Reaction::Dead,
+            ServerResponse::Message(direction, _, text) => {
+                if text == self.rally_call() {
+                    self.rally = Some(direction);
+                }
+                return Reaction::Waiting;
+            }
+            ServerResponse::Movement(_) => {
+                self.plan.clear();
+                return Reaction::Waiting;
+            }
+            ServerResponse::IncantationInProgress => {
+                self.incantating = true;
+                return Reaction::Waiting;
+            }
+            ServerResponse::CurrentLevel(level) => {
+                self.level = level;
+                self.plan.clear();
+                self.rally = None;
+                return if std::mem::take(&mut self.incantating) {
+                    Reaction::Answered
+                } else {
+                    Reaction::Waiting
+                };
+            }
+            ServerResponse::See(cells) => self.vision = Some(cells),
+            ServerResponse::Inventory(items) => {
+                self.commands_since_inventory = 0;
+                for item in items {
+                    let Some((name, count)) = item.split_once(' ') else {
+                        continue;
+                    };
+                    let (Ok(resource), Ok(count)) = (Resource::try_from(name), count.parse())
+                    else {
+                        continue;
+                    };
+                    match resource {
+                        Resource::Nourriture => self.life = count as u64,
+                        Resource::Stone(stone) => self.stones[stone.index()] = count,
+                    }
+                }
+                return Reaction::Answered;
+            }
+            ServerResponse::Ok => match command {
+                PlayerCmd::Take(name) => {
+                    if let Ok(Resource::Stone(stone)) = Resource::try_from(name.as_str()) {
+                        self.stones[stone.index()] += 1;
+                    }
+                }
+                PlayerCmd::Put(name) => {
+                    if let Ok(Resource::Stone(stone)) = Resource::try_from(name.as_str()) {
+                        self.stones[stone.index()] -= 1;
+                    }
+                }
+                _ => {}
+            },
+            ServerResponse::Ko => self.plan.clear(),
+            _ => {}
+        }
+        self.commands_since_inventory += 1;
+        Reaction::Answered
+    }
+}
+
+/// Commands walking to the nearest seen cell holding `resource` and taking it.
+fn path_to(cells: &[String], resource: Resource) -> Option<Vec<PlayerCmd>> {
+    let index = cells.iter().position(|cell| {
+        cell.split_whitespace()
+            .any(|item| item == resource.as_str())
+    })?;
+    let (forward, lateral) = vision::relative_cell(index);
+    let mut plan = vec![PlayerCmd::Move; forward];
+    match lateral {
+        ..0 => plan.push(PlayerCmd::Left),
+        0 => {}
+        1.. => plan.push(PlayerCmd::Right),
+    }
+    plan.extend(iter::repeat_n(PlayerCmd::Move, lateral.unsigned_abs()));
+    plan.push(PlayerCmd::Take(resource.as_str().to_string()));
+    Some(plan)
+}
+
+/// First steps toward a broadcast heard from `direction`.
+fn follow(direction: u8) -> Vec<PlayerCmd> {
+    match direction {
+        3 | 4 => vec![PlayerCmd::Left, PlayerCmd::Move],
+        5 => vec![PlayerCmd::Right, PlayerCmd::Right, PlayerCmd::Move],
+        6 | 7 => vec![PlayerCmd::Right, PlayerCmd::Move],
+        _ => vec![PlayerCmd::Move],
+    }
+}
+
+#[cfg(test)]
+mod bot_tests {
+    use super::*;
+    use crate::args::ServerArgs;
+    use crate::engine_handle::EngineHandle;
+    use crate::session::IdAllocator;
+    use clap::Parser;
+
+    fn brain(difficulty: Difficulty) -> Brain {
+        let mut brain = Brain::new(difficulty, "anton".to_string(), 7);
+        brain.commands_since_inventory = 0;
+        brain
+    }
+
+    fn cells(cells: &[&str]) -> Option<Vec<String>> {
+        Some(cells.iter().map(|cell| cell.to_string()).collect())
+    }
+
+    fn plan(brain: &mut Brain) -> Vec<PlayerCmd> {
+        let first = brain.next_command();
+        iter::once(first).chain(brain.plan.drain(..)).collect()
+    }
+
+    #[test]
+    fn walks_to_missing_stone() {
+        // Given
+        let mut brain = brain(Difficulty::Medium);
+        brain.vision = cells(&["", "", "nourriture", "linemate"]);
+
+        // When
+        let plan = plan(&mut brain);
+
+        // Then
+        assert_eq!(
+            plan,
+            vec![
+                PlayerCmd::Move,
+                PlayerCmd::Right,
+                PlayerCmd::Move,
+                PlayerCmd::Take("linemate".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn looks_for_food_when_hungry() {
+        // Given
+        let mut brain = brain(Difficulty::Medium);
+        brain.life = HUNGRY_LIFE - 1;
+        brain.vision = cells(&["linemate", "nourriture", "", ""]);
+
+        // When
+        let plan = plan(&mut brain);
+
+        // Then
+        assert_eq!(
+            plan,
+            vec![
+                PlayerCmd::Move,
+                PlayerCmd::Left,
+                PlayerCmd::Move,
+                PlayerCmd::Take("nourriture".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn levels_up_with_required_stones() {
+        // Given
+        let mut brain = brain(Difficulty::Medium);
+        brain.stones[Stone::Linemate.index()] = 1;
+        brain.vision = cells(&["", "", "", ""]);
+
+        // When
+        let plan = plan(&mut brain);
+        let waiting = brain.on_response(
+            &PlayerCmd::Incantation,
+            ServerResponse::IncantationInProgress,
+        );
+        let answered = brain.on_response(&PlayerCmd::Incantation, ServerResponse::CurrentLevel(2));
+
+        // Then
+        assert_eq!(
+            plan,
+            vec![
+                PlayerCmd::Put("linemate".to_string()),
+                PlayerCmd::Incantation
+            ]
+        );
+        assert_eq!((waiting, answered), (Reaction::Waiting, Reaction::Answered));
+        assert_eq!(brain.level, 2);
+    }
+
+    #[test]
+    fn rallies_teammates_and_follows_their_calls() {
+        // Given
+        let mut caller = brain(Difficulty::Hard);
+        caller.level = 2;
+        caller.stones = [1, 1, 0, 0, 1, 0];
+        caller.vision = cells(&[""; 9]);
+        let mut follower = brain(Difficulty::Hard);
+        follower.level = 2;
+        follower.vision = cells(&[""; 9]);
+
+        // When
+        let call = caller.next_command();
+        let PlayerCmd::Broadcast(text) = call.clone() else {
+            panic!("expected a broadcast, got {call:?}");
+        };
+        let reaction =
+            follower.on_response(&PlayerCmd::See, ServerResponse::Message(3, None, text));
+        let plan = plan(&mut follower);
+
+        // Then
+        assert_eq!(call, PlayerCmd::Broadcast("rally anton 2".to_string()));
+        assert_eq!(reaction, Reaction::Waiting);
+        assert_eq!(plan, vec![PlayerCmd::Left, PlayerCmd::Move]);
+    }
+
+    #[test]
+    fn reads_inventory() {
+        // Given
+        let mut brain = Brain::new(Difficulty::Easy, "anton".to_string(), 7);
+
+        // When
+        let first = brain.next_command();
+        let reaction = brain.on_response(
+            &PlayerCmd::Inventory,
+            ServerResponse::Inventory(vec![
+                "nourriture 1260".to_string(),
+                "linemate 2".to_string(),
+                "sibur 1".to_string(),
+            ]),
+        );
+
+        // Then
+        assert_eq!(first, PlayerCmd::Inventory);
+        assert_eq!(reaction, Reaction::Answered);
+        assert_eq!(brain.life, 1260);
+        assert_eq!(brain.stones, [0, 2, 0, 0, 1, 0]);
+        assert_eq!(brain.next_command(), PlayerCmd::See);
+    }
+
+    #[tokio::test]
+    async fn fills_free_slots_of_bot_teams() {
+        // Given
+        let args = ServerArgs::try_parse_from([
+            "server",
+            "-x",
+            "5",
+            "-y",
+            "4",
+            "-n",
+            "anton",
+            "axel",
+            "-c",
+            "3",
+            "--bots",
+            "easy",
+            "--bot-teams",
+            "axel",
+        ])
+        .unwrap();
+        let ids = IdAllocator::default();
+        let mut engine = GameEngine::new(&args);
+        engine
+            .add_player(ids.allocate().unwrap(), "axel".to_string())
+            .unwrap();
+        let (handle, mut mailbox) = EngineHandle::channel("game");
+        let game = Game::new(handle, &args, ids);
+        let bots = Bots {
+            difficulty: args.bots.unwrap(),
+            teams: args.bot_teams.clone(),
+            human_slots: args.human_slots,
+        };
+
+        // When
+        bots.fill(&game, &mut engine);
+        let first_command = mailbox.recv().await.unwrap();
+        first_command(&mut engine);
+
+        // Then
+        assert_eq!(engine.teams()["anton"].remaining_members(), 3);
+        assert_eq!(engine.teams()["axel"].remaining_members(), 1);
+        assert_eq!(engine.players()[&2].team(), "axel");
+        assert!(game.player_senders.lock().await.contains_key(&2));
+        assert_eq!(engine.players()[&2].commands().len(), 1);
+    }
+}
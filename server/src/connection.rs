@@ -1,32 +1,70 @@
+use crate::args::ServerArgs;
 use shared::NetworkError::{
     ConnectionClosedByClient, FailedToReadFromSocket, FailedToWriteToSocket,
-    MessageCantBeMappedToFromUtf8, MessageIsTooBig,
+    MessageCantBeMappedToFromUtf8, MessageIsTooBig, TimedOut,
 };
 use shared::ZappyError::Network;
 use shared::{ZappyError, HANDSHAKE_MSG};
 use std::pin::Pin;
-use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
 const BUF_SIZE: usize = 1024;
+/// Longest line a peer can send if not set with `--max-line-length`, without its newline.
+pub const DEFAULT_MAX_LINE_LENGTH: usize = 1024;
 
-pub trait AsyncReadWrite: AsyncRead + AsyncWrite {}
-impl<T: AsyncRead + AsyncWrite + ?Sized> AsyncReadWrite for T {}
+/// Limits of the player and admin connections, set by the server arguments.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ConnectionLimits {
+    pub max_line_length: usize,
+    /// For every line of the handshake.
+    pub handshake_timeout: Duration,
+    /// For the TLS negotiation of the admins.
+    pub tls_timeout: Duration,
+    /// `None` if idle connections are kept.
+    pub idle_timeout: Option<Duration>,
+}
+
+impl ConnectionLimits {
+    pub fn new(args: &ServerArgs) -> Self {
+        Self {
+            max_line_length: args.max_line_length,
+            handshake_timeout: Duration::from_secs(args.handshake_timeout),
+            tls_timeout: Duration::from_secs(args.tls_timeout),
+            idle_timeout: (args.idle_timeout > 0).then(|| Duration::from_secs(args.idle_timeout)),
+        }
+    }
+}
+
+pub use shared::tls::AsyncReadWrite;
 
 pub struct Connection {
     stream: Pin<Box<dyn AsyncReadWrite + Send>>,
-    buf: Vec<u8>,
+    /// Bytes received after the last line returned by `read`.
+    pending: Vec<u8>,
+    max_line_length: usize,
+    /// The rest of an overlong line is dropped until its newline.
+    discarding: bool,
     id: u16,
 }
 
 impl Connection {
-    //TODO: check when msg > buf size
     pub async fn send_handshake(&mut self) -> Result<(), ZappyError> {
         self.write(HANDSHAKE_MSG).await
     }
 
-    pub fn new(stream: Pin<Box<dyn AsyncReadWrite + Send>>, id: u16) -> Self {
-        let buf = vec![0u8; BUF_SIZE];
-        Self { buf, stream, id }
+    pub fn new(
+        stream: Pin<Box<dyn AsyncReadWrite + Send>>,
+        id: u16,
+        max_line_length: usize,
+    ) -> Self {
+        Self {
+            stream,
+            pending: Vec::new(),
+            max_line_length,
+            discarding: false,
+            id,
+        }
     }
 
     pub async fn writeln(&mut self, message: &str) -> Result<(), ZappyError> {
@@ -43,25 +81,182 @@ impl Connection {
             .map_err(|e| Network(FailedToWriteToSocket(self.id, e.to_string())))
     }
 
-    // TODO: handle multiline commands and buffer with Ctrl+D like ft_irc/webserv
+    /// Reads the next line, without its `\n` or `\r\n`. Several commands received at once are
+    /// returned by successive calls, and a command split across reads once it is complete.
+    ///
+    /// A line longer than the maximum is answered with a protocol error and skipped; a line
+    /// left unfinished when the peer disconnects is dropped. Cancel safe: the bytes read are
+    /// kept in the connection, so it can be used in `tokio::select!`.
     pub async fn read(&mut self) -> Result<String, ZappyError> {
-        let n = self
-            .stream
-            .read(&mut *self.buf)
+        loop {
+            if let Some(line) = self.next_line() {
+                return line;
+            }
+            // The newline may be preceded by `\r`.
+            if !self.discarding && self.pending.len() > self.max_line_length + 1 {
+                self.pending.clear();
+                self.discarding = true;
+                return Err(Network(MessageIsTooBig(self.id, self.max_line_length)));
+            }
+            let mut buf = [0u8; BUF_SIZE];
+            let n = self
+                .stream
+                .read(&mut buf)
+                .await
+                .map_err(|e| Network(FailedToReadFromSocket(self.id, e.to_string())))?;
+            if n == 0 {
+                return Err(Network(ConnectionClosedByClient(self.id)));
+            }
+            if self.discarding {
+                match buf[..n].iter().position(|&byte| byte == b'\n') {
+                    Some(end) => {
+                        self.discarding = false;
+                        self.pending.extend_from_slice(&buf[end + 1..n]);
+                    }
+                    None => continue,
+                }
+            } else {
+                self.pending.extend_from_slice(&buf[..n]);
+            }
+        }
+    }
+
+    /// Like `read`, but fails with `TimedOut` if no line is received within `timeout`.
+    pub async fn read_within(
+        &mut self,
+        timeout: Duration,
+        waiting_for: &str,
+    ) -> Result<String, ZappyError> {
+        tokio::time::timeout(timeout, self.read())
             .await
-            .map_err(|e| Network(FailedToReadFromSocket(self.id, e.to_string())))?;
-        if n == 0 {
-            Err(Network(ConnectionClosedByClient(self.id)))
-        } else if n > BUF_SIZE {
-            //TODO: handle properly
-            Err(Network(MessageIsTooBig(self.id)))
-        } else {
-            Ok(String::from_utf8(self.buf[..n].to_vec())
-                .map_err(|e| Network(MessageCantBeMappedToFromUtf8(self.id, e.to_string())))?)
+            .map_err(|_| Network(TimedOut(self.id, waiting_for.to_string())))?
+    }
+
+    /// Takes the first complete line of the pending bytes.
+    fn next_line(&mut self) -> Option<Result<String, ZappyError>> {
+        let end = self.pending.iter().position(|&byte| byte == b'\n')?;
+        let mut line: Vec<u8> = self.pending.drain(..=end).collect();
+        line.pop();
+        if line.last() == Some(&b'\r') {
+            line.pop();
         }
+        if line.len() > self.max_line_length {
+            return Some(Err(Network(MessageIsTooBig(self.id, self.max_line_length))));
+        }
+        Some(
+            String::from_utf8(line)
+                .map_err(|e| Network(MessageCantBeMappedToFromUtf8(self.id, e.to_string()))),
+        )
     }
 
     pub fn id(&self) -> u16 {
         self.id
     }
+
+    /// Takes the id of the player whose session the connection resumed.
+    pub fn set_id(&mut self, id: u16) {
+        self.id = id;
+    }
+}
+
+#[cfg(test)]
+mod connection_tests {
+    use super::*;
+    use tokio::io::DuplexStream;
+
+    fn connection(max_line_length: usize) -> (Connection, DuplexStream) {
+        let (server, peer) = tokio::io::duplex(64);
+        (Connection::new(Box::pin(server), 1, max_line_length), peer)
+    }
+
+    #[tokio::test]
+    async fn reads_one_line_per_command() {
+        // Given
+        let (mut connection, mut peer) = connection(32);
+
+        // When
+        peer.write_all(b"avance\r\ndroite\nvoir\n").await.unwrap();
+        peer.write_all(b"inven").await.unwrap();
+        let pipelined = [
+            connection.read().await.unwrap(),
+            connection.read().await.unwrap(),
+            connection.read().await.unwrap(),
+        ];
+        peer.write_all(b"taire\n").await.unwrap();
+        let split = connection.read().await.unwrap();
+        drop(peer);
+
+        // Then
+        assert_eq!(pipelined, ["avance", "droite", "voir"].map(str::to_string));
+        assert_eq!(split, "inventaire");
+        assert_eq!(
+            connection.read().await,
+            Err(Network(ConnectionClosedByClient(1)))
+        );
+    }
+
+    #[tokio::test]
+    async fn decodes_characters_split_across_reads() {
+        // Given
+        let (mut connection, mut peer) = connection(32);
+        let message = "broadcast çà".as_bytes();
+
+        // When
+        peer.write_all(&message[..message.len() - 1]).await.unwrap();
+        let read = tokio::spawn(async move { connection.read().await });
+        tokio::task::yield_now().await;
+        peer.write_all(&message[message.len() - 1..]).await.unwrap();
+        peer.write_all(b"\n").await.unwrap();
+
+        // Then
+        assert_eq!(read.await.unwrap().unwrap(), "broadcast çà");
+    }
+
+    #[tokio::test]
+    async fn skips_lines_over_the_maximum_length() {
+        // Given
+        let (mut connection, mut peer) = connection(16);
+
+        // When
+        let writer = tokio::spawn(async move {
+            peer.write_all(format!("{}\nvoir\n", "a".repeat(100)).as_bytes())
+                .await
+                .unwrap();
+            peer.write_all(b"broadcast sixteen\nexactly sixteen.\r\n")
+                .await
+                .unwrap();
+            peer
+        });
+        let long = connection.read().await;
+        let next = connection.read().await;
+        let complete = connection.read().await;
+        let exact = connection.read().await;
+        drop(writer.await.unwrap());
+
+        // Then
+        assert_eq!(long, Err(Network(MessageIsTooBig(1, 16))));
+        assert_eq!(next.unwrap(), "voir");
+        assert_eq!(complete, Err(Network(MessageIsTooBig(1, 16))));
+        assert_eq!(exact.unwrap(), "exactly sixteen.");
+    }
+
+    #[tokio::test]
+    async fn times_out_waiting_for_a_line() {
+        // Given
+        let (mut connection, mut peer) = connection(32);
+        let timeout = Duration::from_millis(20);
+
+        // When
+        peer.write_all(b"equi").await.unwrap();
+        let silent = connection.read_within(timeout, "the team name").await;
+        peer.write_all(b"pe\n").await.unwrap();
+        let late = connection.read_within(timeout, "the team name").await;
+
+        // Then
+        assert_eq!(
+            silent,
+            Err(Network(TimedOut(1, "the team name".to_string())))
+        );
+        assert_eq!(late.unwrap(), "equipe");
+    }
 }
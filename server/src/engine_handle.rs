@@ -0,0 +1,225 @@
+use crate::game_engine::GameEngine;
+use shared::{GFXData, PlayerError, ZappyError};
+use std::sync::{Arc, Weak};
+use tokio::sync::{mpsc, oneshot, watch, Mutex};
+
+/// Calls waiting for the engine before the senders wait too.
+const CALL_QUEUE: usize = 1024;
+
+type EngineCall = Box<dyn FnOnce(&mut GameEngine) + Send>;
+
+/// A state of the game encoded for the gfx clients, a JSON line shared by all of them. Empty
+/// until the first state is encoded.
+pub type Frame = Arc<str>;
+
+/// The way to the engine of a game, owned by the game routine: calls run one at a time between
+/// two ticks, and the state of the last tick is published to the observers.
+#[derive(Clone)]
+pub struct EngineHandle {
+    game: String,
+    calls: mpsc::Sender<EngineCall>,
+    states: Arc<watch::Sender<Arc<GFXData>>>,
+    /// The encoder of the frames, while it has viewers.
+    frames: Arc<Mutex<Weak<watch::Sender<Frame>>>>,
+}
+
+/// The receiving end of an `EngineHandle`, for the game routine.
+pub struct EngineMailbox {
+    calls: mpsc::Receiver<EngineCall>,
+    states: Arc<watch::Sender<Arc<GFXData>>>,
+}
+
+impl EngineHandle {
+    pub fn channel(game: &str) -> (Self, EngineMailbox) {
+        let (calls, receiver) = mpsc::channel(CALL_QUEUE);
+        let states = Arc::new(watch::Sender::new(Arc::new(GFXData::default())));
+        let handle = Self {
+            game: game.to_string(),
+            calls,
+            states: Arc::clone(&states),
+            frames: Arc::default(),
+        };
+        let mailbox = EngineMailbox {
+            calls: receiver,
+            states,
+        };
+        (handle, mailbox)
+    }
+
+    /// Runs `f` on the engine between two ticks and returns its result, `GameDoesntExist` if
+    /// the game was removed.
+    pub async fn call<R, F>(&self, f: F) -> Result<R, ZappyError>
+    where
+        R: Send + 'static,
+        F: FnOnce(&mut GameEngine) -> R + Send + 'static,
+    {
+        let (result_tx, result_rx) = oneshot::channel();
+        let call: EngineCall = Box::new(move |engine| {
+            let _ = result_tx.send(f(engine));
+        });
+        self.calls.send(call).await.map_err(|_| self.gone())?;
+        result_rx.await.map_err(|_| self.gone())
+    }
+
+    /// Subscribes to the state published after every tick, starting with the current one.
+    pub async fn observe(&self) -> Result<watch::Receiver<Arc<GFXData>>, ZappyError> {
+        let states = self.states.subscribe();
+        let publisher = Arc::clone(&self.states);
+        self.call(move |engine| publisher.send_replace(Arc::new(engine.gfx_data())))
+            .await?;
+        Ok(states)
+    }
+
+    /// Subscribes to the frames of the game, starting its encoder for the first viewer. Every
+    /// state is encoded once for all the viewers, which only get the latest frame when they
+    /// are ready for it.
+    pub async fn frames(&self) -> Result<watch::Receiver<Frame>, ZappyError> {
+        let mut encoder = self.frames.lock().await;
+        if let Some(frames) = encoder.upgrade() {
+            let mut viewer = frames.subscribe();
+            viewer.mark_changed();
+            return Ok(viewer);
+        }
+        let frames = Arc::new(watch::Sender::new(Frame::from("")));
+        let viewer = frames.subscribe();
+        *encoder = Arc::downgrade(&frames);
+        tokio::spawn(encode_frames(self.observe().await?, frames));
+        Ok(viewer)
+    }
+
+    fn gone(&self) -> ZappyError {
+        ZappyError::Player(PlayerError::GameDoesntExist(self.game.clone()))
+    }
+}
+
+/// Encodes every new state of the game until it has no viewer left or is removed.
+async fn encode_frames(
+    mut states: watch::Receiver<Arc<GFXData>>,
+    frames: Arc<watch::Sender<Frame>>,
+) {
+    let mut last_data = Arc::new(GFXData::default());
+    loop {
+        tokio::select! {
+            changed = states.changed() => {
+                if changed.is_err() {
+                    return;
+                }
+            }
+            _ = frames.closed() => return,
+        }
+        let data = states.borrow_and_update().clone();
+        if data == last_data {
+            continue;
+        }
+        match serde_json::to_string(&*data) {
+            Ok(json) => {
+                frames.send_replace(Frame::from(format!("{json}\n")));
+            }
+            Err(e) => log::error!("Failed to serialize the gfx state: {e}"),
+        }
+        last_data = data;
+    }
+}
+
+#[cfg(test)]
+impl EngineHandle {
+    /// Serves the calls to an engine that doesn't tick.
+    pub fn idle(game: &str, mut engine: GameEngine) -> Self {
+        let (handle, mut mailbox) = Self::channel(game);
+        tokio::spawn(async move {
+            while let Some(call) = mailbox.recv().await {
+                call(&mut engine);
+            }
+        });
+        handle
+    }
+}
+
+impl EngineMailbox {
+    /// The next call, `None` once every handle is gone.
+    pub async fn recv(&mut self) -> Option<EngineCall> {
+        self.calls.recv().await
+    }
+
+    /// Runs the calls already waiting, so that a game behind schedule, whose next tick is
+    /// always due, still answers them.
+    pub fn run_pending(&mut self, engine: &mut GameEngine) {
+        for _ in 0..CALL_QUEUE {
+            match self.calls.try_recv() {
+                Ok(call) => call(engine),
+                Err(_) => return,
+            }
+        }
+    }
+
+    /// Publishes the state of the engine if anyone observes it.
+    pub fn publish(&self, engine: &GameEngine) {
+        if self.states.receiver_count() > 0 {
+            self.states.send_replace(Arc::new(engine.gfx_data()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod engine_handle_tests {
+    use super::*;
+    use crate::args::ServerArgs;
+    use clap::Parser;
+
+    #[tokio::test]
+    async fn calls_and_observes_engine() {
+        // Given
+        let args =
+            ServerArgs::try_parse_from(["server", "-x", "5", "-y", "4", "-n", "anton"]).unwrap();
+        let engine = EngineHandle::idle("game", GameEngine::new(&args));
+
+        // When
+        let joined = engine
+            .call(|engine| engine.add_player(1, "anton".to_string()))
+            .await;
+        let mut states = engine.observe().await.unwrap();
+
+        // Then
+        assert!(joined.unwrap().is_ok());
+        assert!(states.has_changed().unwrap());
+        assert!(states.borrow_and_update().players.contains_key(&1));
+    }
+
+    #[tokio::test]
+    async fn shares_encoded_frames_between_viewers() {
+        // Given
+        let args =
+            ServerArgs::try_parse_from(["server", "-x", "5", "-y", "4", "-n", "anton"]).unwrap();
+        let engine = EngineHandle::idle("game", GameEngine::new(&args));
+
+        // When
+        let mut first = engine.frames().await.unwrap();
+        first.changed().await.unwrap();
+        let mut second = engine.frames().await.unwrap();
+        let frame = first.borrow_and_update().clone();
+
+        // Then
+        assert!(second.has_changed().unwrap());
+        assert!(Arc::ptr_eq(&frame, &second.borrow_and_update()));
+        assert!(frame.ends_with('\n'));
+        let data: GFXData = serde_json::from_str(&frame).unwrap();
+        assert_eq!(data.map.field.len(), 4);
+    }
+
+    #[tokio::test]
+    async fn fails_to_call_removed_game() {
+        // Given
+        let (engine, mailbox) = EngineHandle::channel("game");
+
+        // When
+        drop(mailbox);
+
+        // Then
+        assert_eq!(
+            engine.call(|engine| *engine.frame()).await,
+            Err(ZappyError::Player(PlayerError::GameDoesntExist(
+                "game".to_string()
+            )))
+        );
+    }
+}
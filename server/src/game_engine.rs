@@ -1,5 +1,10 @@
 use crate::args::ServerArgs;
+use crate::rules::{GameRules, ResolutionPolicy, VisionPolicy};
 use derive_getters::Getters;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use serde::Serialize;
 use shared::{
     color::ZAPPY_COLORS,
     commands::PlayerCmd,
@@ -7,14 +12,45 @@ use shared::{
     player::Player,
     position::{Direction, Position, Side},
     resource::Resource,
+    stats::{Death, DeathCause, PlayerStats, TeamStats},
     team::Team,
-    Egg,
+    vision, Egg, GFXData,
     NetworkError::IsNotConnectedToServer,
     PlayerError, ServerResponse,
     ZappyError::{self, Network},
-    MAX_COMMANDS,
+    MAX_COMMANDS, MAX_PLAYER_LVL, WINNING_PLAYERS_AT_MAX_LVL,
 };
-use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub enum GameOutcome {
+    /// The team has `WINNING_PLAYERS_AT_MAX_LVL` players at the max level.
+    Victory(String),
+    /// `GameRules::max_frames` has been reached.
+    TimeLimit,
+}
+
+/// Progress of a team, teams are ranked by highest level and then by players at this level.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub struct TeamScore {
+    pub max_level: u8,
+    pub players_at_max_level: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LevelUp {
+    pub frame: u64,
+    pub team: String,
+    pub player: u16,
+    pub level: u8,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ResourceSample {
+    pub frame: u64,
+    /// Indexed by `Resource::index`.
+    pub totals: [usize; Resource::SIZE],
+}
 
 #[derive(Debug, Getters, Clone, PartialEq)]
 pub struct GameEngine {
@@ -24,20 +60,42 @@ pub struct GameEngine {
     incantation: BTreeMap<u64, Vec<u16>>,
     map: Map,
     frame: u64,
+    rules: GameRules,
+    /// Seed of the generated map and of `rng`, drawn at random when not given in the arguments so
+    /// that every game can be replayed.
+    seed: u64,
+    #[getter(skip)]
+    rng: StdRng,
+    round_robin_cursor: Option<u16>,
+    outcome: Option<GameOutcome>,
+    /// Statistics of the players that died or left the game.
+    departed: Vec<PlayerStats>,
+    level_ups: Vec<LevelUp>,
+    /// Frames between two samples of the resources lying on the map, `--metrics-interval`.
+    sample_interval: u64,
+    /// Taken every `sample_interval` frames, starting with the generated map. The reports and
+    /// the metrics files use these samples.
+    resource_samples: Vec<ResourceSample>,
 }
 
 impl GameEngine {
+    /// A game on the map of the arguments if any, on a generated one otherwise.
     pub fn new(args: &ServerArgs) -> Self {
-        let mut map = Map::empty(args.width, args.height);
-        map.generate_resources();
-        let teams = args
+        if let Some(map) = &args.map {
+            return Self::with_map(args, map.clone());
+        }
+        let seed = args.seed.unwrap_or_else(rand::random);
+        let mut rng = StdRng::seed_from_u64(seed);
+        let (width, height) = args.dimensions();
+        let mut map = Map::empty(width, height);
+        map.generate_resources(&mut rng);
+        let spawn_positions = args
             .names
-            .clone()
             .iter()
-            .enumerate()
-            .map(|(i, team_name)| {
-                let spawn_positions: VecDeque<Position> =
-                    (0..args.clients).map(|_| map.random_position()).collect();
+            .map(|team_name| {
+                let spawn_positions: VecDeque<Position> = (0..args.clients)
+                    .map(|_| map.random_position(&mut rng))
+                    .collect();
                 for pos in &spawn_positions {
                     map.field[pos.y][pos.x]
                         .eggs
@@ -45,6 +103,53 @@ impl GameEngine {
                         .or_insert((0, 0))
                         .1 += 1;
                 }
+                spawn_positions
+            })
+            .collect();
+        Self::build(args, map, spawn_positions, seed, rng)
+    }
+
+    /// Starts a game on a prepared map instead of a generated one, the hatched eggs of the map
+    /// being the free slots of the teams. Eggs of teams missing from the arguments are ignored.
+    pub fn with_map(args: &ServerArgs, map: Map) -> Self {
+        let seed = args.seed.unwrap_or_else(rand::random);
+        let mut rng = StdRng::seed_from_u64(seed);
+        let spawn_positions = args
+            .names
+            .iter()
+            .map(|team_name| {
+                let mut spawn_positions = VecDeque::new();
+                for (y, row) in map.field.iter().enumerate() {
+                    for (x, cell) in row.iter().enumerate() {
+                        let hatched = cell.eggs.get(team_name).map_or(0, |eggs| eggs.1);
+                        for _ in 0..hatched {
+                            spawn_positions.push_back(Position {
+                                x,
+                                y,
+                                dir: Direction::random(&mut rng),
+                            });
+                        }
+                    }
+                }
+                spawn_positions
+            })
+            .collect();
+        Self::build(args, map, spawn_positions, seed, rng)
+    }
+
+    fn build(
+        args: &ServerArgs,
+        map: Map,
+        spawn_positions: Vec<VecDeque<Position>>,
+        seed: u64,
+        rng: StdRng,
+    ) -> Self {
+        let teams = args
+            .names
+            .iter()
+            .zip(spawn_positions)
+            .enumerate()
+            .map(|(i, (team_name, spawn_positions))| {
                 (
                     team_name.clone(),
                     Team::new(team_name.clone(), ZAPPY_COLORS[i], spawn_positions),
@@ -56,8 +161,39 @@ impl GameEngine {
             teams,
             players: BTreeMap::new(),
             eggs: BTreeMap::new(),
-            map,
             frame: 0,
+            rules: GameRules::from(args),
+            seed,
+            rng,
+            round_robin_cursor: None,
+            outcome: None,
+            departed: Vec::new(),
+            level_ups: Vec::new(),
+            sample_interval: args.metrics_interval,
+            resource_samples: vec![ResourceSample {
+                frame: 0,
+                totals: map.resource_totals(),
+            }],
+            map,
+        }
+    }
+
+    fn order_simultaneous_commands(&mut self, commands: &mut [(u16, Vec<PlayerCmd>)]) {
+        if commands.len() < 2 {
+            return;
+        }
+        match self.rules.resolution {
+            ResolutionPolicy::IdOrder => {}
+            ResolutionPolicy::Random => commands.shuffle(&mut self.rng),
+            ResolutionPolicy::RoundRobin => {
+                // commands are collected in ascending id order
+                let first = commands
+                    .iter()
+                    .position(|(id, _)| Some(*id) > self.round_robin_cursor)
+                    .unwrap_or(0);
+                commands.rotate_left(first);
+                self.round_robin_cursor = Some(commands[0].0);
+            }
         }
     }
 
@@ -79,6 +215,69 @@ impl GameEngine {
             .insert(*player.id(), new_cell.random_position());
     }
 
+    /// Cells seen by a player, as in the response to `See`.
+    pub fn see(&self, player_id: u16) -> Option<Vec<String>> {
+        let player = self.players.get(&player_id)?;
+        let pos = player.position();
+        let cells = vision::cone(*player.level())
+            .map(|(forward, lateral)| self.map.relative_cell(pos, forward, lateral))
+            .collect::<Vec<_>>();
+        let mut first_occurrences = HashMap::with_capacity(cells.len());
+        let first_indexes = cells
+            .iter()
+            .enumerate()
+            .map(|(index, cell)| *first_occurrences.entry(cell).or_insert(index))
+            .collect::<Vec<_>>();
+        let visible_cells = if self.rules.vision == VisionPolicy::Cap {
+            let first_repetition = (0..cells.len())
+                .find(|&index| first_indexes[index] != index)
+                .unwrap_or(cells.len());
+            vision::relative_cell(first_repetition).0.pow(2)
+        } else {
+            cells.len()
+        };
+        let response = cells[..visible_cells]
+            .iter()
+            .zip(first_indexes)
+            .enumerate()
+            .map(|(index, (&(x, y), first_index))| {
+                if self.rules.vision == VisionPolicy::Mark && first_index != index {
+                    return format!("={first_index}");
+                }
+                let is_same_pos = x == pos.x && y == pos.y;
+                let cell = &self.map.field[y][x];
+                let mut cell_response = vec!["player"; cell.players.len() - is_same_pos as usize];
+                cell_response.extend(
+                    cell.get_resources_copy()
+                        .iter()
+                        .map(|resource| resource.as_str())
+                        .collect::<Vec<&str>>(),
+                );
+                cell_response.join(" ")
+            })
+            .collect();
+        Some(response)
+    }
+
+    /// Nourriture and stones of a player, as in the response to `Inventory`.
+    pub fn inventory(&self, player_id: u16) -> Option<Vec<String>> {
+        let player = self.players.get(&player_id)?;
+        let mut inventory = vec![format!(
+            "{} {}",
+            Resource::Nourriture,
+            player.remaining_life()
+        )];
+        inventory.extend(
+            player
+                .inventory()
+                .iter()
+                .enumerate()
+                .map(|(i, b)| format!("{} {}", Resource::try_from(i).unwrap(), b))
+                .collect::<Vec<String>>(),
+        );
+        Some(inventory)
+    }
+
     fn apply_cmd(&mut self, player_id: u16, command: &PlayerCmd) -> Vec<(u16, ServerResponse)> {
         log::debug!("Executing command: {:?} for {}", command, player_id);
         match command {
@@ -98,6 +297,8 @@ impl GameEngine {
                     player.position().dir
                 };
                 self.handle_move(player_id, &player_direction);
+                let player = self.players.get_mut(&player_id).unwrap();
+                player.stats_mut().distance_moved += 1;
                 vec![(player_id, ServerResponse::Ok)]
             }
             PlayerCmd::Take(resource_name) => {
@@ -107,6 +308,7 @@ impl GameEngine {
                         let cell = &mut self.map.field[player.position().y][player.position().x];
                         if cell.remove_resource(&resource) {
                             player.add_to_inventory(resource);
+                            player.stats_mut().taken[resource.index()] += 1;
                             ServerResponse::Ok
                         } else {
                             ServerResponse::Ko
@@ -122,6 +324,7 @@ impl GameEngine {
                         let cell = &mut self.map.field[player.position().y][player.position().x];
                         if player.remove_from_inventory(resource) {
                             cell.add_resource(resource);
+                            player.stats_mut().put[resource.index()] += 1;
                             ServerResponse::Ok
                         } else {
                             ServerResponse::Ko
@@ -130,55 +333,11 @@ impl GameEngine {
                     .unwrap_or(ServerResponse::Ko);
                 vec![(player_id, response)]
             }
-            PlayerCmd::See => {
-                let player = self.players.get(&player_id).unwrap();
-                let pos = *player.position();
-                let (player_x, player_y) = (pos.x as isize, pos.y as isize);
-                let (width, height) = (self.map_width() as isize, self.map_height() as isize);
-                let mut response = Vec::with_capacity((*player.level() as usize + 1).pow(2));
-                for line in 0..=(*player.level() as isize) {
-                    for idx in -line..=line {
-                        let (x, y) = {
-                            let (x, y) = match pos.dir {
-                                Direction::North => (player_x + idx, player_y - line),
-                                Direction::East => (player_x + line, player_y + idx),
-                                Direction::South => (player_x - idx, player_y + line),
-                                Direction::West => (player_x - line, player_y - idx),
-                            };
-                            (x.rem_euclid(width) as usize, y.rem_euclid(height) as usize)
-                        };
-                        let is_same_pos = x == player.position().x && y == player.position().y;
-                        let cell = &self.map.field[y][x];
-                        let mut cell_response =
-                            vec!["player"; cell.players.len() - is_same_pos as usize];
-                        cell_response.extend(
-                            cell.get_resources_copy()
-                                .iter()
-                                .map(|resource| resource.as_str())
-                                .collect::<Vec<&str>>(),
-                        );
-                        response.push(cell_response.join(" "));
-                    }
-                }
-                vec![(player_id, ServerResponse::See(response))]
-            }
-            PlayerCmd::Inventory => {
-                let player = self.players.get(&player_id).unwrap();
-                let mut inventory = vec![format!(
-                    "{} {}",
-                    Resource::Nourriture,
-                    player.remaining_life()
-                )];
-                inventory.extend(
-                    player
-                        .inventory()
-                        .iter()
-                        .enumerate()
-                        .map(|(i, b)| format!("{} {}", Resource::try_from(i).unwrap(), b))
-                        .collect::<Vec<String>>(),
-                );
-                vec![(player_id, ServerResponse::Inventory(inventory))]
-            }
+            PlayerCmd::See => vec![(player_id, ServerResponse::See(self.see(player_id).unwrap()))],
+            PlayerCmd::Inventory => vec![(
+                player_id,
+                ServerResponse::Inventory(self.inventory(player_id).unwrap()),
+            )],
             PlayerCmd::Expel => {
                 let (target_ids, direction) = {
                     let player = self.players.get(&player_id).unwrap();
@@ -193,27 +352,47 @@ impl GameEngine {
                     .iter()
                     .map(|&id| {
                         self.handle_move(id, &direction);
+                        self.players
+                            .get_mut(&id)
+                            .unwrap()
+                            .stats_mut()
+                            .expels_suffered += 1;
                         (id, ServerResponse::Movement(direction.opposite()))
                     })
                     .collect();
+                self.players
+                    .get_mut(&player_id)
+                    .unwrap()
+                    .stats_mut()
+                    .expels_done += target_ids.len();
                 result.push((player_id, ServerResponse::Ok));
                 result
             }
             PlayerCmd::Broadcast(text) => {
+                self.players
+                    .get_mut(&player_id)
+                    .unwrap()
+                    .stats_mut()
+                    .broadcasts += 1;
                 let sender_pos = self.players.get(&player_id).unwrap().position();
+                let broadcast = &self.rules.broadcast;
                 self.players
-                    .keys()
-                    .map(|&id| {
-                        let resp = if id == player_id {
-                            ServerResponse::Ok
-                        } else {
-                            let receiver_pos = self.players.get(&id).unwrap().position();
+                    .iter()
+                    .filter_map(|(&id, receiver)| {
+                        if id == player_id {
+                            return Some((id, ServerResponse::Ok));
+                        }
+                        let receiver_pos = receiver.position();
+                        let distance = self.map.distance(sender_pos, receiver_pos);
+                        let heard = broadcast.hear(text, distance, &mut self.rng)?;
+                        Some((
+                            id,
                             ServerResponse::Message(
                                 self.map.find_broadcast_source(sender_pos, receiver_pos),
-                                text.clone(),
-                            )
-                        };
-                        (id, resp)
+                                broadcast.report_distance.then_some(distance),
+                                heard,
+                            ),
+                        ))
                     })
                     .collect()
             }
@@ -274,6 +453,8 @@ impl GameEngine {
                     .entry(player.team().clone())
                     .and_modify(|(unhatched, _)| *unhatched += 1)
                     .or_insert((1, 0));
+                let player = self.players.get_mut(&player_id).unwrap();
+                player.stats_mut().eggs_laid += 1;
                 vec![(player_id, ServerResponse::Ok)]
             }
             PlayerCmd::ConnectNbr => {
@@ -313,28 +494,33 @@ impl GameEngine {
             }
 
             player.decrease_life();
+            let level = *player.level() as usize;
+            player.stats_mut().frames_at_level[level - 1] += 1;
 
-            if !player.commands().is_empty() && current_frame >= *player.next_frame() {
-                let command = player.pop_command_from_queue().unwrap();
-                player.set_next_frame(current_frame + command.delay());
-                commands_to_process.push((*id, command));
+            let commands = player.pop_commands_for_frame(current_frame);
+            if !commands.is_empty() {
+                commands_to_process.push((*id, commands));
             }
         }
 
         for player_id in dead_players {
-            self.remove_player(player_id);
+            self.retire_player(player_id, DeathCause::Starvation);
         }
 
-        for (player_id, command) in commands_to_process {
-            if *self
-                .players
-                .get(&player_id)
-                .unwrap()
-                .is_performing_incantation()
-            {
-                execution_results.push((player_id, ServerResponse::IncantationInProgress));
-            } else {
-                execution_results.extend(self.apply_cmd(player_id, &command));
+        self.order_simultaneous_commands(&mut commands_to_process);
+
+        for (player_id, commands) in commands_to_process {
+            for command in commands {
+                if *self
+                    .players
+                    .get(&player_id)
+                    .unwrap()
+                    .is_performing_incantation()
+                {
+                    execution_results.push((player_id, ServerResponse::IncantationInProgress));
+                } else {
+                    execution_results.extend(self.apply_cmd(player_id, &command));
+                }
             }
         }
 
@@ -363,7 +549,15 @@ impl GameEngine {
             for id in players_to_stop_incantation {
                 if let Some(player) = self.players.get_mut(&id) {
                     match player.stop_incantation() {
-                        Ok(lvl) => execution_results.push((id, ServerResponse::CurrentLevel(lvl))),
+                        Ok(lvl) => {
+                            self.level_ups.push(LevelUp {
+                                frame: current_frame,
+                                team: player.team().clone(),
+                                player: id,
+                                level: lvl,
+                            });
+                            execution_results.push((id, ServerResponse::CurrentLevel(lvl)))
+                        }
                         Err(e) => log::error!("{e}"),
                     }
                 }
@@ -371,6 +565,133 @@ impl GameEngine {
         }
         //TODO: implement and uncomment
         //self.map.generate_resources();
+
+        if current_frame.is_multiple_of(self.sample_interval) {
+            self.resource_samples.push(ResourceSample {
+                frame: current_frame,
+                totals: self.map.resource_totals(),
+            });
+        }
+
+        self.update_outcome();
+    }
+
+    fn update_outcome(&mut self) {
+        if self.outcome.is_some() {
+            return;
+        }
+        let winner = self.team_scores().into_iter().find(|(_, score)| {
+            score.max_level == MAX_PLAYER_LVL
+                && score.players_at_max_level >= WINNING_PLAYERS_AT_MAX_LVL
+        });
+        self.outcome = match winner {
+            Some((team, _)) => Some(GameOutcome::Victory(team)),
+            None if self.rules.max_frames.is_some_and(|max| self.frame >= max) => {
+                Some(GameOutcome::TimeLimit)
+            }
+            None => None,
+        };
+        if let Some(outcome) = &self.outcome {
+            log::info!("Game over at frame {}: {:?}", self.frame, outcome);
+        }
+    }
+
+    pub fn team_scores(&self) -> BTreeMap<String, TeamScore> {
+        let mut scores: BTreeMap<String, TeamScore> = self
+            .teams
+            .keys()
+            .map(|name| (name.clone(), TeamScore::default()))
+            .collect();
+        for player in self.players.values() {
+            let score = scores.get_mut(player.team()).unwrap();
+            if *player.level() > score.max_level {
+                *score = TeamScore {
+                    max_level: *player.level(),
+                    players_at_max_level: 0,
+                };
+            }
+            if *player.level() == score.max_level {
+                score.players_at_max_level += 1;
+            }
+        }
+        scores
+    }
+
+    /// The team that won, or that leads alone when the game is stopped without a victory.
+    pub fn winner(&self) -> Option<String> {
+        if let Some(GameOutcome::Victory(team)) = &self.outcome {
+            return Some(team.clone());
+        }
+        let scores = self.team_scores();
+        let best = scores.values().max()?;
+        let mut leaders = scores.iter().filter(|(_, score)| *score == best);
+        match (leaders.next(), leaders.next()) {
+            (Some((team, _)), None) => Some(team.clone()),
+            _ => None,
+        }
+    }
+
+    /// Statistics of the living players, then of the departed ones in order of departure.
+    pub fn player_stats(&self) -> Vec<PlayerStats> {
+        self.players
+            .values()
+            .map(|player| PlayerStats {
+                id: *player.id(),
+                team: player.team().clone(),
+                level: *player.level(),
+                stats: player.stats().clone(),
+                death: None,
+            })
+            .chain(self.departed.iter().cloned())
+            .collect()
+    }
+
+    pub fn team_stats(&self) -> BTreeMap<String, TeamStats> {
+        let mut stats: BTreeMap<String, TeamStats> = self
+            .teams
+            .keys()
+            .map(|name| (name.clone(), TeamStats::default()))
+            .collect();
+        for player in self.player_stats() {
+            *stats.entry(player.team.clone()).or_default() += &player;
+        }
+        stats
+    }
+
+    /// The state shown to the gfx clients.
+    pub fn gfx_data(&self) -> GFXData {
+        GFXData::new(
+            self.map.clone(),
+            self.players.clone(),
+            self.teams
+                .iter()
+                .map(|(name, team)| (name.clone(), (team.color(), team.members_count())))
+                .collect(),
+            self.team_stats(),
+        )
+    }
+
+    /// Statistics of the teams, each one followed by its players, one per line.
+    pub fn stats_report(&self) -> Vec<String> {
+        let players = self.player_stats();
+        let mut lines = Vec::new();
+        for (team, stats) in self.team_stats() {
+            lines.push(format!(
+                "team {team}: {} players, {} dead, {}",
+                stats.players, stats.deaths, stats.stats
+            ));
+            for player in players.iter().filter(|player| player.team == team) {
+                let state = match &player.death {
+                    None => "alive".to_string(),
+                    Some(Death { cause, frame }) => format!("{cause:?} at frame {frame}"),
+                };
+                lines.push(format!(
+                    "  player {} (level {}, {state}): {}",
+                    player.id, player.level, player.stats
+                ));
+            }
+        }
+        lines
     }
 
     pub fn add_player(&mut self, player_id: u16, team_name: String) -> Result<u16, ZappyError> {
@@ -392,8 +713,23 @@ impl GameEngine {
         Ok(team.remaining_members())
     }
 
+    /// Removes a player whose client left the game.
     pub fn remove_player(&mut self, player_id: u16) {
+        self.retire_player(player_id, DeathCause::Disconnection);
+    }
+
+    fn retire_player(&mut self, player_id: u16, cause: DeathCause) {
         if let Some(player) = self.players.remove(&player_id) {
+            self.departed.push(PlayerStats {
+                id: player_id,
+                team: player.team().clone(),
+                level: *player.level(),
+                stats: player.stats().clone(),
+                death: Some(Death {
+                    cause,
+                    frame: self.frame,
+                }),
+            });
             log::debug!("Client {player_id} has been removed from the server");
             self.map.remove_player(player.id(), player.position());
             self.teams
@@ -420,6 +756,28 @@ impl GameEngine {
         }
     }
 
+    /// Adds a hatched egg of a team, a free slot for a new player at `position`.
+    pub fn add_egg(&mut self, team_name: &str, position: Position) -> Result<(), ZappyError> {
+        let team = self.teams.get_mut(team_name).ok_or(ZappyError::Player(
+            PlayerError::TeamDoesntExist(team_name.to_string()),
+        ))?;
+        team.add_next_spawn_position(position);
+        self.map.field[position.y][position.x]
+            .eggs
+            .entry(team_name.to_string())
+            .or_insert((0, 0))
+            .1 += 1;
+        Ok(())
+    }
+
+    pub fn player_mut(&mut self, player_id: u16) -> Option<&mut Player> {
+        self.players.get_mut(&player_id)
+    }
+
+    pub fn map_mut(&mut self) -> &mut Map {
+        &mut self.map
+    }
+
     pub fn map_width(&self) -> usize {
         *self.map.width()
     }
@@ -433,6 +791,7 @@ impl GameEngine {
 mod game_engine_tests {
     use super::*;
     use crate::args::ServerArgsBuilder;
+    use shared::color::ZappyColor;
 
     // Common test constants
     const GAME_WIDTH: usize = 3;
@@ -493,8 +852,38 @@ mod game_engine_tests {
             .sum::<usize>()
     }
 
+    fn game_engine_with(
+        positions: &[Position],
+        resources: Option<&Vec<((usize, usize), Resource)>>,
+    ) -> (Vec<u16>, GameEngine) {
+        let team_name = test_team_name();
+        let mut game = default_game_engine();
+        game.map = Map::empty(GAME_WIDTH, GAME_HEIGHT);
+        let mut res = Vec::new();
+        game.teams = BTreeMap::from([(
+            test_team_name(),
+            Team::new(
+                test_team_name(),
+                ZappyColor::Magenta,
+                VecDeque::from(positions.to_owned()),
+            ),
+        )]);
+        if let Some(resources) = resources {
+            for ((x, y), res) in resources {
+                game.map.field[*y][*x].add_resource(*res)
+            }
+        }
+        for (i, pos) in positions.iter().enumerate() {
+            game.map.field[pos.y][pos.x].eggs = BTreeMap::from([(team_name.clone(), (0, 1))]);
+            game.add_player(i as u16, team_name.clone()).unwrap();
+            res.push(i as u16);
+        }
+        (res, game)
+    }
+
     mod creation {
         use super::*;
+        use clap::Parser;
 
         #[test]
         fn successfully_creates_new_game() {
@@ -572,6 +961,42 @@ mod game_engine_tests {
             // Other
             assert_eq!(game.frame, 0, "Game should start at frame 0");
         }
+
+        #[test]
+        fn creates_game_on_map_file() {
+            // Given
+            let path = std::env::temp_dir().join(format!("zappy-map-{}.txt", std::process::id()));
+            std::fs::write(
+                &path,
+                "4 2\nN . . L\n. . DS .\negg Axel 1 1 2\negg Nobody 0 0\n",
+            )
+            .unwrap();
+            let args = ServerArgs::try_parse_from([
+                "server",
+                "-n",
+                TEST_TEAM_NAME_STR,
+                "--map",
+                path.to_str().unwrap(),
+            ]);
+            std::fs::remove_file(&path).unwrap();
+
+            // When
+            let mut game = GameEngine::new(&args.unwrap());
+            game.add_player(1, test_team_name()).unwrap();
+
+            // Then
+            assert_eq!((game.map_width(), game.map_height()), (4, 2));
+            assert_eq!(game.map.resource_totals().iter().sum::<usize>(), 4);
+            assert_eq!(game.teams[TEST_TEAM_NAME_STR].remaining_members(), 1);
+            assert_eq!(
+                (game.players[&1].position().x, game.players[&1].position().y),
+                (1, 1)
+            );
+            assert_eq!(
+                game.map.to_text(),
+                "4 2\nN  .  .  L\n.  .  DS .\negg Nobody 0 0\negg Axel 1 1\n"
+            );
+        }
     }
 
     mod player_management {
@@ -756,43 +1181,12 @@ mod game_engine_tests {
     mod commands_execution {
         use super::*;
         use rstest::rstest;
-        use shared::color::ZappyColor;
         use shared::resource::Stone::*;
         use shared::resource::StoneSet;
         use shared::LIFE_TICKS;
-        use std::collections::BTreeMap;
         use Direction::*;
         use Resource::*;
 
-        fn game_engine_with(
-            positions: &Vec<Position>,
-            resources: Option<&Vec<((usize, usize), Resource)>>,
-        ) -> (Vec<u16>, GameEngine) {
-            let team_name = test_team_name();
-            let mut game = default_game_engine();
-            game.map = Map::empty(GAME_WIDTH, GAME_HEIGHT);
-            let mut res = Vec::new();
-            game.teams = BTreeMap::from([(
-                test_team_name(),
-                Team::new(
-                    test_team_name(),
-                    ZappyColor::Magenta,
-                    VecDeque::from(positions.clone()),
-                ),
-            )]);
-            if let Some(resources) = resources {
-                for ((x, y), res) in resources {
-                    game.map.field[*y][*x].add_resource(*res)
-                }
-            }
-            for (i, pos) in positions.iter().enumerate() {
-                game.map.field[pos.y][pos.x].eggs = BTreeMap::from([(team_name.clone(), (0, 1))]);
-                game.add_player(i as u16, team_name.clone()).unwrap();
-                res.push(i as u16);
-            }
-            (res, game)
-        }
-
         #[rstest]
         // Movement tests - North/South
         #[case(Position{ x: 0, y: 0, dir: North }, Position{ x: 0, y: 2, dir: North }, PlayerCmd::Move)]
@@ -1067,6 +1461,48 @@ mod game_engine_tests {
             );
         }
 
+        #[rstest]
+        #[case(VisionPolicy::Repeat, vec!["mendiane", "sibur", "", "", "thystame", "", "", "thystame", ""])]
+        #[case(VisionPolicy::Mark, vec!["mendiane", "sibur", "", "", "thystame", "", "", "=4", "=5"])]
+        #[case(VisionPolicy::Cap, vec!["mendiane", "sibur", "", ""])]
+        fn applies_see_command_bigger_than_map(
+            #[case] vision: VisionPolicy,
+            #[case] result: Vec<&str>,
+        ) {
+            // Given
+            let result = result.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+            let (players_ids, mut game) = game_engine_with(
+                &vec![Position {
+                    x: 1,
+                    y: 0,
+                    dir: North,
+                }],
+                Some(&vec![
+                    ((1, 0), Stone(Mendiane)),
+                    ((0, 2), Stone(Sibur)),
+                    ((2, 1), Stone(Thystame)),
+                ]),
+            );
+            let player_under_test_id = players_ids[0];
+            game.rules.vision = vision;
+            player_lvl_up(game.players.get_mut(&player_under_test_id).unwrap(), 2);
+            let mut execution_results_buffer = Vec::new();
+            let command = PlayerCmd::See;
+            game.take_command(&player_under_test_id, command.clone())
+                .unwrap();
+
+            // When
+            for _ in 0..command.delay() {
+                game.tick(&mut execution_results_buffer)
+            }
+
+            // Then
+            assert_eq!(
+                execution_results_buffer,
+                vec![(player_under_test_id, ServerResponse::See(result))]
+            );
+        }
+
         #[rstest]
         // Take test for stones
         // Successfully takes a stone from sell
@@ -1416,4 +1852,702 @@ mod game_engine_tests {
             );
         }
     }
+
+    mod tick {
+        use super::*;
+        use shared::resource::Stone::Linemate;
+        use shared::MAX_COMMANDS_PER_FRAME;
+
+        const POSITION: Position = Position {
+            x: 1,
+            y: 1,
+            dir: Direction::North,
+        };
+
+        fn tick_n(
+            game: &mut GameEngine,
+            n: u64,
+            execution_results_buffer: &mut Vec<(u16, ServerResponse)>,
+        ) {
+            for _ in 0..n {
+                game.tick(execution_results_buffer);
+            }
+        }
+
+        #[test]
+        fn decreases_life_every_frame() {
+            // Given
+            let (player_id, mut game) = one_player_game_engine();
+            player_set_hp(game.players.get_mut(&player_id).unwrap(), 100);
+            let mut execution_results_buffer = Vec::new();
+
+            // When
+            tick_n(&mut game, 42, &mut execution_results_buffer);
+
+            // Then
+            assert_eq!(game.frame, 42);
+            assert_eq!(*game.players.get(&player_id).unwrap().remaining_life(), 58);
+            assert!(execution_results_buffer.is_empty());
+        }
+
+        #[test]
+        fn removes_player_without_life() {
+            // Given
+            let (player_id, mut game) = one_player_game_engine();
+            player_set_hp(game.players.get_mut(&player_id).unwrap(), 1);
+            let mut execution_results_buffer = Vec::new();
+
+            // When
+            tick_n(&mut game, 2, &mut execution_results_buffer);
+
+            // Then
+            assert_eq!(
+                execution_results_buffer,
+                vec![(player_id, ServerResponse::Mort)]
+            );
+            assert!(game.players.is_empty());
+            assert!(game
+                .map
+                .field
+                .iter()
+                .flatten()
+                .all(|c| c.players.is_empty()));
+            assert_eq!(
+                game.teams.get(&test_team_name()).unwrap().members_count(),
+                0
+            );
+        }
+
+        #[test]
+        fn waits_for_the_command_delay_before_the_next_command() {
+            // Given
+            let (player_ids, mut game) = game_engine_with(&[POSITION], None);
+            let player_id = player_ids[0];
+            let mut execution_results_buffer = Vec::new();
+            game.take_command(&player_id, PlayerCmd::Move).unwrap();
+            game.take_command(&player_id, PlayerCmd::Move).unwrap();
+
+            // When
+            tick_n(
+                &mut game,
+                PlayerCmd::Move.delay(),
+                &mut execution_results_buffer,
+            );
+
+            // Then
+            assert_eq!(execution_results_buffer.len(), 1);
+            assert_eq!(
+                *game.players.get(&player_id).unwrap().next_frame(),
+                1 + PlayerCmd::Move.delay()
+            );
+
+            // When
+            game.tick(&mut execution_results_buffer);
+
+            // Then
+            assert_eq!(
+                execution_results_buffer,
+                vec![(player_id, ServerResponse::Ok); 2]
+            );
+        }
+
+        #[test]
+        fn executes_zero_delay_commands_in_the_frame_they_are_reached() {
+            // Given
+            let (player_ids, mut game) = game_engine_with(&[POSITION], None);
+            let player_id = player_ids[0];
+            let mut execution_results_buffer = Vec::new();
+            for command in [
+                PlayerCmd::Move,
+                PlayerCmd::ConnectNbr,
+                PlayerCmd::ConnectNbr,
+                PlayerCmd::Left,
+                PlayerCmd::Right,
+            ] {
+                game.take_command(&player_id, command).unwrap();
+            }
+
+            // When
+            tick_n(
+                &mut game,
+                PlayerCmd::Move.delay(),
+                &mut execution_results_buffer,
+            );
+
+            // Then
+            assert_eq!(
+                execution_results_buffer,
+                vec![(player_id, ServerResponse::Ok)]
+            );
+
+            // When
+            game.tick(&mut execution_results_buffer);
+
+            // Then
+            assert_eq!(
+                execution_results_buffer,
+                vec![
+                    (player_id, ServerResponse::Ok),
+                    (player_id, ServerResponse::Value("0".to_string())),
+                    (player_id, ServerResponse::Value("0".to_string())),
+                    (player_id, ServerResponse::Ok),
+                ]
+            );
+            let player = game.players.get(&player_id).unwrap();
+            assert_eq!(player.commands(), &VecDeque::from([PlayerCmd::Right]));
+            assert_eq!(
+                *player.next_frame(),
+                1 + PlayerCmd::Move.delay() + PlayerCmd::Left.delay()
+            );
+        }
+
+        #[test]
+        fn bounds_the_commands_executed_in_a_frame() {
+            // Given
+            let (player_id, mut game) = one_player_game_engine();
+            let mut execution_results_buffer = Vec::new();
+            let player = game.players.get_mut(&player_id).unwrap();
+            for _ in 0..MAX_COMMANDS_PER_FRAME + 1 {
+                player.push_command_to_queue(PlayerCmd::ConnectNbr);
+            }
+
+            // When
+            game.tick(&mut execution_results_buffer);
+
+            // Then
+            assert_eq!(execution_results_buffer.len(), MAX_COMMANDS_PER_FRAME);
+
+            // When
+            game.tick(&mut execution_results_buffer);
+
+            // Then
+            assert_eq!(execution_results_buffer.len(), MAX_COMMANDS_PER_FRAME + 1);
+        }
+
+        #[test]
+        fn levels_up_at_the_end_of_the_incantation() {
+            // Given
+            let (player_ids, mut game) = game_engine_with(
+                &[POSITION],
+                Some(&vec![((1, 1), Resource::Stone(Linemate))]),
+            );
+            let player_id = player_ids[0];
+            let mut execution_results_buffer = Vec::new();
+            game.take_command(&player_id, PlayerCmd::Incantation)
+                .unwrap();
+            game.take_command(&player_id, PlayerCmd::Move).unwrap();
+
+            // When
+            game.tick(&mut execution_results_buffer);
+
+            // Then
+            assert_eq!(
+                execution_results_buffer,
+                vec![(player_id, ServerResponse::IncantationInProgress); 2]
+            );
+            assert!(game.map.field[1][1].stones.iter().all(VecDeque::is_empty));
+
+            // When
+            tick_n(
+                &mut game,
+                PlayerCmd::INCANTATION_DURATION,
+                &mut execution_results_buffer,
+            );
+
+            // Then
+            assert_eq!(
+                execution_results_buffer.last(),
+                Some(&(player_id, ServerResponse::CurrentLevel(2)))
+            );
+            assert_eq!(*game.players.get(&player_id).unwrap().level(), 2);
+        }
+
+        #[test]
+        fn fails_incantation_without_stones() {
+            // Given
+            let (player_ids, mut game) = game_engine_with(&[POSITION], None);
+            let player_id = player_ids[0];
+            let mut execution_results_buffer = Vec::new();
+            game.take_command(&player_id, PlayerCmd::Incantation)
+                .unwrap();
+
+            // When
+            game.tick(&mut execution_results_buffer);
+
+            // Then
+            assert_eq!(
+                execution_results_buffer,
+                vec![(player_id, ServerResponse::Ko)]
+            );
+            assert!(game.incantation.is_empty());
+        }
+
+        #[test]
+        fn hatches_forked_egg_after_its_delay() {
+            // Given
+            let (player_ids, mut game) = game_engine_with(&[POSITION], None);
+            let player_id = player_ids[0];
+            let mut execution_results_buffer = Vec::new();
+            game.take_command(&player_id, PlayerCmd::Fork).unwrap();
+
+            // When
+            tick_n(
+                &mut game,
+                PlayerCmd::EGG_FETCH_TIME_DELAY,
+                &mut execution_results_buffer,
+            );
+
+            // Then
+            assert_eq!(
+                execution_results_buffer,
+                vec![(player_id, ServerResponse::Ok)]
+            );
+            assert_eq!(
+                game.map.field[1][1].eggs.get(&test_team_name()),
+                Some(&(1, 0))
+            );
+            assert_eq!(
+                game.teams
+                    .get(&test_team_name())
+                    .unwrap()
+                    .remaining_members(),
+                0
+            );
+
+            // When
+            game.tick(&mut execution_results_buffer);
+            game.take_command(&player_id, PlayerCmd::ConnectNbr)
+                .unwrap();
+            game.tick(&mut execution_results_buffer);
+
+            // Then
+            assert_eq!(
+                game.map.field[1][1].eggs.get(&test_team_name()),
+                Some(&(0, 1))
+            );
+            assert_eq!(
+                execution_results_buffer.last(),
+                Some(&(player_id, ServerResponse::Value("1".to_string())))
+            );
+        }
+    }
+
+    mod stats {
+        use super::*;
+        use shared::resource::Stone::Linemate;
+        use shared::stats::Stats;
+
+        const POSITION: Position = Position {
+            x: 1,
+            y: 1,
+            dir: Direction::North,
+        };
+
+        #[test]
+        fn counts_player_actions() {
+            // Given
+            let (player_ids, mut game) = game_engine_with(
+                &[POSITION, POSITION],
+                Some(&vec![((1, 1), Resource::Stone(Linemate))]),
+            );
+            let (actor, target) = (player_ids[0], player_ids[1]);
+            let linemate = Resource::Stone(Linemate).index();
+
+            // When
+            for command in [
+                PlayerCmd::Take("linemate".to_string()),
+                PlayerCmd::Take("linemate".to_string()),
+                PlayerCmd::Put("linemate".to_string()),
+                PlayerCmd::Broadcast("hi".to_string()),
+                PlayerCmd::Expel,
+                PlayerCmd::Fork,
+                PlayerCmd::Move,
+                PlayerCmd::Move,
+            ] {
+                game.apply_cmd(actor, &command);
+            }
+
+            // Then
+            let mut expected = Stats {
+                distance_moved: 2,
+                broadcasts: 1,
+                expels_done: 1,
+                eggs_laid: 1,
+                ..Stats::default()
+            };
+            expected.taken[linemate] = 1;
+            expected.put[linemate] = 1;
+            assert_eq!(game.players[&actor].stats(), &expected);
+            assert_eq!(game.players[&target].stats().expels_suffered, 1);
+            assert_eq!(game.players[&target].stats().distance_moved, 0);
+        }
+
+        #[test]
+        fn records_level_ups_and_resource_samples() {
+            // Given
+            let (player_ids, mut game) = game_engine_with(&[POSITION], None);
+            let player_id = player_ids[0];
+            game.players
+                .get_mut(&player_id)
+                .unwrap()
+                .start_incantation();
+            game.incantation.insert(1, vec![player_id]);
+            game.map.field[0][0].add_resource(Resource::Nourriture);
+
+            // When
+            for _ in 0..game.sample_interval {
+                game.tick(&mut Vec::new());
+            }
+
+            // Then
+            assert_eq!(
+                game.level_ups,
+                vec![LevelUp {
+                    frame: 1,
+                    team: test_team_name(),
+                    player: player_id,
+                    level: 2
+                }]
+            );
+            let nourriture = Resource::Nourriture.index();
+            assert_eq!(
+                game.resource_samples
+                    .iter()
+                    .map(|sample| (sample.frame, sample.totals[nourriture]))
+                    .collect::<Vec<_>>(),
+                vec![
+                    (0, game.resource_samples[0].totals[nourriture]),
+                    (game.sample_interval, 1)
+                ]
+            );
+        }
+
+        #[test]
+        fn keeps_stats_of_departed_players() {
+            // Given
+            let (player_ids, mut game) = game_engine_with(&[POSITION, POSITION], None);
+            player_set_hp(game.players.get_mut(&player_ids[0]).unwrap(), 2);
+            player_set_hp(game.players.get_mut(&player_ids[1]).unwrap(), 100);
+            let mut execution_results_buffer = Vec::new();
+
+            // When
+            for _ in 0..3 {
+                game.tick(&mut execution_results_buffer);
+            }
+            game.remove_player(player_ids[1]);
+
+            // Then
+            let deaths: Vec<_> = game
+                .player_stats()
+                .into_iter()
+                .map(|player| (player.id, player.death))
+                .collect();
+            assert_eq!(
+                deaths,
+                vec![
+                    (
+                        player_ids[0],
+                        Some(Death {
+                            cause: DeathCause::Starvation,
+                            frame: 3
+                        })
+                    ),
+                    (
+                        player_ids[1],
+                        Some(Death {
+                            cause: DeathCause::Disconnection,
+                            frame: 3
+                        })
+                    ),
+                ]
+            );
+            let team = &game.team_stats()[&test_team_name()];
+            assert_eq!((team.players, team.deaths), (2, 2));
+            assert_eq!(team.stats.frames_at_level[0], 2 + 3);
+        }
+
+        #[test]
+        fn sends_only_team_stats_to_gfx() {
+            // Given
+            let (player_ids, mut game) = game_engine_with(&[POSITION], None);
+            game.apply_cmd(player_ids[0], &PlayerCmd::Broadcast("hi".to_string()));
+
+            // When
+            let frame = serde_json::to_value(game.gfx_data()).unwrap();
+
+            // Then
+            let player = &frame["players"][player_ids[0].to_string()];
+            assert!(player.get("stats").is_none());
+            assert_eq!(frame["stats"][test_team_name()]["stats"]["broadcasts"], 1);
+        }
+    }
+
+    mod outcome {
+        use super::*;
+
+        fn positions(n: usize) -> Vec<Position> {
+            (0..n)
+                .map(|i| Position {
+                    x: i % GAME_WIDTH,
+                    y: i / GAME_WIDTH,
+                    dir: Direction::North,
+                })
+                .collect()
+        }
+
+        #[test]
+        fn stops_at_time_limit() {
+            // Given
+            let (_, mut game) = game_engine_with(&positions(1), None);
+            game.rules.max_frames = Some(3);
+            let mut execution_results_buffer = Vec::new();
+
+            // When
+            game.tick(&mut execution_results_buffer);
+            game.tick(&mut execution_results_buffer);
+            let before_limit = game.outcome.clone();
+            game.tick(&mut execution_results_buffer);
+
+            // Then
+            assert_eq!(before_limit, None);
+            assert_eq!(game.outcome, Some(GameOutcome::TimeLimit));
+        }
+
+        #[test]
+        fn team_wins_with_enough_players_at_max_level() {
+            // Given
+            let (player_ids, mut game) =
+                game_engine_with(&positions(WINNING_PLAYERS_AT_MAX_LVL), None);
+            for id in &player_ids {
+                player_lvl_up(game.players.get_mut(id).unwrap(), MAX_PLAYER_LVL);
+            }
+
+            // When
+            game.tick(&mut Vec::new());
+
+            // Then
+            assert_eq!(game.outcome, Some(GameOutcome::Victory(test_team_name())));
+            assert_eq!(game.winner(), Some(test_team_name()));
+        }
+
+        #[test]
+        fn ranks_teams_by_level_then_by_players_at_that_level() {
+            // Given
+            let mut game = default_game_engine();
+            game.teams = BTreeMap::from([
+                (
+                    "first".to_string(),
+                    Team::new("first".to_string(), ZappyColor::Red, positions(2).into()),
+                ),
+                (
+                    "second".to_string(),
+                    Team::new("second".to_string(), ZappyColor::Blue, positions(2).into()),
+                ),
+            ]);
+            game.map = Map::empty(GAME_WIDTH, GAME_HEIGHT);
+            for pos in positions(2) {
+                game.map.field[pos.y][pos.x].eggs = BTreeMap::from([
+                    ("first".to_string(), (0, 1)),
+                    ("second".to_string(), (0, 1)),
+                ]);
+            }
+            game.add_player(1, "first".to_string()).unwrap();
+            game.add_player(2, "first".to_string()).unwrap();
+            game.add_player(3, "second".to_string()).unwrap();
+            player_lvl_up(game.players.get_mut(&1).unwrap(), 3);
+            player_lvl_up(game.players.get_mut(&3).unwrap(), 3);
+
+            // When
+            let tie = game.winner();
+            player_lvl_up(game.players.get_mut(&2).unwrap(), 3);
+
+            // Then
+            assert_eq!(tie, None);
+            assert_eq!(
+                game.team_scores().get("first"),
+                Some(&TeamScore {
+                    max_level: 3,
+                    players_at_max_level: 2
+                })
+            );
+            assert_eq!(game.winner(), Some("first".to_string()));
+        }
+    }
+
+    mod broadcast {
+        use super::*;
+        use crate::rules::BroadcastRules;
+        use Direction::*;
+
+        const TEXT: &str = "meet me at the linemate";
+
+        /// Player 0 broadcasts to player 1 on the same cell and player 2 on the next one.
+        fn broadcast_with(rules: BroadcastRules) -> Vec<(u16, ServerResponse)> {
+            let (players_ids, mut game) = game_engine_with(
+                &[
+                    Position {
+                        x: 1,
+                        y: 1,
+                        dir: North,
+                    },
+                    Position {
+                        x: 1,
+                        y: 1,
+                        dir: North,
+                    },
+                    Position {
+                        x: 2,
+                        y: 1,
+                        dir: North,
+                    },
+                ],
+                None,
+            );
+            game.rules.broadcast = rules;
+            game.rng = StdRng::seed_from_u64(0);
+            let command = PlayerCmd::Broadcast(TEXT.to_string());
+            game.take_command(&players_ids[0], command.clone()).unwrap();
+            let mut execution_results_buffer = Vec::new();
+            for _ in 0..command.delay() {
+                game.tick(&mut execution_results_buffer);
+            }
+            execution_results_buffer
+        }
+
+        #[test]
+        fn is_heard_by_everyone_by_default() {
+            assert_eq!(
+                broadcast_with(BroadcastRules::default()),
+                vec![
+                    (0, ServerResponse::Ok),
+                    (1, ServerResponse::Message(0, None, TEXT.to_string())),
+                    (2, ServerResponse::Message(3, None, TEXT.to_string())),
+                ]
+            );
+        }
+
+        #[test]
+        fn is_not_heard_outside_of_hearing_radius() {
+            let rules = BroadcastRules {
+                hearing_radius: Some(0),
+                ..Default::default()
+            };
+
+            assert_eq!(
+                broadcast_with(rules),
+                vec![
+                    (0, ServerResponse::Ok),
+                    (1, ServerResponse::Message(0, None, TEXT.to_string())),
+                ]
+            );
+        }
+
+        #[test]
+        fn reports_distance_to_the_sender() {
+            let rules = BroadcastRules {
+                report_distance: true,
+                ..Default::default()
+            };
+
+            assert_eq!(
+                broadcast_with(rules),
+                vec![
+                    (0, ServerResponse::Ok),
+                    (1, ServerResponse::Message(0, Some(0), TEXT.to_string())),
+                    (2, ServerResponse::Message(3, Some(1), TEXT.to_string())),
+                ]
+            );
+        }
+
+        #[test]
+        fn garbles_message_with_distance() {
+            let rules = BroadcastRules {
+                garble_rate: 1.,
+                ..Default::default()
+            };
+
+            let results = broadcast_with(rules);
+
+            assert_eq!(
+                results[1],
+                (1, ServerResponse::Message(0, None, TEXT.to_string()))
+            );
+            let ServerResponse::Message(3, None, garbled) = &results[2].1 else {
+                panic!("Player 2 should hear the broadcast from the left");
+            };
+            assert_eq!(garbled.chars().count(), TEXT.chars().count());
+            assert_ne!(garbled, TEXT);
+        }
+    }
+
+    mod simultaneous_actions {
+        use super::*;
+        use rstest::rstest;
+        use shared::resource::Stone::Linemate;
+
+        const CONTESTS: usize = 20;
+
+        /// Players 0 and 1 share a cell and both try to take the only linemate every
+        /// `Take` delay, returns the id of the winner of each contest.
+        fn contest_winners(resolution: ResolutionPolicy, seed: u64) -> Vec<u16> {
+            let position = Position {
+                x: 1,
+                y: 1,
+                dir: Direction::North,
+            };
+            let (players_ids, mut game) = game_engine_with(&[position; 2], None);
+            game.rules.resolution = resolution;
+            game.rng = StdRng::seed_from_u64(seed);
+            let take = PlayerCmd::Take(Linemate.to_string());
+            let mut winners = Vec::with_capacity(CONTESTS);
+
+            for _ in 0..CONTESTS {
+                for player_id in &players_ids {
+                    game.take_command(player_id, take.clone()).unwrap();
+                }
+                game.map.field[position.y][position.x].add_resource(Resource::Stone(Linemate));
+                let mut execution_results_buffer = Vec::new();
+                for _ in 0..take.delay() {
+                    game.tick(&mut execution_results_buffer);
+                }
+                let winners_of_contest = execution_results_buffer
+                    .iter()
+                    .filter(|(_, response)| *response == ServerResponse::Ok)
+                    .map(|(id, _)| *id)
+                    .collect::<Vec<_>>();
+                assert_eq!(winners_of_contest.len(), 1, "Only one player can take it");
+                winners.push(winners_of_contest[0]);
+            }
+            winners
+        }
+
+        #[test]
+        fn id_order_always_favors_the_lowest_id() {
+            assert!(contest_winners(ResolutionPolicy::IdOrder, 0)
+                .iter()
+                .all(|&id| id == 0));
+        }
+
+        #[test]
+        fn round_robin_alternates_the_first_player() {
+            let winners = contest_winners(ResolutionPolicy::RoundRobin, 0);
+
+            assert_eq!(
+                winners,
+                (0..CONTESTS).map(|i| (i % 2) as u16).collect::<Vec<_>>()
+            );
+        }
+
+        #[rstest]
+        #[case(0)]
+        #[case(42)]
+        #[case(4242)]
+        fn random_order_is_reproducible_with_seed(#[case] seed: u64) {
+            let winners = contest_winners(ResolutionPolicy::Random, seed);
+
+            assert_eq!(winners, contest_winners(ResolutionPolicy::Random, seed));
+            assert!(winners.contains(&0), "Player 0 should win at least once");
+            assert!(winners.contains(&1), "Player 1 should win at least once");
+        }
+    }
 }
@@ -0,0 +1,363 @@
+use crate::archive::Archive;
+use crate::args::ServerArgs;
+use crate::bot::Bots;
+use crate::engine_handle::EngineHandle;
+use crate::game_engine::GameEngine;
+use crate::history;
+use crate::metrics::MetricsWriter;
+use crate::outbox::{Outbox, SlowClientPolicy};
+use crate::routine::game::game_routine;
+use crate::session::{IdAllocator, Sessions};
+use serde::Serialize;
+use shared::{GFXData, GameError, PlayerError, ServerCommandToClient, ZappyError};
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::sync::Arc;
+use tokio::sync::{mpsc, Mutex};
+use tokio::task::JoinHandle;
+
+pub type PlayerSenders = Arc<Mutex<HashMap<u16, Outbox>>>;
+
+/// A running game: its engine and the channels to its connected players.
+#[derive(Clone)]
+pub struct Game {
+    pub engine: EngineHandle,
+    pub player_senders: PlayerSenders,
+    /// UTC, RFC 3339.
+    pub started_at: String,
+    /// Shared by every game.
+    pub ids: IdAllocator,
+    send_queue: usize,
+    slow_clients: SlowClientPolicy,
+}
+
+impl Game {
+    pub fn new(engine: EngineHandle, args: &ServerArgs, ids: IdAllocator) -> Self {
+        Self {
+            engine,
+            player_senders: Arc::new(Mutex::new(HashMap::new())),
+            started_at: history::now(),
+            ids,
+            send_queue: args.send_queue,
+            slow_clients: args.slow_clients,
+        }
+    }
+
+    /// Opens the queue of the messages to the player `id`, closed when the player lags too far
+    /// behind.
+    pub async fn connect(&self, id: u16) -> mpsc::Receiver<ServerCommandToClient> {
+        let (outbox, receiver) = Outbox::channel(self.send_queue, self.slow_clients);
+        self.player_senders.lock().await.insert(id, outbox);
+        receiver
+    }
+}
+
+/// The state of a game when the server stopped.
+#[derive(Serialize, Debug)]
+pub struct GameSnapshot {
+    /// UTC, RFC 3339.
+    pub started_at: String,
+    pub frame: u64,
+    pub state: GFXData,
+}
+
+/// The games hosted by the server, each one ticking in its own task at its own pace.
+pub struct GameManager {
+    games: BTreeMap<String, (Game, JoinHandle<()>)>,
+    /// Records every game reaching an outcome.
+    archive: Archive,
+    ids: IdAllocator,
+    sessions: Sessions,
+}
+
+impl GameManager {
+    pub fn new(archive: Archive) -> Self {
+        Self {
+            games: BTreeMap::new(),
+            archive,
+            ids: IdAllocator::default(),
+            sessions: Sessions::default(),
+        }
+    }
+
+    pub fn create_game(&mut self, name: String, args: &ServerArgs) -> Result<(), ZappyError> {
+        if self.games.contains_key(&name) {
+            return Err(ZappyError::Game(GameError::GameAlreadyExists(name)));
+        }
+        let metrics = args
+            .metrics
+            .as_ref()
+            .map(|path| MetricsWriter::create(path, &name))
+            .transpose()
+            .map_err(|e| ZappyError::Game(GameError::InvalidGameArguments(e.to_string())))?;
+        let engine = GameEngine::new(args);
+        log::info!(
+            "Game \"{name}\" created: {}x{}, teams: {:?}, seed: {}",
+            engine.map_width(),
+            engine.map_height(),
+            args.names,
+            engine.seed()
+        );
+        let (handle, mailbox) = EngineHandle::channel(&name);
+        let game = Game::new(handle, args, self.ids.clone());
+        let routine = tokio::spawn(game_routine(
+            name.clone(),
+            game.clone(),
+            (engine, mailbox),
+            self.archive.clone(),
+            metrics,
+            args.bots.map(|difficulty| Bots {
+                difficulty,
+                teams: args.bot_teams.clone(),
+                human_slots: args.human_slots,
+            }),
+            args.tud,
+        ));
+        self.games.insert(name, (game, routine));
+        Ok(())
+    }
+
+    /// Stops the game and disconnects its players.
+    pub async fn remove_game(&mut self, name: &str) -> Result<(), ZappyError> {
+        let (game, routine) = self
+            .games
+            .remove(name)
+            .ok_or_else(|| ZappyError::Player(PlayerError::GameDoesntExist(name.to_string())))?;
+        routine.abort();
+        for (_, outbox) in game.player_senders.lock().await.drain() {
+            let _ = outbox.send(ServerCommandToClient::Shutdown);
+        }
+        log::info!("Game \"{name}\" removed");
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Result<Game, ZappyError> {
+        self.games
+            .get(name)
+            .map(|(game, _)| game.clone())
+            .ok_or_else(|| ZappyError::Player(PlayerError::GameDoesntExist(name.to_string())))
+    }
+
+    pub fn archive(&self) -> &Archive {
+        &self.archive
+    }
+
+    pub fn ids(&self) -> &IdAllocator {
+        &self.ids
+    }
+
+    pub fn sessions(&self) -> &Sessions {
+        &self.sessions
+    }
+
+    /// Stops every game for the server to exit: writes the report and logs the statistics of
+    /// the unfinished ones, disconnects their players and writes the state of all of them to the
+    /// JSON file `snapshot` if set.
+    pub async fn shutdown(&mut self, snapshot: Option<&Path>) -> std::io::Result<()> {
+        let mut snapshots = BTreeMap::new();
+        for (name, (game, routine)) in std::mem::take(&mut self.games) {
+            let archive = self.archive.clone();
+            let game_name = name.clone();
+            let started_at = game.started_at.clone();
+            let state = game
+                .engine
+                .call(move |engine| {
+                    let mut report = None;
+                    if engine.outcome().is_none() {
+                        report = archive.report(&game_name, &started_at, engine);
+                        for line in engine.stats_report() {
+                            log::info!("{game_name}: {line}");
+                        }
+                    }
+                    let snapshot = GameSnapshot {
+                        started_at,
+                        frame: *engine.frame(),
+                        state: engine.gfx_data(),
+                    };
+                    (snapshot, report)
+                })
+                .await;
+            routine.abort();
+            for (_, outbox) in game.player_senders.lock().await.drain() {
+                let _ = outbox.send(ServerCommandToClient::Shutdown);
+            }
+            match state {
+                Ok((state, report)) => {
+                    if let Some(report) = report {
+                        let archive = self.archive.clone();
+                        let _ = tokio::task::spawn_blocking(move || archive.write_report(&report))
+                            .await;
+                    }
+                    snapshots.insert(name, state);
+                }
+                Err(e) => log::error!("Failed to stop game \"{name}\": {e}"),
+            }
+        }
+        if let Some(path) = snapshot {
+            std::fs::write(path, serde_json::to_string_pretty(&snapshots)?)?;
+            log::info!("Snapshot of the games written to {}", path.display());
+        }
+        Ok(())
+    }
+
+    pub fn games(&self) -> impl Iterator<Item = (&String, &Game)> {
+        self.games.iter().map(|(name, (game, _))| (name, game))
+    }
+}
+
+#[cfg(test)]
+mod game_manager_tests {
+    use super::*;
+    use crate::history::History;
+    use crate::ratings::Ratings;
+    use clap::Parser;
+
+    fn game_manager() -> GameManager {
+        GameManager::new(Archive::new(
+            Ratings::default(),
+            History::open(None).unwrap(),
+            None,
+        ))
+    }
+
+    fn args() -> ServerArgs {
+        ServerArgs::try_parse_from(["server", "-x", "5", "-y", "4", "-n", "Axel", "Anton"]).unwrap()
+    }
+
+    #[tokio::test]
+    async fn hosts_independent_games() {
+        // Given
+        let mut games = game_manager();
+        games.create_game("first".to_string(), &args()).unwrap();
+        games.create_game("second".to_string(), &args()).unwrap();
+
+        // When
+        let first = games.get("first").unwrap();
+        let second = games.get("second").unwrap();
+        first
+            .engine
+            .call(|engine| engine.add_player(1, "Axel".to_string()))
+            .await
+            .unwrap()
+            .unwrap();
+
+        // Then
+        let players = |game: Game| async move {
+            game.engine
+                .call(|engine| engine.players().len())
+                .await
+                .unwrap()
+        };
+        assert_eq!(players(first).await, 1);
+        assert_eq!(players(second).await, 0);
+        assert_eq!(
+            games
+                .games()
+                .map(|(name, _)| name.as_str())
+                .collect::<Vec<_>>(),
+            vec!["first", "second"]
+        );
+    }
+
+    #[tokio::test]
+    async fn answers_calls_of_game_behind_schedule() {
+        // Given
+        let args = ServerArgs::try_parse_from([
+            "server", "-x", "100", "-y", "100", "-n", "Axel", "Anton", "-t", "65535",
+        ])
+        .unwrap();
+        let mut games = game_manager();
+        games.create_game("game".to_string(), &args).unwrap();
+        let game = games.get("game").unwrap();
+
+        // When
+        let frame = |game: Game| async move {
+            tokio::time::timeout(
+                std::time::Duration::from_secs(5),
+                game.engine.call(|engine| *engine.frame()),
+            )
+            .await
+            .unwrap()
+            .unwrap()
+        };
+        let start = std::time::Instant::now();
+        let first = frame(game.clone()).await;
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+        let second = frame(game).await;
+        let scheduled = (start.elapsed().as_secs_f64() * 65535.) as u64;
+
+        // Then
+        assert!(first < second);
+        assert!(second < scheduled, "the game should be behind schedule");
+    }
+
+    #[tokio::test]
+    async fn fails_to_create_existing_game() {
+        let mut games = game_manager();
+        games.create_game("game".to_string(), &args()).unwrap();
+
+        assert_eq!(
+            games.create_game("game".to_string(), &args()),
+            Err(ZappyError::Game(GameError::GameAlreadyExists(
+                "game".to_string()
+            )))
+        );
+    }
+
+    #[tokio::test]
+    async fn shuts_down_games_and_writes_snapshot() {
+        // Given
+        let path = std::env::temp_dir().join(format!("zappy-snapshot-{}.json", std::process::id()));
+        let mut games = game_manager();
+        games.create_game("first".to_string(), &args()).unwrap();
+        games.create_game("second".to_string(), &args()).unwrap();
+        let mut rx = games.get("first").unwrap().connect(1).await;
+
+        // When
+        games.shutdown(Some(&path)).await.unwrap();
+        let snapshot: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        // Then
+        assert!(matches!(
+            rx.recv().await,
+            Some(ServerCommandToClient::Shutdown)
+        ));
+        assert_eq!(games.games().count(), 0);
+        assert_eq!(
+            snapshot
+                .as_object()
+                .unwrap()
+                .keys()
+                .map(String::as_str)
+                .collect::<Vec<_>>(),
+            vec!["first", "second"]
+        );
+        assert!(snapshot["first"]["state"]["map"].is_object());
+    }
+
+    #[tokio::test]
+    async fn removes_game_and_disconnects_its_players() {
+        // Given
+        let mut games = game_manager();
+        games.create_game("game".to_string(), &args()).unwrap();
+        let mut rx = games.get("game").unwrap().connect(1).await;
+
+        // When
+        games.remove_game("game").await.unwrap();
+
+        // Then
+        assert!(matches!(
+            rx.recv().await,
+            Some(ServerCommandToClient::Shutdown)
+        ));
+        assert!(games.get("game").is_err());
+        assert_eq!(
+            games.remove_game("game").await,
+            Err(ZappyError::Player(PlayerError::GameDoesntExist(
+                "game".to_string()
+            )))
+        );
+    }
+}
@@ -0,0 +1,531 @@
+use crate::args::ServerArgs;
+use chrono::{DateTime, SecondsFormat};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::watch;
+
+/// Allows `rate` events per second on average, in bursts of up to `rate` events.
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    rate: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(rate: u32) -> Self {
+        Self {
+            rate: rate as f64,
+            tokens: rate as f64,
+            last: Instant::now(),
+        }
+    }
+
+    pub fn allow(&mut self) -> bool {
+        self.allow_at(Instant::now())
+    }
+
+    fn allow_at(&mut self, now: Instant) -> bool {
+        self.refill(now);
+        if self.tokens >= 1. {
+            self.tokens -= 1.;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate);
+        self.last = now;
+    }
+
+    /// Whether the limiter forgot every event, so it can be dropped.
+    fn is_full(&mut self, now: Instant) -> bool {
+        self.refill(now);
+        self.tokens >= self.rate
+    }
+}
+
+/// Connection and command limits of the player, gfx and admin listeners, all together.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GuardLimits {
+    pub max_connections: usize,
+    pub max_connections_per_ip: usize,
+    /// Accepted connections per second of an address, more gets it banned.
+    pub connection_rate: u32,
+    /// Accepted connections per second of all the addresses.
+    pub global_connection_rate: u32,
+    /// Lines per second of a connection, more gets its address banned. No limit if `None`.
+    pub command_rate: Option<u32>,
+    pub ban_duration: Duration,
+}
+
+impl GuardLimits {
+    pub fn new(args: &ServerArgs) -> Self {
+        Self {
+            max_connections: args.max_connections,
+            max_connections_per_ip: args.max_connections_per_ip,
+            connection_rate: args.connection_rate,
+            global_connection_rate: args.global_connection_rate,
+            command_rate: args.command_rate,
+            ban_duration: Duration::from_secs(args.ban_duration),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Ban {
+    /// Unix time in seconds.
+    pub until: u64,
+    pub reason: String,
+}
+
+impl Display for Ban {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let until = DateTime::from_timestamp(self.until as i64, 0)
+            .map(|until| until.to_rfc3339_opts(SecondsFormat::Secs, true))
+            .unwrap_or_else(|| self.until.to_string());
+        write!(f, "banned until {until}: {}", self.reason)
+    }
+}
+
+/// Why a connection is refused.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Refusal {
+    Banned(Ban),
+    TooManyConnections,
+    TooManyConnectionsFromAddress,
+    TooFast,
+}
+
+impl Display for Refusal {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Refusal::Banned(ban) => write!(f, "{ban}"),
+            Refusal::TooManyConnections => write!(f, "too many connections"),
+            Refusal::TooManyConnectionsFromAddress => {
+                write!(f, "too many connections from this address")
+            }
+            Refusal::TooFast => write!(f, "too many connections per second"),
+        }
+    }
+}
+
+/// The open connections of an address.
+#[derive(Debug)]
+struct OpenConnections {
+    count: usize,
+    /// Set once the address is banned, closing the connections.
+    banned: watch::Sender<bool>,
+}
+
+#[derive(Debug)]
+struct GuardState {
+    open: HashMap<IpAddr, OpenConnections>,
+    total: usize,
+    rates: HashMap<IpAddr, RateLimiter>,
+    global_rate: RateLimiter,
+    bans: BTreeMap<IpAddr, Ban>,
+    /// Changes of the bans, so that the saves are written in order.
+    version: u64,
+}
+
+/// Admits the connections within the limits and bans the addresses flooding the server.
+#[derive(Debug, Clone)]
+pub struct Guard {
+    limits: GuardLimits,
+    /// JSON file the bans are loaded from and saved to, in memory only if not set.
+    path: Option<PathBuf>,
+    state: Arc<Mutex<GuardState>>,
+    /// Version of the bans last written to the file.
+    saved: Arc<Mutex<u64>>,
+}
+
+impl Guard {
+    pub fn load(limits: GuardLimits, path: Option<PathBuf>) -> Result<Self, Box<dyn Error>> {
+        let bans = match &path {
+            Some(path) if path.exists() => serde_json::from_str(&std::fs::read_to_string(path)?)?,
+            _ => BTreeMap::new(),
+        };
+        Ok(Self {
+            limits,
+            path,
+            state: Arc::new(Mutex::new(GuardState {
+                open: HashMap::new(),
+                total: 0,
+                rates: HashMap::new(),
+                global_rate: RateLimiter::new(limits.global_connection_rate),
+                bans,
+                version: 0,
+            })),
+            saved: Arc::default(),
+        })
+    }
+
+    /// Admits a connection from `ip`, counted until the permit is dropped. Loopback addresses,
+    /// the ones of the bots and tools running next to the server, only count in the global
+    /// limits.
+    pub fn admit(&self, ip: IpAddr) -> Result<Permit, Refusal> {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        if let Some(ban) = state.bans.get(&ip) {
+            if ban.until > unix_now() {
+                return Err(Refusal::Banned(ban.clone()));
+            }
+            state.bans.remove(&ip);
+            self.save(&mut state);
+        }
+        let GuardState { open, rates, .. } = &mut *state;
+        rates.retain(|ip, rate| open.contains_key(ip) || !rate.is_full(now));
+        let allowed = ip.is_loopback()
+            || rates
+                .entry(ip)
+                .or_insert_with(|| RateLimiter::new(self.limits.connection_rate))
+                .allow_at(now);
+        if !allowed {
+            drop(state);
+            let ban = self.ban(ip, self.limits.ban_duration, "connection flood");
+            return Err(Refusal::Banned(ban));
+        }
+        if !state.global_rate.allow_at(now) {
+            return Err(Refusal::TooFast);
+        }
+        if state.total >= self.limits.max_connections {
+            return Err(Refusal::TooManyConnections);
+        }
+        let open = state.open.entry(ip).or_insert_with(|| OpenConnections {
+            count: 0,
+            banned: watch::Sender::new(false),
+        });
+        if open.count >= self.limits.max_connections_per_ip && !ip.is_loopback() {
+            return Err(Refusal::TooManyConnectionsFromAddress);
+        }
+        open.count += 1;
+        let banned = open.banned.subscribe();
+        state.total += 1;
+        Ok(Permit {
+            guard: self.clone(),
+            ip,
+            commands: self.limits.command_rate.map(RateLimiter::new),
+            banned,
+        })
+    }
+
+    /// Bans `ip` and closes its open connections.
+    pub fn ban(&self, ip: IpAddr, duration: Duration, reason: &str) -> Ban {
+        let ban = Ban {
+            until: unix_now() + duration.as_secs(),
+            reason: reason.to_string(),
+        };
+        log::warn!("{ip} {ban}");
+        let mut state = self.state.lock().unwrap();
+        state.bans.insert(ip, ban.clone());
+        if let Some(open) = state.open.get(&ip) {
+            open.banned.send_replace(true);
+        }
+        self.save(&mut state);
+        ban
+    }
+
+    /// Returns whether `ip` was banned.
+    pub fn unban(&self, ip: IpAddr) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let banned = state.bans.remove(&ip).is_some();
+        self.save(&mut state);
+        banned
+    }
+
+    /// The bans still running.
+    pub fn bans(&self) -> Vec<(IpAddr, Ban)> {
+        let now = unix_now();
+        let state = self.state.lock().unwrap();
+        state
+            .bans
+            .iter()
+            .filter(|(_, ban)| ban.until > now)
+            .map(|(ip, ban)| (*ip, ban.clone()))
+            .collect()
+    }
+
+    pub fn ban_duration(&self) -> Duration {
+        self.limits.ban_duration
+    }
+
+    /// Writes the bans to the file on a blocking thread, not to hold the accepting routines, or
+    /// right away without a runtime. A save older than the file is dropped.
+    fn save(&self, state: &mut GuardState) {
+        let Some(path) = self.path.clone() else {
+            return;
+        };
+        state.version += 1;
+        let version = state.version;
+        let json = match serde_json::to_string_pretty(&state.bans) {
+            Ok(json) => json,
+            Err(e) => {
+                log::error!("Failed to save the bans to {}: {e}", path.display());
+                return;
+            }
+        };
+        let saved = Arc::clone(&self.saved);
+        let write = move || {
+            let mut saved = saved.lock().unwrap();
+            if *saved > version {
+                return;
+            }
+            *saved = version;
+            if let Err(e) = std::fs::write(&path, json) {
+                log::error!("Failed to save the bans to {}: {e}", path.display());
+            }
+        };
+        match tokio::runtime::Handle::try_current() {
+            Ok(runtime) => drop(runtime.spawn_blocking(write)),
+            Err(_) => write(),
+        }
+    }
+}
+
+/// An admitted connection.
+#[derive(Debug)]
+pub struct Permit {
+    guard: Guard,
+    ip: IpAddr,
+    commands: Option<RateLimiter>,
+    banned: watch::Receiver<bool>,
+}
+
+/// Held by a connection to be closed when its address is banned.
+#[derive(Debug, Clone)]
+pub struct BanSignal {
+    receiver: watch::Receiver<bool>,
+}
+
+impl BanSignal {
+    /// Completes once the address of the connection is banned.
+    pub async fn banned(&mut self) {
+        if self.receiver.wait_for(|banned| *banned).await.is_err() {
+            std::future::pending::<()>().await;
+        }
+    }
+}
+
+impl Permit {
+    pub fn ban_signal(&self) -> BanSignal {
+        BanSignal {
+            receiver: self.banned.clone(),
+        }
+    }
+
+    /// Counts a line received on the connection. Returns `false` and bans the address if the
+    /// connection sends too many of them.
+    pub fn allow_command(&mut self) -> bool {
+        if self.ip.is_loopback() || self.commands.as_mut().is_none_or(RateLimiter::allow) {
+            return true;
+        }
+        self.guard
+            .ban(self.ip, self.guard.limits.ban_duration, "command flood");
+        false
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        let mut state = self.guard.state.lock().unwrap();
+        state.total -= 1;
+        if let Some(open) = state.open.get_mut(&self.ip) {
+            open.count -= 1;
+            if open.count == 0 {
+                state.open.remove(&self.ip);
+            }
+        }
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod guard_tests {
+    use super::*;
+
+    fn limits() -> GuardLimits {
+        GuardLimits {
+            max_connections: 3,
+            max_connections_per_ip: 2,
+            connection_rate: 4,
+            global_connection_rate: 100,
+            command_rate: Some(2),
+            ban_duration: Duration::from_secs(60),
+        }
+    }
+
+    fn ip(last: u8) -> IpAddr {
+        IpAddr::from([10, 0, 0, last])
+    }
+
+    #[test]
+    fn limits_rate() {
+        // Given
+        let start = Instant::now();
+        let mut limiter = RateLimiter::new(2);
+
+        // When
+        let burst: Vec<bool> = (0..3).map(|_| limiter.allow_at(start)).collect();
+        let later = limiter.allow_at(start + Duration::from_millis(500));
+
+        // Then
+        assert_eq!(burst, vec![true, true, false]);
+        assert!(later);
+    }
+
+    #[test]
+    fn limits_open_connections() {
+        // Given
+        let guard = Guard::load(limits(), None).unwrap();
+
+        // When
+        let first = guard.admit(ip(1)).unwrap();
+        let _second = guard.admit(ip(1)).unwrap();
+        let third = guard.admit(ip(1));
+        let _other = guard.admit(ip(2)).unwrap();
+        let over = guard.admit(ip(3));
+        drop(first);
+
+        // Then
+        assert_eq!(third.unwrap_err(), Refusal::TooManyConnectionsFromAddress);
+        assert_eq!(over.unwrap_err(), Refusal::TooManyConnections);
+        assert!(guard.admit(ip(1)).is_ok());
+    }
+
+    #[test]
+    fn exempts_loopback_from_address_limits() {
+        // Given
+        let guard = Guard::load(limits(), None).unwrap();
+        let loopback = IpAddr::from([127, 0, 0, 1]);
+
+        // When
+        for _ in 0..5 {
+            drop(guard.admit(loopback).unwrap());
+        }
+        let mut permits: Vec<Permit> = (0..3).map(|_| guard.admit(loopback).unwrap()).collect();
+        let commands: Vec<bool> = (0..3).map(|_| permits[0].allow_command()).collect();
+
+        // Then
+        assert_eq!(commands, vec![true; 3]);
+        assert_eq!(
+            guard.admit(loopback).unwrap_err(),
+            Refusal::TooManyConnections
+        );
+        assert!(guard.bans().is_empty());
+    }
+
+    #[test]
+    fn leaves_commands_unlimited_without_rate() {
+        // Given
+        let guard = Guard::load(
+            GuardLimits {
+                command_rate: None,
+                ..limits()
+            },
+            None,
+        )
+        .unwrap();
+        let mut permit = guard.admit(ip(1)).unwrap();
+
+        // When
+        let commands: Vec<bool> = (0..100).map(|_| permit.allow_command()).collect();
+
+        // Then
+        assert_eq!(commands, vec![true; 100]);
+        assert!(guard.bans().is_empty());
+    }
+
+    #[test]
+    fn bans_floods_and_saves_the_bans() {
+        // Given
+        let path = std::env::temp_dir().join(format!("zappy-bans-{}.json", std::process::id()));
+        let guard = Guard::load(limits(), Some(path.clone())).unwrap();
+
+        // When
+        for _ in 0..4 {
+            drop(guard.admit(ip(1)).unwrap());
+        }
+        let flood = guard.admit(ip(1));
+        let mut permit = guard.admit(ip(2)).unwrap();
+        let commands: Vec<bool> = (0..3).map(|_| permit.allow_command()).collect();
+        let reloaded = Guard::load(limits(), Some(path.clone())).unwrap();
+        let banned = reloaded.bans();
+        let unbanned = reloaded.unban(ip(2));
+        let saved = Guard::load(limits(), Some(path.clone())).unwrap().bans();
+        std::fs::remove_file(&path).unwrap();
+
+        // Then
+        assert!(matches!(flood, Err(Refusal::Banned(ban)) if ban.reason == "connection flood"));
+        assert_eq!(commands, vec![true, true, false]);
+        assert_eq!(
+            banned
+                .iter()
+                .map(|(ip, ban)| (*ip, ban.reason.as_str()))
+                .collect::<Vec<_>>(),
+            vec![(ip(1), "connection flood"), (ip(2), "command flood")]
+        );
+        assert!(unbanned);
+        assert_eq!(saved.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn closes_connections_of_banned_address() {
+        // Given
+        let guard = Guard::load(limits(), None).unwrap();
+        let banned = guard.admit(ip(1)).unwrap();
+        let other = guard.admit(ip(2)).unwrap();
+        let mut banned_signal = banned.ban_signal();
+        let mut other_signal = other.ban_signal();
+
+        // When
+        guard.ban(ip(1), Duration::from_secs(60), "admin");
+
+        // Then
+        let wait = Duration::from_millis(50);
+        assert!(tokio::time::timeout(wait, banned_signal.banned())
+            .await
+            .is_ok());
+        assert!(tokio::time::timeout(wait, other_signal.banned())
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn saves_bans_off_the_runtime() {
+        // Given
+        let path =
+            std::env::temp_dir().join(format!("zappy-bans-async-{}.json", std::process::id()));
+        let guard = Guard::load(limits(), Some(path.clone())).unwrap();
+
+        // When
+        for last in 1..=5 {
+            guard.ban(ip(last), Duration::from_secs(60), "admin");
+        }
+        guard.unban(ip(3));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let saved = Guard::load(limits(), Some(path.clone())).unwrap().bans();
+        std::fs::remove_file(&path).unwrap();
+
+        // Then
+        assert_eq!(
+            saved.iter().map(|(ip, _)| *ip).collect::<Vec<_>>(),
+            vec![ip(1), ip(2), ip(4), ip(5)]
+        );
+    }
+}
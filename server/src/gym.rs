@@ -0,0 +1,391 @@
+use crate::args::ServerArgs;
+use crate::game_engine::GameEngine;
+use crate::listener::remove_stale_socket;
+use serde::{Deserialize, Serialize};
+use shared::commands::PlayerCmd;
+use shared::{ServerResponse, MAX_COMMANDS};
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixListener;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// Reward of a player for every level gained.
+const LEVEL_UP_REWARD: f64 = 1.;
+/// Reward of a player dying, of starvation or not.
+const DEATH_REWARD: f64 = -1.;
+
+/// How a trainer drives the environment: JSON lines over the standard input and output, or over
+/// a Unix socket.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum GymMode {
+    Stdio,
+    Socket(PathBuf),
+}
+
+impl FromStr for GymMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "" => Err("Expected \"stdio\" or a socket path".to_string()),
+            "stdio" => Ok(GymMode::Stdio),
+            path => Ok(GymMode::Socket(PathBuf::from(path))),
+        }
+    }
+}
+
+/// What an agent knows of the game: the responses it would get to `See` and `Inventory`.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Observation {
+    pub see: Vec<String>,
+    pub inventory: Vec<String>,
+    pub level: u8,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Step {
+    /// Agents still alive.
+    pub observations: BTreeMap<u16, Observation>,
+    /// Responses received by every agent during the step, including the messages it heard.
+    pub responses: BTreeMap<u16, Vec<ServerResponse>>,
+    pub rewards: BTreeMap<u16, f64>,
+    /// Whether each agent of the episode is dead.
+    pub dones: BTreeMap<u16, bool>,
+    /// The game reached an outcome or every agent is dead.
+    pub done: bool,
+    pub frame: u64,
+}
+
+/// Reinforcement-learning environment around a game engine. Every team slot of the game is an
+/// agent, numbered from 1 in team order, and a step lasts until every agent given a command is
+/// ready to execute the next one.
+pub struct GymEnv {
+    args: ServerArgs,
+    engine: GameEngine,
+    agents: Vec<u16>,
+}
+
+impl GymEnv {
+    pub fn new(args: &ServerArgs) -> Self {
+        let mut env = Self {
+            args: args.clone(),
+            engine: GameEngine::new(args),
+            agents: Vec::new(),
+        };
+        env.reset(args.seed);
+        env
+    }
+
+    /// Starts a new episode, with a random seed if `None`, and returns the first observations.
+    pub fn reset(&mut self, seed: Option<u64>) -> BTreeMap<u16, Observation> {
+        self.args.seed = seed;
+        self.engine = GameEngine::new(&self.args);
+        self.agents.clear();
+        let slots: Vec<(String, u16)> = self
+            .engine
+            .teams()
+            .iter()
+            .map(|(name, team)| (name.clone(), team.remaining_members()))
+            .collect();
+        for (team, slots) in slots {
+            for _ in 0..slots {
+                let id = self.agents.len() as u16 + 1;
+                if self.engine.add_player(id, team.clone()).is_ok() {
+                    self.agents.push(id);
+                }
+            }
+        }
+        self.observations()
+    }
+
+    pub fn observe(&self, player: u16) -> Option<Observation> {
+        Some(Observation {
+            see: self.engine.see(player)?,
+            inventory: self.engine.inventory(player)?,
+            level: *self.engine.players().get(&player)?.level(),
+        })
+    }
+
+    fn observations(&self) -> BTreeMap<u16, Observation> {
+        self.agents
+            .iter()
+            .filter_map(|&id| Some((id, self.observe(id)?)))
+            .collect()
+    }
+
+    /// Queues a command for some agents and runs the game until they can send the next one, or
+    /// for a single frame if none is given. Nothing is queued if one of the agents can't take
+    /// its command.
+    pub fn step(&mut self, actions: BTreeMap<u16, PlayerCmd>) -> Result<Step, String> {
+        for id in actions.keys() {
+            match self.engine.players().get(id) {
+                None => return Err(format!("Player {id} isn't alive")),
+                Some(player) if player.commands().len() >= MAX_COMMANDS => {
+                    return Err(format!(
+                        "Player {id} can't take a command: {}",
+                        ServerResponse::ActionQueueIsFull
+                    ))
+                }
+                Some(_) => {}
+            }
+        }
+        let levels: BTreeMap<u16, u8> = self
+            .engine
+            .players()
+            .iter()
+            .map(|(&id, player)| (id, *player.level()))
+            .collect();
+        for (id, command) in &actions {
+            match self.engine.take_command(id, command.clone()) {
+                Ok(None) => {}
+                Ok(Some(response)) => {
+                    return Err(format!("Player {id} can't take a command: {response}"))
+                }
+                Err(e) => return Err(format!("Player {id} can't take a command: {e}")),
+            }
+        }
+
+        let mut responses: BTreeMap<u16, Vec<ServerResponse>> = BTreeMap::new();
+        let mut results = Vec::new();
+        loop {
+            self.engine.tick(&mut results);
+            for (id, response) in results.drain(..) {
+                responses.entry(id).or_default().push(response);
+            }
+            let ready = actions.keys().all(|id| {
+                self.engine.players().get(id).is_none_or(|player| {
+                    player.commands().is_empty()
+                        && !player.is_performing_incantation()
+                        && *player.next_frame() <= self.engine.frame() + 1
+                })
+            });
+            if ready || self.engine.outcome().is_some() {
+                break;
+            }
+        }
+
+        let mut rewards = BTreeMap::new();
+        let mut dones = BTreeMap::new();
+        for &id in &self.agents {
+            let player = self.engine.players().get(&id);
+            let reward = match (levels.get(&id), player) {
+                (Some(&before), Some(player)) => (player.level() - before) as f64 * LEVEL_UP_REWARD,
+                (Some(_), None) => DEATH_REWARD,
+                (None, _) => 0.,
+            };
+            rewards.insert(id, reward);
+            dones.insert(id, player.is_none());
+        }
+        Ok(Step {
+            observations: self.observations(),
+            responses,
+            rewards,
+            done: self.engine.outcome().is_some() || dones.values().all(|&done| done),
+            dones,
+            frame: *self.engine.frame(),
+        })
+    }
+
+    /// Answers one JSON request, see `GymRequest`.
+    pub fn handle(&mut self, line: &str) -> serde_json::Value {
+        let result = serde_json::from_str::<GymRequest>(line)
+            .map_err(|e| e.to_string())
+            .and_then(|request| match request {
+                GymRequest::Reset { seed } => Ok(serde_json::json!({
+                    "observations": self.reset(seed),
+                    "seed": self.engine.seed(),
+                })),
+                GymRequest::Observe { player } => self
+                    .observe(player)
+                    .map(|observation| serde_json::json!(observation))
+                    .ok_or(format!("Player {player} isn't alive")),
+                GymRequest::Step { actions } => {
+                    let actions = actions
+                        .into_iter()
+                        .map(|(id, command)| {
+                            let id = id
+                                .parse()
+                                .map_err(|_| format!("Invalid player: \"{id}\""))?;
+                            Ok((id, PlayerCmd::try_from(command.trim())?))
+                        })
+                        .collect::<Result<BTreeMap<_, _>, String>>()?;
+                    self.step(actions).map(|step| serde_json::json!(step))
+                }
+            });
+        match result {
+            Ok(value) => serde_json::json!({ "ok": value }),
+            Err(e) => serde_json::json!({ "error": e }),
+        }
+    }
+
+    /// Answers the requests read line by line until the end of the input.
+    pub fn serve(&mut self, input: impl BufRead, mut output: impl Write) -> std::io::Result<()> {
+        for line in input.lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            writeln!(output, "{}", self.handle(&line))?;
+            output.flush()?;
+        }
+        Ok(())
+    }
+}
+
+/// Request of a trainer, a JSON object on a single line tagged by `op`:
+/// `{"op": "reset", "seed": 42}`, `{"op": "observe", "player": 1}` or
+/// `{"op": "step", "actions": {"1": "avance", "2": "prend nourriture"}}`.
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum GymRequest {
+    Reset {
+        #[serde(default)]
+        seed: Option<u64>,
+    },
+    Observe {
+        player: u16,
+    },
+    Step {
+        /// By player id, the ids being strings in JSON objects.
+        #[serde(default)]
+        actions: BTreeMap<String, String>,
+    },
+}
+
+/// Runs the environment of the game described by `args` until the trainer leaves.
+pub fn run(args: &ServerArgs, mode: &GymMode) -> std::io::Result<()> {
+    let mut env = GymEnv::new(args);
+    match mode {
+        GymMode::Stdio => {
+            log::info!("Gym environment reading requests on the standard input");
+            env.serve(std::io::stdin().lock(), std::io::stdout().lock())
+        }
+        GymMode::Socket(path) => {
+            remove_stale_socket(path)?;
+            let listener = UnixListener::bind(path)?;
+            log::info!("Gym environment listening on {}", path.display());
+            for stream in listener.incoming() {
+                let stream = stream?;
+                log::info!("Trainer connected");
+                if let Err(e) = env.serve(BufReader::new(stream.try_clone()?), stream) {
+                    log::warn!("Trainer left: {e}");
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod gym_tests {
+    use super::*;
+    use clap::Parser;
+    use shared::{LIFE_TICKS, LIVES_START};
+
+    fn env() -> GymEnv {
+        let args = ServerArgs::try_parse_from([
+            "server", "-x", "5", "-y", "4", "-n", "anton", "axel", "-c", "2", "--seed", "7",
+        ])
+        .unwrap();
+        GymEnv::new(&args)
+    }
+
+    #[test]
+    fn resets_with_one_agent_per_slot() {
+        // Given
+        let mut env = env();
+
+        // When
+        let first = env.reset(Some(3));
+        let again = env.reset(Some(3));
+
+        // Then
+        assert_eq!(first.keys().copied().collect::<Vec<_>>(), vec![1, 2, 3, 4]);
+        assert_eq!(first, again);
+        assert_eq!(first[&1].level, 1);
+        assert_eq!(first[&1].see.len(), 4);
+        assert_eq!(
+            first[&1].inventory[0],
+            format!("nourriture {}", LIFE_TICKS * LIVES_START)
+        );
+        assert_eq!(env.engine.players()[&3].team(), "axel");
+    }
+
+    #[test]
+    fn steps_until_agents_are_ready() {
+        // Given
+        let mut env = env();
+
+        // When
+        let idle = env.step(BTreeMap::new()).unwrap();
+        let step = env
+            .step(BTreeMap::from([(1, PlayerCmd::Move), (2, PlayerCmd::Fork)]))
+            .unwrap();
+
+        // Then
+        assert_eq!(idle.frame, 1);
+        assert_eq!(step.frame, 1 + PlayerCmd::Fork.delay());
+        assert_eq!(step.responses[&1], vec![ServerResponse::Ok]);
+        assert_eq!(step.responses[&2], vec![ServerResponse::Ok]);
+        assert_eq!(step.rewards.values().sum::<f64>(), 0.);
+        assert!(!step.done);
+        assert_eq!(step.dones.len(), 4);
+        assert_eq!(
+            env.step(BTreeMap::from([(9, PlayerCmd::Move)])),
+            Err("Player 9 isn't alive".to_string())
+        );
+    }
+
+    #[test]
+    fn queues_nothing_when_an_agent_cant_take_its_command() {
+        // Given
+        let mut env = env();
+        for _ in 0..MAX_COMMANDS {
+            env.engine.take_command(&2, PlayerCmd::Move).unwrap();
+        }
+
+        // When
+        let step = env.step(BTreeMap::from([(1, PlayerCmd::Move), (2, PlayerCmd::Move)]));
+
+        // Then
+        assert_eq!(
+            step,
+            Err(format!(
+                "Player 2 can't take a command: {}",
+                ServerResponse::ActionQueueIsFull
+            ))
+        );
+        assert!(env.engine.players()[&1].commands().is_empty());
+        assert_eq!(*env.engine.frame(), 0);
+    }
+
+    #[test]
+    fn answers_json_lines() {
+        // Given
+        let mut env = env();
+        let input = [
+            r#"{"op": "reset", "seed": 5}"#,
+            r#"{"op": "step", "actions": {"1": "droite"}}"#,
+            r#"{"op": "observe", "player": 1}"#,
+            r#"{"op": "step", "actions": {"1": "vole"}}"#,
+        ]
+        .join("\n");
+        let mut output = Vec::new();
+
+        // When
+        env.serve(input.as_bytes(), &mut output).unwrap();
+
+        // Then
+        let lines: Vec<serde_json::Value> = String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines[0]["ok"]["seed"], 5);
+        assert_eq!(lines[1]["ok"]["responses"]["1"], serde_json::json!(["Ok"]));
+        assert_eq!(lines[1]["ok"]["frame"], 7);
+        assert_eq!(lines[2]["ok"]["level"], 1);
+        assert_eq!(lines[3]["error"], "Unknown command: \"vole\"");
+    }
+}
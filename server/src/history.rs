@@ -0,0 +1,417 @@
+use crate::game_engine::GameEngine;
+use chrono::{SecondsFormat, Utc};
+use rusqlite::{params, Connection};
+use std::error::Error;
+use std::path::Path;
+
+const DEFAULT_LIMIT: usize = 20;
+
+/// Final state of a team in a finished game.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TeamRecord {
+    pub name: String,
+    pub max_level: u8,
+    pub players_at_max_level: usize,
+    /// Players still alive at the end of the game.
+    pub survivors: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MatchRecord {
+    pub id: i64,
+    pub game: String,
+    /// UTC, RFC 3339.
+    pub started_at: String,
+    pub ended_at: String,
+    pub frames: u64,
+    /// `GameRules` as JSON.
+    pub rules: String,
+    pub seed: u64,
+    pub width: usize,
+    pub height: usize,
+    pub teams: Vec<TeamRecord>,
+    pub winner: Option<String>,
+    /// JSON report of the game, if it was written. It replaces a replay file, which the server
+    /// doesn't record: the game can be played again from `seed` and `rules`.
+    pub report: Option<String>,
+}
+
+impl MatchRecord {
+    /// Record of the game `engine` that started at `started_at`, ending now.
+    pub fn new(game: &str, started_at: String, engine: &GameEngine) -> Self {
+        let teams = engine
+            .team_scores()
+            .into_iter()
+            .map(|(name, score)| TeamRecord {
+                survivors: engine
+                    .players()
+                    .values()
+                    .filter(|player| *player.team() == name)
+                    .count(),
+                name,
+                max_level: score.max_level,
+                players_at_max_level: score.players_at_max_level,
+            })
+            .collect();
+        Self {
+            id: 0,
+            game: game.to_string(),
+            started_at,
+            ended_at: now(),
+            frames: *engine.frame(),
+            rules: serde_json::to_string(engine.rules()).unwrap_or_default(),
+            seed: *engine.seed(),
+            width: *engine.map().width(),
+            height: *engine.map().height(),
+            teams,
+            winner: engine.winner(),
+            report: None,
+        }
+    }
+}
+
+/// Current UTC time as stored in the history, dates can be compared as strings.
+pub fn now() -> String {
+    Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+/// Criteria of a history query, all optional.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MatchFilter {
+    pub game: Option<String>,
+    /// Matches this team played.
+    pub team: Option<String>,
+    pub winner: Option<String>,
+    /// Matches started at this date or later, `YYYY-MM-DD` or RFC 3339.
+    pub since: Option<String>,
+    pub limit: usize,
+}
+
+impl Default for MatchFilter {
+    fn default() -> Self {
+        Self {
+            game: None,
+            team: None,
+            winner: None,
+            since: None,
+            limit: DEFAULT_LIMIT,
+        }
+    }
+}
+
+impl TryFrom<&[String]> for MatchFilter {
+    type Error = String;
+
+    /// Parses `key=value` criteria, for example `team=anton limit=5`.
+    fn try_from(criteria: &[String]) -> Result<Self, String> {
+        let mut filter = MatchFilter::default();
+        for criterion in criteria {
+            match criterion.split_once('=') {
+                Some(("game", value)) => filter.game = Some(value.to_string()),
+                Some(("team", value)) => filter.team = Some(value.to_string()),
+                Some(("winner", value)) => filter.winner = Some(value.to_string()),
+                Some(("since", value)) => filter.since = Some(value.to_string()),
+                Some(("limit", value)) => {
+                    filter.limit = value
+                        .parse()
+                        .map_err(|_| format!("Invalid limit: \"{value}\""))?
+                }
+                _ => return Err(format!("Unknown filter: \"{criterion}\"")),
+            }
+        }
+        Ok(filter)
+    }
+}
+
+/// Games that reached an outcome, stored in an SQLite database. Games removed or interrupted
+/// before have no result to store.
+pub struct History {
+    connection: Connection,
+}
+
+impl History {
+    /// Opens the database at `path`, or a database in memory if not set.
+    pub fn open(path: Option<&Path>) -> Result<Self, Box<dyn Error>> {
+        let connection = match path {
+            Some(path) => Connection::open(path)?,
+            None => Connection::open_in_memory()?,
+        };
+        connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS matches (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                game TEXT NOT NULL,
+                started_at TEXT NOT NULL,
+                ended_at TEXT NOT NULL,
+                frames INTEGER NOT NULL,
+                rules TEXT NOT NULL,
+                seed TEXT NOT NULL,
+                width INTEGER NOT NULL,
+                height INTEGER NOT NULL,
+                winner TEXT,
+                report TEXT
+            );
+            CREATE TABLE IF NOT EXISTS match_teams (
+                match_id INTEGER NOT NULL REFERENCES matches(id),
+                team TEXT NOT NULL,
+                max_level INTEGER NOT NULL,
+                players_at_max_level INTEGER NOT NULL,
+                survivors INTEGER NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS match_teams_team ON match_teams(team);",
+        )?;
+        Ok(Self { connection })
+    }
+
+    /// Stores `record` and returns its id.
+    pub fn insert(&mut self, record: &MatchRecord) -> Result<i64, rusqlite::Error> {
+        let transaction = self.connection.transaction()?;
+        transaction.execute(
+            "INSERT INTO matches
+                (game, started_at, ended_at, frames, rules, seed, width, height, winner, report)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+            params![
+                record.game,
+                record.started_at,
+                record.ended_at,
+                record.frames,
+                record.rules,
+                // SQLite integers are signed
+                record.seed.to_string(),
+                record.width,
+                record.height,
+                record.winner,
+                record.report,
+            ],
+        )?;
+        let id = transaction.last_insert_rowid();
+        for team in &record.teams {
+            transaction.execute(
+                "INSERT INTO match_teams (match_id, team, max_level, players_at_max_level, survivors)
+                    VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![
+                    id,
+                    team.name,
+                    team.max_level,
+                    team.players_at_max_level,
+                    team.survivors
+                ],
+            )?;
+        }
+        transaction.commit()?;
+        Ok(id)
+    }
+
+    /// Matches fulfilling `filter`, the most recent first.
+    pub fn query(&self, filter: &MatchFilter) -> Result<Vec<MatchRecord>, rusqlite::Error> {
+        let mut statement = self.connection.prepare(
+            "SELECT id, game, started_at, ended_at, frames, rules, seed, width, height, winner, report
+                FROM matches
+                WHERE (?1 IS NULL OR game = ?1)
+                    AND (?2 IS NULL OR id IN (SELECT match_id FROM match_teams WHERE team = ?2))
+                    AND (?3 IS NULL OR winner = ?3)
+                    AND (?4 IS NULL OR started_at >= ?4)
+                ORDER BY id DESC
+                LIMIT ?5",
+        )?;
+        let mut records = statement
+            .query_map(
+                params![
+                    filter.game,
+                    filter.team,
+                    filter.winner,
+                    filter.since,
+                    filter.limit
+                ],
+                |row| {
+                    Ok(MatchRecord {
+                        id: row.get(0)?,
+                        game: row.get(1)?,
+                        started_at: row.get(2)?,
+                        ended_at: row.get(3)?,
+                        frames: row.get(4)?,
+                        rules: row.get(5)?,
+                        seed: row.get::<_, String>(6)?.parse().unwrap_or_default(),
+                        width: row.get(7)?,
+                        height: row.get(8)?,
+                        teams: Vec::new(),
+                        winner: row.get(9)?,
+                        report: row.get(10)?,
+                    })
+                },
+            )?
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut teams = self.connection.prepare(
+            "SELECT team, max_level, players_at_max_level, survivors
+                FROM match_teams WHERE match_id = ?1 ORDER BY team",
+        )?;
+        for record in &mut records {
+            record.teams = teams
+                .query_map([record.id], |row| {
+                    Ok(TeamRecord {
+                        name: row.get(0)?,
+                        max_level: row.get(1)?,
+                        players_at_max_level: row.get(2)?,
+                        survivors: row.get(3)?,
+                    })
+                })?
+                .collect::<Result<_, _>>()?;
+        }
+        Ok(records)
+    }
+}
+
+impl std::fmt::Display for MatchRecord {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let teams = self
+            .teams
+            .iter()
+            .map(|team| {
+                format!(
+                    "{} (level {} x{}, {} alive)",
+                    team.name, team.max_level, team.players_at_max_level, team.survivors
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(" vs ");
+        write!(
+            f,
+            "#{} {}: {} -> {}, {} frames, {}x{}, seed {}, {teams}, winner: {}",
+            self.id,
+            self.game,
+            self.started_at,
+            self.ended_at,
+            self.frames,
+            self.width,
+            self.height,
+            self.seed,
+            self.winner.as_deref().unwrap_or("none")
+        )?;
+        if let Some(report) = &self.report {
+            write!(f, ", report: {report}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod history_tests {
+    use super::*;
+    use crate::args::ServerArgs;
+    use clap::Parser;
+
+    fn record(game: &str, started_at: &str, teams: &[&str], winner: Option<&str>) -> MatchRecord {
+        MatchRecord {
+            id: 0,
+            game: game.to_string(),
+            started_at: started_at.to_string(),
+            ended_at: started_at.to_string(),
+            frames: 1000,
+            rules: "{}".to_string(),
+            seed: u64::MAX,
+            width: 10,
+            height: 8,
+            teams: teams
+                .iter()
+                .map(|name| TeamRecord {
+                    name: name.to_string(),
+                    max_level: 2,
+                    players_at_max_level: 1,
+                    survivors: 3,
+                })
+                .collect(),
+            winner: winner.map(str::to_string),
+            report: None,
+        }
+    }
+
+    fn filter(criteria: &[&str]) -> MatchFilter {
+        let criteria: Vec<String> = criteria.iter().map(|c| c.to_string()).collect();
+        MatchFilter::try_from(criteria.as_slice()).unwrap()
+    }
+
+    fn ids(history: &History, criteria: &[&str]) -> Vec<i64> {
+        history
+            .query(&filter(criteria))
+            .unwrap()
+            .iter()
+            .map(|record| record.id)
+            .collect()
+    }
+
+    #[test]
+    fn stores_and_filters_matches() {
+        // Given
+        let mut history = History::open(None).unwrap();
+        let first = record(
+            "ladder",
+            "2026-10-01T10:00:00Z",
+            &["anton", "axel"],
+            Some("axel"),
+        );
+        let second = record("ladder", "2026-10-08T10:00:00Z", &["anton", "victor"], None);
+        let third = record(
+            "test",
+            "2026-10-15T10:00:00Z",
+            &["axel", "victor"],
+            Some("axel"),
+        );
+
+        // When
+        for record in [&first, &second, &third] {
+            history.insert(record).unwrap();
+        }
+
+        // Then
+        assert_eq!(
+            history.query(&MatchFilter::default()).unwrap()[2],
+            MatchRecord { id: 1, ..first }
+        );
+        assert_eq!(ids(&history, &[]), vec![3, 2, 1]);
+        assert_eq!(ids(&history, &["team=anton"]), vec![2, 1]);
+        assert_eq!(ids(&history, &["winner=axel"]), vec![3, 1]);
+        assert_eq!(ids(&history, &["game=ladder", "since=2026-10-02"]), vec![2]);
+        assert_eq!(ids(&history, &["limit=1"]), vec![3]);
+    }
+
+    #[test]
+    fn records_final_state_of_game() {
+        // Given
+        let args = ServerArgs::try_parse_from([
+            "server", "-x", "5", "-y", "4", "-n", "anton", "axel", "--seed", "42",
+        ])
+        .unwrap();
+        let mut engine = GameEngine::new(&args);
+        engine.add_player(1, "axel".to_string()).unwrap();
+
+        // When
+        let record = MatchRecord::new("game", now(), &engine);
+
+        // Then
+        assert_eq!((record.seed, record.width, record.height), (42, 5, 4));
+        assert_eq!(
+            record.teams,
+            vec![
+                TeamRecord {
+                    name: "anton".to_string(),
+                    max_level: 0,
+                    players_at_max_level: 0,
+                    survivors: 0
+                },
+                TeamRecord {
+                    name: "axel".to_string(),
+                    max_level: 1,
+                    players_at_max_level: 1,
+                    survivors: 1
+                }
+            ]
+        );
+        assert_eq!(record.winner, Some("axel".to_string()));
+    }
+
+    #[test]
+    fn rejects_unknown_filters() {
+        for criterion in ["winner", "limit=many", "color=red"] {
+            assert!(MatchFilter::try_from([criterion.to_string()].as_slice()).is_err());
+        }
+    }
+}
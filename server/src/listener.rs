@@ -0,0 +1,306 @@
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs};
+use std::os::unix::fs::FileTypeExt;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::str::FromStr;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{TcpListener, TcpStream, UnixListener, UnixStream};
+
+/// Removes the socket file of a server that stopped, for a new one to bind the same path. Fails
+/// with `AddrInUse` if a server still accepts connections on it, other files are left alone.
+pub fn remove_stale_socket(path: &Path) -> io::Result<()> {
+    if std::fs::metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+        if std::os::unix::net::UnixStream::connect(path).is_ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("{} is used by a running server", path.display()),
+            ));
+        }
+        std::fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+/// Where a port of the server listens: `host:port`, `[ipv6]:port` or `unix:<path>`. A host name
+/// is resolved once, to its first address. Port 0 binds an ephemeral port.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ListenAddress {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+impl FromStr for ListenAddress {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s.strip_prefix("unix:") {
+            Some("") => Err("Missing socket path after \"unix:\"".to_string()),
+            Some(path) => Ok(ListenAddress::Unix(PathBuf::from(path))),
+            None => s
+                .to_socket_addrs()
+                .ok()
+                .and_then(|mut addrs| addrs.next())
+                .map(ListenAddress::Tcp)
+                .ok_or_else(|| {
+                    format!("Expected host:port, [ipv6]:port or unix:<path>, got \"{s}\"")
+                }),
+        }
+    }
+}
+
+impl Display for ListenAddress {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ListenAddress::Tcp(addr) => write!(f, "{addr}"),
+            ListenAddress::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+impl ListenAddress {
+    pub fn tcp(ip: [u8; 4], port: u16) -> Self {
+        ListenAddress::Tcp(SocketAddr::from((ip, port)))
+    }
+
+    /// Binds the address, replacing the stale socket file of a previous server. The socket of
+    /// a server still accepting connections is left alone.
+    pub async fn bind(&self) -> io::Result<Listener> {
+        match self {
+            ListenAddress::Tcp(addr) => Ok(Listener::Tcp(TcpListener::bind(addr).await?)),
+            ListenAddress::Unix(path) => {
+                remove_stale_socket(path)?;
+                Ok(Listener::Unix(UnixListener::bind(path)?))
+            }
+        }
+    }
+
+    /// The host and port a process of the same machine connects to, the loopback address for
+    /// the unspecified ones. A socket path and no port for Unix sockets.
+    pub fn local_target(&self) -> (String, String) {
+        match self {
+            ListenAddress::Tcp(addr) => {
+                let ip = match addr.ip() {
+                    IpAddr::V4(ip) if ip.is_unspecified() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+                    IpAddr::V6(ip) if ip.is_unspecified() => IpAddr::V6(Ipv6Addr::LOCALHOST),
+                    ip => ip,
+                };
+                (ip.to_string(), addr.port().to_string())
+            }
+            ListenAddress::Unix(path) => (path.display().to_string(), String::new()),
+        }
+    }
+}
+
+pub enum Listener {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+impl Listener {
+    /// The bound address, with the port chosen by the system for port 0.
+    pub fn local_address(&self) -> io::Result<ListenAddress> {
+        match self {
+            Listener::Tcp(listener) => Ok(ListenAddress::Tcp(listener.local_addr()?)),
+            Listener::Unix(listener) => Ok(ListenAddress::Unix(
+                listener
+                    .local_addr()?
+                    .as_pathname()
+                    .map(PathBuf::from)
+                    .unwrap_or_default(),
+            )),
+        }
+    }
+
+    pub async fn accept(&self) -> io::Result<(Stream, Peer)> {
+        match self {
+            Listener::Tcp(listener) => {
+                let (stream, addr) = listener.accept().await?;
+                Ok((Stream::Tcp(stream), Peer::Tcp(addr)))
+            }
+            Listener::Unix(listener) => {
+                let (stream, _) = listener.accept().await?;
+                Ok((Stream::Unix(stream), Peer::Unix))
+            }
+        }
+    }
+}
+
+/// The other end of an accepted connection.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Peer {
+    Tcp(SocketAddr),
+    Unix,
+}
+
+impl Peer {
+    /// The address of the peer, loopback for Unix sockets as they are local.
+    pub fn ip(&self) -> IpAddr {
+        match self {
+            Peer::Tcp(addr) => addr.ip(),
+            Peer::Unix => IpAddr::V4(Ipv4Addr::LOCALHOST),
+        }
+    }
+}
+
+impl Display for Peer {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Peer::Tcp(addr) => write!(f, "{addr}"),
+            Peer::Unix => write!(f, "unix socket"),
+        }
+    }
+}
+
+pub enum Stream {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+impl Stream {
+    /// Writes what fits in the socket buffer without waiting.
+    pub fn try_write(&self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.try_write(buf),
+            Stream::Unix(stream) => stream.try_write(buf),
+        }
+    }
+}
+
+impl AsyncRead for Stream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Stream::Tcp(stream) => Pin::new(stream).poll_read(cx, buf),
+            Stream::Unix(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for Stream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Stream::Tcp(stream) => Pin::new(stream).poll_write(cx, buf),
+            Stream::Unix(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Stream::Tcp(stream) => Pin::new(stream).poll_flush(cx),
+            Stream::Unix(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Stream::Tcp(stream) => Pin::new(stream).poll_shutdown(cx),
+            Stream::Unix(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
+
+#[cfg(test)]
+mod listener_tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[test]
+    fn parses_addresses() {
+        assert_eq!(
+            "0.0.0.0:4444".parse(),
+            Ok(ListenAddress::tcp([0, 0, 0, 0], 4444))
+        );
+        assert_eq!(
+            "[::]:0"
+                .parse::<ListenAddress>()
+                .map(|addr| addr.local_target()),
+            Ok(("::1".to_string(), "0".to_string()))
+        );
+        assert_eq!(
+            "unix:/tmp/zappy.sock".parse(),
+            Ok(ListenAddress::Unix(PathBuf::from("/tmp/zappy.sock")))
+        );
+        assert!("localhost:4444"
+            .parse::<ListenAddress>()
+            .is_ok_and(|addr| addr
+                .local_target()
+                .0
+                .parse::<IpAddr>()
+                .unwrap()
+                .is_loopback()));
+        assert!("localhost".parse::<ListenAddress>().is_err());
+        assert!("unix:".parse::<ListenAddress>().is_err());
+    }
+
+    #[tokio::test]
+    async fn binds_ephemeral_port_and_stale_unix_socket() {
+        // Given
+        let path = std::env::temp_dir().join(format!("zappy-{}.sock", std::process::id()));
+        let tcp = ListenAddress::tcp([127, 0, 0, 1], 0).bind().await.unwrap();
+        drop(ListenAddress::Unix(path.clone()).bind().await.unwrap());
+        let unix = ListenAddress::Unix(path.clone()).bind().await.unwrap();
+
+        // When
+        let ListenAddress::Tcp(addr) = tcp.local_address().unwrap() else {
+            panic!("TCP listener bound to a Unix socket");
+        };
+        let mut client = UnixStream::connect(&path).await.unwrap();
+        let (mut stream, peer) = unix.accept().await.unwrap();
+        client.write_all(b"voir\n").await.unwrap();
+        let mut line = [0; 5];
+        stream.read_exact(&mut line).await.unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        // Then
+        assert_ne!(addr.port(), 0);
+        assert_eq!(peer, Peer::Unix);
+        assert!(peer.ip().is_loopback());
+        assert_eq!(&line, b"voir\n");
+    }
+
+    #[tokio::test]
+    async fn keeps_socket_of_running_server() {
+        // Given
+        let path = std::env::temp_dir().join(format!("zappy-live-{}.sock", std::process::id()));
+        let running = ListenAddress::Unix(path.clone()).bind().await.unwrap();
+
+        // When
+        let second = ListenAddress::Unix(path.clone()).bind().await;
+        let client = UnixStream::connect(&path).await;
+        drop(running);
+        std::fs::remove_file(&path).unwrap();
+
+        // Then
+        assert_eq!(
+            second.err().map(|e| e.kind()),
+            Some(io::ErrorKind::AddrInUse)
+        );
+        assert!(client.is_ok());
+    }
+
+    #[test]
+    fn keeps_files_other_than_sockets() {
+        // Given
+        let path = std::env::temp_dir().join(format!("zappy-file-{}.sock", std::process::id()));
+        std::fs::write(&path, "data").unwrap();
+
+        // When
+        let removed = remove_stale_socket(&path);
+        let content = std::fs::read_to_string(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        // Then
+        assert!(removed.is_ok());
+        assert_eq!(content.unwrap(), "data");
+    }
+}
@@ -1,56 +1,127 @@
+mod archive;
 mod args;
+mod bot;
 mod connection;
+mod engine_handle;
 mod game_engine;
+mod game_manager;
+mod guard;
+mod gym;
+mod history;
+mod listener;
 mod logger;
+mod metrics;
+mod outbox;
+mod ratings;
+mod report;
 mod routine;
+mod rules;
+mod scenario;
 mod security;
+mod session;
+mod shutdown;
+mod tournament;
 
+use crate::archive::Archive;
 use crate::args::ServerArgs;
-use crate::game_engine::GameEngine;
+use crate::connection::ConnectionLimits;
+use crate::game_manager::GameManager;
+use crate::guard::{Guard, GuardLimits};
+use crate::history::History;
 use crate::logger::init_logger;
+use crate::ratings::Ratings;
 use crate::routine::admin::admin_routine;
 use crate::security::tls::setup_tls;
+use crate::shutdown::{interrupted, Shutdown};
 use clap::Parser;
 use routine::client::client_routine;
-use routine::game::game_routine;
 use routine::gfx::gfx_routine;
 use security::security_context::SecurityContext;
-use shared::{ServerCommandToClient, ADMIN_PORT, GFX_PORT};
-use std::collections::HashMap;
+use shared::DEFAULT_GAME;
 use std::error::Error;
 use std::sync::Arc;
-use tokio::net::TcpListener;
-use tokio::sync::mpsc::UnboundedSender;
+use std::time::Duration;
 use tokio::sync::Mutex;
+use tournament::tournament_routine;
 
-#[tokio::main(flavor = "current_thread")]
+#[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
     init_logger();
 
-    let args = ServerArgs::parse();
-    let server = GameEngine::new(&args);
-    let client_listener = TcpListener::bind(format!("127.0.0.1:{}", args.port)).await?;
-    let admin_listener = TcpListener::bind(format!("127.0.0.1:{}", ADMIN_PORT)).await?;
-    let gfx_listener = TcpListener::bind(format!("127.0.0.1:{}", GFX_PORT)).await?;
+    let mut args = ServerArgs::parse();
+    if let Some(mode) = &args.gym {
+        return Ok(gym::run(&args, mode)?);
+    }
+    let client_listener = args.client_address().bind().await?;
+    let admin_listener = args.admin_address().bind().await?;
+    let gfx_listener = args.gfx_address().bind().await?;
+    // The ports chosen by the system for port 0 replace it, for the bots of the tournaments.
+    args.listen = Some(client_listener.local_address()?);
+    args.admin_listen = Some(admin_listener.local_address()?);
+    args.gfx_listen = Some(gfx_listener.local_address()?);
     let security_context = Arc::new(Mutex::new(SecurityContext::from_env()?));
-    let player_senders: Arc<Mutex<HashMap<u16, UnboundedSender<ServerCommandToClient>>>> =
-        Arc::new(Mutex::new(HashMap::new()));
-    let server = Arc::new(Mutex::new(server));
+    let mut games = GameManager::new(Archive::new(
+        Ratings::load(args.ratings.clone())?,
+        History::open(args.history.as_deref())?,
+        args.report_dir.clone(),
+    ));
+    if args.tournament.is_none() {
+        games.create_game(DEFAULT_GAME.to_string(), &args)?;
+    }
+    let games = Arc::new(Mutex::new(games));
     let acceptor = setup_tls()?;
+    let limits = ConnectionLimits::new(&args);
+    let guard = Guard::load(GuardLimits::new(&args), args.bans.clone())?;
 
     log::info!(
-        "Server running on 127.0.0.1:{} (client), 127.0.0.1:{} (admin), 127.0.0.1:{} (gfx)",
-        args.port,
-        ADMIN_PORT,
-        GFX_PORT
+        "Server running on {} (client), {} (admin), {} (gfx)",
+        args.client_address(),
+        args.admin_address(),
+        args.gfx_address()
+    );
+    println!(
+        "{}",
+        serde_json::json!({
+            "listening": {
+                "client": args.client_address().to_string(),
+                "admin": args.admin_address().to_string(),
+                "gfx": args.gfx_address().to_string(),
+            }
+        })
     );
 
-    tokio::select! {
-        _ = client_routine(Arc::clone(&server), Arc::clone(&player_senders), client_listener) => {},
-        _ = admin_routine(Arc::clone(&server), Arc::clone(&player_senders), (admin_listener, acceptor), Arc::clone(&security_context)) => {},
-        _ = gfx_routine(Arc::clone(&server), gfx_listener) => {},
-        _ = game_routine(server, Arc::clone(&player_senders), args.tud) => {},
-    }
+    let shutdown = Shutdown::new();
+    let result: Result<(), Box<dyn Error>> = tokio::select! {
+        result = client_routine(Arc::clone(&games), (client_listener, args.tls.then(|| acceptor.clone())), limits, Duration::from_secs(args.resume_grace), guard.clone(), shutdown.signal()) => result,
+        result = admin_routine(Arc::clone(&games), (admin_listener, acceptor.clone()), Arc::clone(&security_context), limits, guard.clone(), shutdown.signal()) => result,
+        result = gfx_routine(Arc::clone(&games), (gfx_listener, args.gfx_tls.then(|| acceptor.clone())), limits, guard, shutdown.signal()) => result,
+        result = async {
+            match &args.tournament {
+                Some(path) => tournament_routine(Arc::clone(&games), &args, path).await,
+                None => std::future::pending().await,
+            }
+        } => result,
+        signal = interrupted() => {
+            log::info!("Server interrupted by {}", signal?);
+            Ok(())
+        },
+    };
 
-    Ok(())
+    // The listeners are closed, the games and the connections still open are stopped.
+    log::info!("Shutting down the server...");
+    if let Err(e) = games.lock().await.shutdown(args.snapshot.as_deref()).await {
+        log::error!("Failed to write the snapshot of the games: {e}");
+    }
+    let open = shutdown
+        .close_connections(Duration::from_secs(args.shutdown_timeout))
+        .await;
+    if open > 0 {
+        log::warn!(
+            "{open} connections still open after {}s, closing them",
+            args.shutdown_timeout
+        );
+    }
+    log::info!("Server stopped");
+    log::logger().flush();
+    result
 }
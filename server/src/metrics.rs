@@ -0,0 +1,324 @@
+use crate::game_engine::GameEngine;
+use serde::Serialize;
+use shared::resource::Resource;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{SyncSender, TrySendError};
+use tokio::task::JoinHandle;
+
+/// Samples waiting to be written to a metrics file.
+const METRICS_QUEUE: usize = 64;
+
+/// World metrics of a game at a given frame.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct MetricsSample {
+    pub frame: u64,
+    /// Resources lying on the map at the last sample of the engine, by name.
+    pub resources: BTreeMap<&'static str, usize>,
+    pub players_alive: BTreeMap<String, usize>,
+    /// 0 without players.
+    pub average_level: f64,
+    pub queued_commands: usize,
+    /// Laid eggs that haven't hatched yet.
+    pub pending_eggs: usize,
+}
+
+impl MetricsSample {
+    pub fn new(engine: &GameEngine) -> Self {
+        let resources = engine
+            .resource_samples()
+            .last()
+            .map(|sample| sample.totals)
+            .unwrap_or_default()
+            .into_iter()
+            .enumerate()
+            .map(|(i, total)| (Resource::try_from(i).unwrap().as_str(), total))
+            .collect();
+        let mut players_alive: BTreeMap<String, usize> = engine
+            .teams()
+            .keys()
+            .map(|team| (team.clone(), 0))
+            .collect();
+        for player in engine.players().values() {
+            *players_alive.entry(player.team().clone()).or_default() += 1;
+        }
+        let players = engine.players().len();
+        let average_level = if players == 0 {
+            0.
+        } else {
+            engine
+                .players()
+                .values()
+                .map(|player| *player.level() as f64)
+                .sum::<f64>()
+                / players as f64
+        };
+        Self {
+            frame: *engine.frame(),
+            resources,
+            players_alive,
+            average_level,
+            queued_commands: engine
+                .players()
+                .values()
+                .map(|player| player.commands().len())
+                .sum(),
+            pending_eggs: engine.eggs().values().map(Vec::len).sum(),
+        }
+    }
+
+    /// The sample of the current frame if the engine sampled its resources in it.
+    pub fn of_sampled_frame(engine: &GameEngine) -> Option<Self> {
+        let sampled = engine.resource_samples().last().map(|sample| sample.frame);
+        (sampled == Some(*engine.frame())).then(|| Self::new(engine))
+    }
+
+    fn csv_header(&self) -> String {
+        let mut columns = vec!["frame".to_string()];
+        columns.extend(self.resources.keys().map(|name| name.to_string()));
+        columns.extend(
+            self.players_alive
+                .keys()
+                .map(|team| format!("alive_{team}")),
+        );
+        columns.extend(["average_level", "queued_commands", "pending_eggs"].map(str::to_string));
+        columns.join(",")
+    }
+
+    fn csv_row(&self) -> String {
+        let mut values = vec![self.frame.to_string()];
+        values.extend(self.resources.values().map(usize::to_string));
+        values.extend(self.players_alive.values().map(usize::to_string));
+        values.push(format!("{:.3}", self.average_level));
+        values.push(self.queued_commands.to_string());
+        values.push(self.pending_eggs.to_string());
+        values.join(",")
+    }
+}
+
+/// The name of a game as a part of a file name: the characters other than ASCII letters, digits,
+/// `-`, `_` and `.` are replaced by `_`, so that a name like `../x` stays in the directory.
+pub fn file_name_of(game: &str) -> String {
+    game.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// Appends the metrics of a game to a CSV file if its extension is `.csv`, to a JSON lines file
+/// otherwise, on the frames the engine samples its resources.
+pub struct MetricsWriter {
+    file: File,
+    csv: bool,
+    header_written: bool,
+}
+
+impl MetricsWriter {
+    /// The file of the game `game` for the metrics file `path`: `metrics.csv` becomes
+    /// `metrics-<game>.csv`.
+    pub fn path(path: &Path, game: &str) -> PathBuf {
+        let game = file_name_of(game);
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        match path.extension() {
+            Some(extension) => {
+                path.with_file_name(format!("{stem}-{game}.{}", extension.to_string_lossy()))
+            }
+            None => path.with_file_name(format!("{stem}-{game}")),
+        }
+    }
+
+    pub fn create(path: &Path, game: &str) -> std::io::Result<Self> {
+        let path = Self::path(path, game);
+        let header_written = path.exists() && std::fs::metadata(&path)?.len() > 0;
+        Ok(Self {
+            file: File::options().create(true).append(true).open(&path)?,
+            csv: path.extension().is_some_and(|extension| extension == "csv"),
+            header_written,
+        })
+    }
+
+    /// Moves the writer to a blocking thread, fed with the samples of the game `game` through
+    /// the returned feed, for a slow disk not to hold the game.
+    pub fn spawn(mut self, game: String) -> MetricsFeed {
+        let (samples, received) = std::sync::mpsc::sync_channel::<MetricsSample>(METRICS_QUEUE);
+        let writer = tokio::task::spawn_blocking(move || {
+            for sample in received {
+                if let Err(e) = self.write(&sample) {
+                    log::error!("{game}: failed to write the metrics, stopping them: {e}");
+                    return;
+                }
+            }
+        });
+        MetricsFeed { samples, writer }
+    }
+
+    pub fn write(&mut self, sample: &MetricsSample) -> std::io::Result<()> {
+        let line = if self.csv {
+            if !self.header_written {
+                writeln!(self.file, "{}", sample.csv_header())?;
+                self.header_written = true;
+            }
+            sample.csv_row()
+        } else {
+            serde_json::to_string(sample)?
+        };
+        writeln!(self.file, "{line}")
+    }
+}
+
+/// The samples of a game on their way to its `MetricsWriter`.
+pub struct MetricsFeed {
+    samples: SyncSender<MetricsSample>,
+    writer: JoinHandle<()>,
+}
+
+impl MetricsFeed {
+    /// Queues the sample of the frame if the engine sampled its resources in it. A sample is
+    /// dropped if the writer is `METRICS_QUEUE` samples behind. Returns `false` once the writer
+    /// stopped on an error.
+    pub fn on_frame(&self, engine: &GameEngine) -> bool {
+        let Some(sample) = MetricsSample::of_sampled_frame(engine) else {
+            return true;
+        };
+        match self.samples.try_send(sample) {
+            Ok(()) => true,
+            Err(TrySendError::Full(sample)) => {
+                log::warn!(
+                    "Metrics of frame {} dropped, the file is written too slowly",
+                    sample.frame
+                );
+                true
+            }
+            Err(TrySendError::Disconnected(_)) => false,
+        }
+    }
+
+    /// Waits for the queued samples to be written.
+    pub async fn finish(self) {
+        drop(self.samples);
+        let _ = self.writer.await;
+    }
+}
+
+#[cfg(test)]
+mod metrics_tests {
+    use super::*;
+    use crate::args::ServerArgs;
+    use clap::Parser;
+    use shared::commands::PlayerCmd;
+
+    fn game_engine() -> GameEngine {
+        let args = ServerArgs::try_parse_from([
+            "server",
+            "-x",
+            "5",
+            "-y",
+            "4",
+            "-n",
+            "anton",
+            "axel",
+            "-c",
+            "2",
+            "--metrics-interval",
+            "2",
+        ])
+        .unwrap();
+        let mut engine = GameEngine::new(&args);
+        engine.add_player(1, "anton".to_string()).unwrap();
+        engine.add_player(2, "anton".to_string()).unwrap();
+        engine.take_command(&1, PlayerCmd::Fork).unwrap();
+        engine.take_command(&1, PlayerCmd::See).unwrap();
+        engine.take_command(&2, PlayerCmd::See).unwrap();
+        engine
+    }
+
+    #[test]
+    fn samples_world() {
+        // Given
+        let mut engine = game_engine();
+        let resources = engine.map().resource_totals();
+
+        // When
+        let before = MetricsSample::new(&engine);
+        engine.tick(&mut Vec::new());
+        let after = MetricsSample::new(&engine);
+
+        // Then
+        assert_eq!(
+            before.resources["nourriture"],
+            resources[Resource::Nourriture.index()]
+        );
+        assert_eq!(
+            before.players_alive,
+            BTreeMap::from([("anton".to_string(), 2), ("axel".to_string(), 0)])
+        );
+        assert_eq!(before.average_level, 1.);
+        assert_eq!((before.queued_commands, before.pending_eggs), (3, 0));
+        assert_eq!((after.queued_commands, after.pending_eggs), (1, 1));
+    }
+
+    #[tokio::test]
+    async fn appends_csv_rows_every_interval() {
+        // Given
+        let mut engine = game_engine();
+        let dir = std::env::temp_dir().join(format!("zappy-metrics-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("metrics.csv");
+
+        // When
+        let feed = MetricsWriter::create(&path, "scrim")
+            .unwrap()
+            .spawn("scrim".to_string());
+        for _ in 0..5 {
+            engine.tick(&mut Vec::new());
+            assert!(feed.on_frame(&engine));
+        }
+        feed.finish().await;
+        let reopened = MetricsWriter::create(&path, "scrim")
+            .unwrap()
+            .spawn("scrim".to_string());
+        engine.tick(&mut Vec::new());
+        assert!(reopened.on_frame(&engine));
+        reopened.finish().await;
+        let csv = std::fs::read_to_string(dir.join("metrics-scrim.csv")).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        // Then
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(
+            lines[0],
+            "frame,deraumere,linemate,mendiane,nourriture,phiras,sibur,thystame,alive_anton,\
+             alive_axel,average_level,queued_commands,pending_eggs"
+        );
+        assert_eq!(
+            lines[1..]
+                .iter()
+                .map(|line| line.split(',').next().unwrap())
+                .collect::<Vec<_>>(),
+            vec!["2", "4", "6"]
+        );
+    }
+
+    #[test]
+    fn names_file_after_game() {
+        assert_eq!(
+            MetricsWriter::path(Path::new("out/metrics.jsonl"), "scrim"),
+            PathBuf::from("out/metrics-scrim.jsonl")
+        );
+        assert_eq!(
+            MetricsWriter::path(Path::new("metrics"), "scrim"),
+            PathBuf::from("metrics-scrim")
+        );
+        assert_eq!(
+            MetricsWriter::path(Path::new("out/metrics.csv"), "../../x"),
+            PathBuf::from("out/metrics-.._.._x.csv")
+        );
+    }
+}
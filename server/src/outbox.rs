@@ -0,0 +1,131 @@
+use clap::ValueEnum;
+use shared::ServerCommandToClient;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TrySendError;
+
+/// What happens to a message for a client whose queue is full.
+#[derive(ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SlowClientPolicy {
+    /// The message is lost, the client stays connected.
+    Drop,
+    /// The client is disconnected.
+    #[default]
+    Disconnect,
+}
+
+/// The client of a full queue under the `Disconnect` policy, or already gone.
+#[derive(Debug, PartialEq)]
+pub struct Lagging;
+
+/// Bounded queue of the messages to a connected client, filled by the game without ever
+/// waiting for the client to read them.
+#[derive(Debug, Clone)]
+pub struct Outbox {
+    sender: mpsc::Sender<ServerCommandToClient>,
+    policy: SlowClientPolicy,
+    dropped: Arc<AtomicU64>,
+}
+
+/// Depth of a client queue, for the admins.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueueStatus {
+    pub queued: usize,
+    pub capacity: usize,
+    pub dropped: u64,
+}
+
+impl Outbox {
+    pub fn channel(
+        capacity: usize,
+        policy: SlowClientPolicy,
+    ) -> (Self, mpsc::Receiver<ServerCommandToClient>) {
+        let (sender, receiver) = mpsc::channel(capacity);
+        let outbox = Self {
+            sender,
+            policy,
+            dropped: Arc::new(AtomicU64::new(0)),
+        };
+        (outbox, receiver)
+    }
+
+    /// Queues a message if there is room. Otherwise drops it and returns `Err(Lagging)` if the
+    /// client must be disconnected, which its removal from the senders does. A dropped
+    /// `Shutdown` always disconnects.
+    pub fn send(&self, command: ServerCommandToClient) -> Result<(), Lagging> {
+        match self.sender.try_send(command) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(command)) => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                match (self.policy, command) {
+                    (_, ServerCommandToClient::Shutdown) => Err(Lagging),
+                    (SlowClientPolicy::Drop, _) => Ok(()),
+                    (SlowClientPolicy::Disconnect, _) => Err(Lagging),
+                }
+            }
+            Err(TrySendError::Closed(_)) => Err(Lagging),
+        }
+    }
+
+    pub fn status(&self) -> QueueStatus {
+        QueueStatus {
+            queued: self.sender.max_capacity() - self.sender.capacity(),
+            capacity: self.sender.max_capacity(),
+            dropped: self.dropped.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod outbox_tests {
+    use super::*;
+    use shared::ServerResponse;
+
+    fn message() -> ServerCommandToClient {
+        ServerCommandToClient::SendMessage(ServerResponse::Ok)
+    }
+
+    #[test]
+    fn drops_messages_of_full_queue() {
+        // Given
+        let (outbox, mut receiver) = Outbox::channel(2, SlowClientPolicy::Drop);
+
+        // When
+        let results: Vec<_> = (0..4).map(|_| outbox.send(message())).collect();
+        let full = outbox.status();
+        let shutdown = outbox.send(ServerCommandToClient::Shutdown);
+        receiver.try_recv().unwrap();
+
+        // Then
+        assert!(results.iter().all(Result::is_ok));
+        assert_eq!(shutdown, Err(Lagging));
+        assert_eq!(
+            full,
+            QueueStatus {
+                queued: 2,
+                capacity: 2,
+                dropped: 2
+            }
+        );
+        assert_eq!((outbox.status().queued, outbox.status().dropped), (1, 3));
+    }
+
+    #[test]
+    fn reports_lagging_client_to_disconnect() {
+        // Given
+        let (outbox, receiver) = Outbox::channel(1, SlowClientPolicy::Disconnect);
+
+        // When
+        let first = outbox.send(message());
+        let second = outbox.send(message());
+        drop(receiver);
+        let closed = outbox.send(message());
+
+        // Then
+        assert_eq!(first, Ok(()));
+        assert_eq!(second, Err(Lagging));
+        assert_eq!(closed, Err(Lagging));
+        assert_eq!(outbox.status().dropped, 1);
+    }
+}
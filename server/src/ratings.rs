@@ -0,0 +1,203 @@
+use crate::game_engine::TeamScore;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::path::PathBuf;
+
+pub const INITIAL_RATING: f64 = 1500.;
+/// Maximum rating change of a team for a single game.
+pub const K_FACTOR: f64 = 32.;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Rating {
+    pub rating: f64,
+    pub games: u32,
+    pub wins: u32,
+    pub draws: u32,
+    pub losses: u32,
+}
+
+impl Default for Rating {
+    fn default() -> Self {
+        Self {
+            rating: INITIAL_RATING,
+            games: 0,
+            wins: 0,
+            draws: 0,
+            losses: 0,
+        }
+    }
+}
+
+/// Elo ratings of the teams, by team name, kept across games and server restarts.
+#[derive(Debug, Default)]
+pub struct Ratings {
+    /// JSON file the ratings are loaded from and saved to, in memory only if not set.
+    path: Option<PathBuf>,
+    teams: BTreeMap<String, Rating>,
+}
+
+impl Ratings {
+    pub fn load(path: Option<PathBuf>) -> Result<Self, Box<dyn Error>> {
+        let teams = match &path {
+            Some(path) if path.exists() => serde_json::from_str(&std::fs::read_to_string(path)?)?,
+            _ => BTreeMap::new(),
+        };
+        Ok(Self { path, teams })
+    }
+
+    pub fn save(&self) -> Result<(), Box<dyn Error>> {
+        if let Some(path) = &self.path {
+            std::fs::write(path, serde_json::to_string_pretty(&self.teams)?)?;
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, team: &str) -> Option<&Rating> {
+        self.teams.get(team)
+    }
+
+    /// Teams from the best rated to the worst rated.
+    pub fn ladder(&self) -> Vec<(&String, &Rating)> {
+        let mut ladder: Vec<_> = self.teams.iter().collect();
+        ladder.sort_by(|(_, a), (_, b)| b.rating.total_cmp(&a.rating));
+        ladder
+    }
+
+    /// Updates the ratings with the result of a game, every team playing a match against each
+    /// other one: the winner beats everyone, the other teams are compared by their score.
+    /// Wins, draws and losses are counted against the winner of the game, a draw meaning that
+    /// there is none. Games with less than two teams aren't rated.
+    pub fn record(&mut self, scores: &BTreeMap<String, TeamScore>, winner: Option<&str>) {
+        if scores.len() < 2 {
+            return;
+        }
+        let k = K_FACTOR / (scores.len() - 1) as f64;
+        let before: BTreeMap<&String, f64> = scores
+            .keys()
+            .map(|team| (team, self.get(team).map_or(INITIAL_RATING, |r| r.rating)))
+            .collect();
+        for (team, score) in scores {
+            let delta: f64 = scores
+                .iter()
+                .filter(|(opponent, _)| *opponent != team)
+                .map(|(opponent, opponent_score)| {
+                    let actual = match winner {
+                        Some(winner) if winner == team => 1.,
+                        Some(winner) if winner == opponent => 0.,
+                        _ => match score.cmp(opponent_score) {
+                            Ordering::Greater => 1.,
+                            Ordering::Equal => 0.5,
+                            Ordering::Less => 0.,
+                        },
+                    };
+                    k * (actual - expected_score(before[team], before[opponent]))
+                })
+                .sum();
+            let rating = self.teams.entry(team.clone()).or_default();
+            rating.rating += delta;
+            rating.games += 1;
+            match winner {
+                Some(winner) if winner == team => rating.wins += 1,
+                Some(_) => rating.losses += 1,
+                None => rating.draws += 1,
+            }
+        }
+        if let Err(e) = self.save() {
+            log::error!("Failed to save the ratings: {e}");
+        }
+    }
+}
+
+/// Probability that a team rated `rating` beats a team rated `opponent`.
+fn expected_score(rating: f64, opponent: f64) -> f64 {
+    1. / (1. + 10f64.powf((opponent - rating) / 400.))
+}
+
+#[cfg(test)]
+mod ratings_tests {
+    use super::*;
+
+    fn scores(levels: &[(&str, u8)]) -> BTreeMap<String, TeamScore> {
+        levels
+            .iter()
+            .map(|(team, level)| {
+                (
+                    team.to_string(),
+                    TeamScore {
+                        max_level: *level,
+                        players_at_max_level: 1,
+                    },
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn winner_takes_points_from_loser() {
+        // Given
+        let mut ratings = Ratings::default();
+
+        // When
+        ratings.record(&scores(&[("anton", 2), ("axel", 5)]), Some("axel"));
+
+        // Then
+        let (anton, axel) = (ratings.get("anton").unwrap(), ratings.get("axel").unwrap());
+        assert_eq!(axel.rating, INITIAL_RATING + K_FACTOR / 2.);
+        assert_eq!(anton.rating, INITIAL_RATING - K_FACTOR / 2.);
+        assert_eq!((axel.games, axel.wins, axel.losses), (1, 1, 0));
+        assert_eq!((anton.games, anton.wins, anton.losses), (1, 0, 1));
+        assert_eq!(
+            ratings
+                .ladder()
+                .iter()
+                .map(|(team, _)| team.as_str())
+                .collect::<Vec<_>>(),
+            vec!["axel", "anton"]
+        );
+    }
+
+    #[test]
+    fn draw_moves_ratings_toward_each_other() {
+        // Given
+        let mut ratings = Ratings::default();
+        ratings.record(&scores(&[("anton", 2), ("axel", 5)]), Some("axel"));
+
+        // When
+        ratings.record(&scores(&[("anton", 3), ("axel", 3)]), None);
+
+        // Then
+        let (anton, axel) = (ratings.get("anton").unwrap(), ratings.get("axel").unwrap());
+        assert!(axel.rating < INITIAL_RATING + K_FACTOR / 2.);
+        assert!(anton.rating > INITIAL_RATING - K_FACTOR / 2.);
+        assert_eq!(anton.rating + axel.rating, 2. * INITIAL_RATING);
+        assert_eq!(axel.draws, 1);
+    }
+
+    #[test]
+    fn ignores_single_team_games() {
+        let mut ratings = Ratings::default();
+
+        ratings.record(&scores(&[("anton", 8)]), Some("anton"));
+
+        assert!(ratings.ladder().is_empty());
+    }
+
+    #[test]
+    fn persists_ratings() {
+        // Given
+        let path = std::env::temp_dir().join(format!("zappy-ratings-{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut ratings = Ratings::load(Some(path.clone())).unwrap();
+
+        // When
+        ratings.record(&scores(&[("anton", 2), ("axel", 1), ("victor", 1)]), None);
+        let reloaded = Ratings::load(Some(path.clone())).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        // Then
+        assert_eq!(reloaded.teams, ratings.teams);
+        assert!(reloaded.get("anton").unwrap().rating > INITIAL_RATING);
+    }
+}
@@ -0,0 +1,340 @@
+use crate::game_engine::{GameEngine, GameOutcome, LevelUp, ResourceSample};
+use crate::history;
+use crate::metrics;
+use serde::Serialize;
+use shared::resource::Resource;
+use shared::stats::{PlayerStats, TeamStats};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+
+/// Number of players listed as the longest survivors.
+const LONGEST_SURVIVORS: usize = 5;
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Standing {
+    pub team: String,
+    pub max_level: u8,
+    pub players_at_max_level: usize,
+    pub survivors: usize,
+    pub stats: TeamStats,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Survivor {
+    pub id: u16,
+    pub team: String,
+    pub frames_alive: u64,
+    pub alive: bool,
+}
+
+/// Summary of a game, written when it ends or when the server stops.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct GameReport {
+    pub game: String,
+    pub started_at: String,
+    pub ended_at: String,
+    pub frames: u64,
+    pub seed: u64,
+    /// `None` if the game was stopped before its end.
+    pub outcome: Option<GameOutcome>,
+    pub winner: Option<String>,
+    /// Best team first.
+    pub standings: Vec<Standing>,
+    pub level_ups: BTreeMap<String, Vec<LevelUp>>,
+    pub resources: Vec<ResourceSample>,
+    /// In order of death.
+    pub deaths: Vec<PlayerStats>,
+    pub longest_survivors: Vec<Survivor>,
+}
+
+impl GameReport {
+    pub fn new(game: &str, started_at: &str, engine: &GameEngine) -> Self {
+        let players = engine.player_stats();
+        let mut stats = engine.team_stats();
+        let mut standings: Vec<Standing> = engine
+            .team_scores()
+            .into_iter()
+            .map(|(team, score)| Standing {
+                survivors: players
+                    .iter()
+                    .filter(|player| player.team == team && player.death.is_none())
+                    .count(),
+                stats: stats.remove(&team).unwrap_or_default(),
+                team,
+                max_level: score.max_level,
+                players_at_max_level: score.players_at_max_level,
+            })
+            .collect();
+        let winner = engine.winner();
+        standings.sort_by_key(|standing| {
+            (
+                Some(&standing.team) != winner.as_ref(),
+                std::cmp::Reverse((standing.max_level, standing.players_at_max_level)),
+            )
+        });
+
+        let mut level_ups: BTreeMap<String, Vec<LevelUp>> = engine
+            .teams()
+            .keys()
+            .map(|team| (team.clone(), Vec::new()))
+            .collect();
+        for level_up in engine.level_ups() {
+            level_ups
+                .entry(level_up.team.clone())
+                .or_default()
+                .push(level_up.clone());
+        }
+
+        let mut resources = engine.resource_samples().clone();
+        if resources.last().map(|sample| sample.frame) != Some(*engine.frame()) {
+            resources.push(ResourceSample {
+                frame: *engine.frame(),
+                totals: engine.map().resource_totals(),
+            });
+        }
+
+        let mut longest_survivors: Vec<Survivor> = players
+            .iter()
+            .map(|player| Survivor {
+                id: player.id,
+                team: player.team.clone(),
+                frames_alive: player.stats.frames_at_level.iter().sum(),
+                alive: player.death.is_none(),
+            })
+            .collect();
+        longest_survivors.sort_by_key(|survivor| std::cmp::Reverse(survivor.frames_alive));
+        longest_survivors.truncate(LONGEST_SURVIVORS);
+
+        let mut deaths: Vec<PlayerStats> = players
+            .into_iter()
+            .filter(|player| player.death.is_some())
+            .collect();
+        deaths.sort_by_key(|player| player.death.as_ref().map(|death| death.frame));
+
+        Self {
+            game: game.to_string(),
+            started_at: started_at.to_string(),
+            ended_at: history::now(),
+            frames: *engine.frame(),
+            seed: *engine.seed(),
+            outcome: engine.outcome().clone(),
+            winner,
+            standings,
+            level_ups,
+            resources,
+            deaths,
+            longest_survivors,
+        }
+    }
+
+    pub fn to_markdown(&self) -> String {
+        let mut md = String::new();
+        let outcome = match &self.outcome {
+            Some(GameOutcome::Victory(team)) => format!("victory of {team}"),
+            Some(GameOutcome::TimeLimit) => "time limit".to_string(),
+            None => "stopped".to_string(),
+        };
+        let _ = writeln!(md, "# Game {}\n", self.game);
+        let _ = writeln!(md, "- started: {}", self.started_at);
+        let _ = writeln!(md, "- ended: {} ({outcome})", self.ended_at);
+        let _ = writeln!(md, "- frames: {}", self.frames);
+        let _ = writeln!(md, "- seed: {}", self.seed);
+        let _ = writeln!(md, "- winner: {}", self.winner.as_deref().unwrap_or("none"));
+
+        let _ = writeln!(md, "\n## Standings\n");
+        let _ = writeln!(
+            md,
+            "| Rank | Team | Level | Players at level | Survivors | Deaths | Moved | Broadcasts | Eggs |"
+        );
+        let _ = writeln!(md, "|---|---|---|---|---|---|---|---|---|");
+        for (rank, standing) in self.standings.iter().enumerate() {
+            let _ = writeln!(
+                md,
+                "| {} | {} | {} | {} | {} | {} | {} | {} | {} |",
+                rank + 1,
+                standing.team,
+                standing.max_level,
+                standing.players_at_max_level,
+                standing.survivors,
+                standing.stats.deaths,
+                standing.stats.stats.distance_moved,
+                standing.stats.stats.broadcasts,
+                standing.stats.stats.eggs_laid
+            );
+        }
+
+        let _ = writeln!(md, "\n## Level-ups\n");
+        for (team, level_ups) in &self.level_ups {
+            let timeline = level_ups
+                .iter()
+                .map(|level_up| {
+                    format!(
+                        "frame {}: player {} to level {}",
+                        level_up.frame, level_up.player, level_up.level
+                    )
+                })
+                .collect::<Vec<_>>();
+            let _ = writeln!(
+                md,
+                "- {team}: {}",
+                if timeline.is_empty() {
+                    "none".to_string()
+                } else {
+                    timeline.join(", ")
+                }
+            );
+        }
+
+        let _ = writeln!(md, "\n## Resources on the map\n");
+        let header = (0..Resource::SIZE)
+            .map(|i| Resource::try_from(i).unwrap().as_str())
+            .collect::<Vec<_>>();
+        let _ = writeln!(md, "| Frame | {} |", header.join(" | "));
+        let _ = writeln!(md, "|---|{}", "---|".repeat(Resource::SIZE));
+        for sample in &self.resources {
+            let totals = sample.totals.map(|total| total.to_string());
+            let _ = writeln!(md, "| {} | {} |", sample.frame, totals.join(" | "));
+        }
+
+        let _ = writeln!(md, "\n## Deaths\n");
+        if self.deaths.is_empty() {
+            let _ = writeln!(md, "None");
+        }
+        for player in &self.deaths {
+            if let Some(death) = &player.death {
+                let _ = writeln!(
+                    md,
+                    "- frame {}: player {} ({}, level {}), {:?}",
+                    death.frame, player.id, player.team, player.level, death.cause
+                );
+            }
+        }
+
+        let _ = writeln!(md, "\n## Longest survivors\n");
+        for survivor in &self.longest_survivors {
+            let _ = writeln!(
+                md,
+                "- player {} ({}): {} frames{}",
+                survivor.id,
+                survivor.team,
+                survivor.frames_alive,
+                if survivor.alive { ", alive" } else { "" }
+            );
+        }
+        md
+    }
+
+    /// Writes `<game>-<end time>.json` and `.md` in `dir`, returns the path of the JSON one.
+    pub fn write(&self, dir: &Path) -> std::io::Result<PathBuf> {
+        std::fs::create_dir_all(dir)?;
+        let stem = format!(
+            "{}-{}",
+            metrics::file_name_of(&self.game),
+            self.ended_at.replace(':', "")
+        );
+        let json = dir.join(format!("{stem}.json"));
+        std::fs::write(&json, serde_json::to_string_pretty(self)?)?;
+        std::fs::write(dir.join(format!("{stem}.md")), self.to_markdown())?;
+        Ok(json)
+    }
+}
+
+#[cfg(test)]
+mod report_tests {
+    use super::*;
+    use crate::args::ServerArgs;
+    use clap::Parser;
+    use shared::stats::DeathCause;
+
+    fn game_engine() -> GameEngine {
+        let args = ServerArgs::try_parse_from([
+            "server", "-x", "5", "-y", "4", "-n", "anton", "axel", "-c", "2", "--seed", "7",
+        ])
+        .unwrap();
+        let mut engine = GameEngine::new(&args);
+        engine.add_player(1, "anton".to_string()).unwrap();
+        engine.add_player(2, "axel".to_string()).unwrap();
+        engine.add_player(3, "axel".to_string()).unwrap();
+        engine
+    }
+
+    #[test]
+    fn summarizes_game() {
+        // Given
+        let mut engine = game_engine();
+        engine.remove_player(3);
+        for _ in 0..150 {
+            engine.tick(&mut Vec::new());
+        }
+
+        // When
+        let report = GameReport::new("scrim", "2026-10-19T10:00:00Z", &engine);
+
+        // Then
+        assert_eq!((report.frames, report.seed), (150, 7));
+        assert_eq!(report.winner, None);
+        assert_eq!(
+            report
+                .standings
+                .iter()
+                .map(|standing| (
+                    standing.team.as_str(),
+                    standing.survivors,
+                    standing.stats.deaths
+                ))
+                .collect::<Vec<_>>(),
+            vec![("anton", 1, 0), ("axel", 1, 1)]
+        );
+        assert_eq!(report.level_ups.get("anton"), Some(&Vec::new()));
+        assert_eq!(
+            report
+                .resources
+                .iter()
+                .map(|sample| sample.frame)
+                .collect::<Vec<_>>(),
+            vec![0, 100, 150]
+        );
+        assert_eq!(report.deaths.len(), 1);
+        assert_eq!(
+            report.deaths[0].death.as_ref().map(|death| death.cause),
+            Some(DeathCause::Disconnection)
+        );
+        assert_eq!(
+            report
+                .longest_survivors
+                .iter()
+                .map(|survivor| (survivor.id, survivor.frames_alive, survivor.alive))
+                .collect::<Vec<_>>(),
+            vec![(1, 150, true), (2, 150, true), (3, 0, false)]
+        );
+    }
+
+    #[test]
+    fn writes_json_and_markdown() {
+        // Given
+        let report = GameReport::new("scrim", "2026-10-19T10:00:00Z", &game_engine());
+        let dir = std::env::temp_dir().join(format!("zappy-reports-{}", std::process::id()));
+
+        // When
+        let json = report.write(&dir).unwrap();
+        let markdown = std::fs::read_to_string(json.with_extension("md")).unwrap();
+        let written: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&json).unwrap()).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        // Then
+        assert_eq!(written["game"], "scrim");
+        assert_eq!(written["standings"].as_array().map(Vec::len), Some(2));
+        for section in [
+            "# Game scrim",
+            "## Standings",
+            "## Level-ups",
+            "## Resources on the map",
+            "## Deaths",
+            "## Longest survivors",
+        ] {
+            assert!(markdown.contains(section), "missing {section}");
+        }
+    }
+}
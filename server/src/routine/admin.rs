@@ -1,43 +1,76 @@
-use crate::connection::{AsyncReadWrite, Connection};
-use crate::game_engine::GameEngine;
+use crate::args::ServerArgs;
+use crate::connection::{AsyncReadWrite, Connection, ConnectionLimits};
+use crate::game_manager::GameManager;
+use crate::guard::{Guard, Permit};
+use crate::history::MatchFilter;
+use crate::listener::Listener;
+use crate::scenario::Scenario;
 use crate::security::security_context::SecurityContext;
+use crate::shutdown::ShutdownSignal;
+use clap::Parser;
 use shared::commands::AdminCommand;
-use shared::{PlayerError, ServerCommandToClient, ZappyError};
-use std::collections::HashMap;
+use shared::{GameError, NetworkError, PlayerError, ZappyError};
 use std::error::Error;
+use std::path::Path;
 use std::pin::Pin;
 use std::sync::Arc;
-use tokio::net::TcpListener;
-use tokio::sync::mpsc::UnboundedSender;
+use std::time::Duration;
 use tokio::sync::Mutex;
 use tokio_rustls::TlsAcceptor;
 
 pub async fn admin_routine(
-    server: Arc<Mutex<GameEngine>>,
-    player_senders: Arc<Mutex<HashMap<u16, UnboundedSender<ServerCommandToClient>>>>,
-    (listener, acceptor): (TcpListener, TlsAcceptor),
+    games: Arc<Mutex<GameManager>>,
+    (listener, acceptor): (Listener, TlsAcceptor),
     security_context: Arc<Mutex<SecurityContext>>,
+    limits: ConnectionLimits,
+    guard: Guard,
+    shutdown: ShutdownSignal,
 ) -> Result<(), Box<dyn Error>> {
+    let ids = games.lock().await.ids().clone();
     loop {
         let (socket, addr) = listener.accept().await?;
+        let mut permit = match guard.admit(addr.ip()) {
+            Ok(permit) => permit,
+            Err(refusal) => {
+                log::warn!("Refused an admin connection from {addr}: {refusal}");
+                continue;
+            }
+        };
         let acceptor = acceptor.clone();
-        let id = addr.port();
-        log::info!("New connection, assigned id: {}", id);
+        let Some(id) = ids.allocate() else {
+            log::warn!("No id left for {addr}, closing the connection");
+            continue;
+        };
+        log::info!("New connection from {addr}, assigned id: {id}");
 
-        let server_clone = Arc::clone(&server);
-        let client_senders_clone = Arc::clone(&player_senders);
+        let games = Arc::clone(&games);
         let security_context = Arc::clone(&security_context);
+        let ids = ids.clone();
+        let guard = guard.clone();
+        let mut shutdown = shutdown.clone();
+        let mut banned = permit.ban_signal();
 
         tokio::spawn(async move {
-            match acceptor.accept(socket).await {
-                Ok(tls_stream) => {
+            match tokio::time::timeout(limits.tls_timeout, acceptor.accept(socket)).await {
+                Err(_) => {
+                    log::info!("TLS negotiation of {id} timed out, closing the connection");
+                }
+                Ok(Ok(tls_stream)) => {
                     let stream: Pin<Box<dyn AsyncReadWrite + Send>> = Box::pin(tls_stream);
-                    let mut client = Connection::new(stream, id);
-                    let handle_result: Result<(), ZappyError> = async {
+                    let mut client = Connection::new(stream, id, limits.max_line_length);
+                    let handling = async {
                         client.writeln("Username:").await?;
-                        let username = client.read().await?.trim_end().to_string();
+                        let username = client
+                            .read_within(limits.handshake_timeout, "the username")
+                            .await?
+                            .trim_end()
+                            .to_string();
                         client.writeln("Password:").await?;
-                        let password = client.read().await?.trim_end().to_string();
+                        let password = client
+                            .read_within(limits.handshake_timeout, "the password")
+                            .await?
+                            .trim_end()
+                            .to_string();
                         {
                             if !security_context.lock().await.is_valid(&username, &password) {
                                 return Err(ZappyError::Player(
@@ -46,14 +79,36 @@ pub async fn admin_routine(
                             }
                         }
                         client.writeln("Hi admin!").await?;
-                        return handle_admin(server_clone, &mut client, client_senders_clone).await;
+                        return handle_admin(
+                            games,
+                            &mut client,
+                            limits.idle_timeout,
+                            &guard,
+                            &mut permit,
+                        )
+                        .await;
+                    };
+                    let handle_result: Result<(), ZappyError> = tokio::select! {
+                        biased;
+                        result = handling => Ok(result),
+                        _ = shutdown.requested() => Err(NetworkError::ShuttingDown(id)),
+                        _ = banned.banned() => Err(NetworkError::Banned(id)),
                     }
-                    .await;
+                    .unwrap_or_else(|err| Err(ZappyError::Network(err)));
 
                     //Specific client loop ends here, cleanup before quiting async task
                     log::debug!("Admin: {} has been deleted by server", id);
                     if let Err(err) = handle_result {
                         match err {
+                            ZappyError::Network(
+                                err @ (NetworkError::TimedOut(..)
+                                | NetworkError::Flooding(_)
+                                | NetworkError::Banned(_)
+                                | NetworkError::ShuttingDown(_)),
+                            ) => {
+                                let _ = client.writeln(&err.to_string()).await;
+                                log::info!("{err}");
+                            }
                             ZappyError::Network(err) => log::error!("{err}"),
                             ZappyError::Game(err) => log::error!("{err}"),
                             ZappyError::Player(err) => {
@@ -66,24 +121,49 @@ pub async fn admin_routine(
                     }
                     let _ = client.writeln("Disconnected").await;
                 }
-                Err(e) => {
+                Ok(Err(e)) => {
                     log::error!("TLS handshake failed for {}: {}", id, e);
                 }
             };
+            ids.release(id);
         });
     }
 }
 
 async fn handle_admin(
-    _server: Arc<Mutex<GameEngine>>,
+    games: Arc<Mutex<GameManager>>,
     client: &mut Connection,
-    _player_senders: Arc<Mutex<HashMap<u16, UnboundedSender<ServerCommandToClient>>>>,
+    idle_timeout: Option<Duration>,
+    guard: &Guard,
+    permit: &mut Permit,
 ) -> Result<(), ZappyError> {
     loop {
-        let msg = client.read().await?;
+        let read = match idle_timeout {
+            Some(timeout) => client.read_within(timeout, "a command").await,
+            None => client.read().await,
+        };
+        let msg = match read {
+            Err(ZappyError::Network(err @ NetworkError::MessageIsTooBig(..))) => {
+                log::warn!("{err}");
+                client.writeln(&err.to_string()).await?;
+                continue;
+            }
+            result => result?,
+        };
+        if !permit.allow_command() {
+            return Err(ZappyError::Network(NetworkError::Flooding(client.id())));
+        }
         let trimmed = msg.trim_end();
-        match AdminCommand::try_from(trimmed) {
-            Ok(command) => command.show_off(),
+        let result = match AdminCommand::try_from(trimmed) {
+            Ok(command) => execute_admin_command(&games, guard, command).await,
+            Err(err) => Err(err),
+        };
+        match result {
+            Ok(lines) => {
+                for line in lines {
+                    client.writeln(&line).await?;
+                }
+            }
             Err(err) => {
                 log::error!("{}: {}", client.id(), err);
                 client.writeln(&err).await?;
@@ -91,3 +171,163 @@ async fn handle_admin(
         }
     }
 }
+
+async fn execute_admin_command(
+    games: &Mutex<GameManager>,
+    guard: &Guard,
+    command: AdminCommand,
+) -> Result<Vec<String>, String> {
+    match command {
+        AdminCommand::ShowOff => {
+            command.show_off();
+            Ok(vec![])
+        }
+        AdminCommand::CreateGame(name, args) => {
+            let args =
+                ServerArgs::try_parse_from(std::iter::once("create".to_string()).chain(args))
+                    .map_err(|e| {
+                        ZappyError::Game(GameError::InvalidGameArguments(e.to_string())).to_string()
+                    })?;
+            games
+                .lock()
+                .await
+                .create_game(name.clone(), &args)
+                .map_err(|e| e.to_string())?;
+            Ok(vec![format!("Game \"{name}\" created")])
+        }
+        AdminCommand::RemoveGame(name) => {
+            games
+                .lock()
+                .await
+                .remove_game(&name)
+                .await
+                .map_err(|e| e.to_string())?;
+            Ok(vec![format!("Game \"{name}\" removed")])
+        }
+        AdminCommand::ListGames => {
+            let games = games.lock().await;
+            let mut lines = Vec::new();
+            for (name, game) in games.games() {
+                let name = name.clone();
+                let line = game.engine.call(move |engine| {
+                    format!(
+                        "{name}: {}x{}, frame {}, {} players, teams: {}",
+                        engine.map_width(),
+                        engine.map_height(),
+                        engine.frame(),
+                        engine.players().len(),
+                        engine
+                            .teams()
+                            .keys()
+                            .cloned()
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    )
+                });
+                lines.push(line.await.map_err(|e| e.to_string())?);
+            }
+            Ok(lines)
+        }
+        AdminCommand::Ratings(team) => {
+            let games = games.lock().await;
+            let ratings = games.archive().ratings().lock().await;
+            let ladder = ratings.ladder();
+            let lines: Vec<String> = ladder
+                .iter()
+                .enumerate()
+                .filter(|(_, (name, _))| team.as_ref().is_none_or(|team| team == *name))
+                .map(|(i, (name, rating))| {
+                    format!(
+                        "{}. {name}: {:.0} ({} games, {}W {}D {}L)",
+                        i + 1,
+                        rating.rating,
+                        rating.games,
+                        rating.wins,
+                        rating.draws,
+                        rating.losses
+                    )
+                })
+                .collect();
+            match team {
+                Some(team) if lines.is_empty() => Err(format!("No rating for team \"{team}\"")),
+                _ => Ok(lines),
+            }
+        }
+        AdminCommand::History(criteria) => {
+            let filter = MatchFilter::try_from(criteria.as_slice())?;
+            let games = games.lock().await;
+            let records = games
+                .archive()
+                .history()
+                .lock()
+                .await
+                .query(&filter)
+                .map_err(|e| e.to_string())?;
+            Ok(records.iter().map(ToString::to_string).collect())
+        }
+        AdminCommand::Stats(name) => {
+            let game = games.lock().await.get(&name).map_err(|e| e.to_string())?;
+            game.engine
+                .call(|engine| engine.stats_report())
+                .await
+                .map_err(|e| e.to_string())
+        }
+        AdminCommand::ExportMap(name) => {
+            let game = games.lock().await.get(&name).map_err(|e| e.to_string())?;
+            let text = game
+                .engine
+                .call(|engine| engine.map().to_text())
+                .await
+                .map_err(|e| e.to_string())?;
+            Ok(text.lines().map(str::to_string).collect())
+        }
+        AdminCommand::Queues(name) => {
+            let game = games.lock().await.get(&name).map_err(|e| e.to_string())?;
+            let senders = game.player_senders.lock().await;
+            let mut ids: Vec<&u16> = senders.keys().collect();
+            ids.sort();
+            Ok(ids
+                .into_iter()
+                .map(|id| {
+                    let status = senders[id].status();
+                    format!(
+                        "player {id}: {}/{} queued, {} dropped",
+                        status.queued, status.capacity, status.dropped
+                    )
+                })
+                .collect())
+        }
+        AdminCommand::Bans => Ok(guard
+            .bans()
+            .iter()
+            .map(|(ip, ban)| format!("{ip}: {ban}"))
+            .collect()),
+        AdminCommand::Ban(ip, seconds) => {
+            let duration = seconds.map_or(guard.ban_duration(), Duration::from_secs);
+            let ban = guard.ban(ip, duration, "banned by an admin");
+            Ok(vec![format!("{ip}: {ban}")])
+        }
+        AdminCommand::Unban(ip) => Ok(vec![if guard.unban(ip) {
+            format!("{ip} unbanned")
+        } else {
+            format!("{ip} isn't banned")
+        }]),
+        AdminCommand::Scenario(path) => {
+            let file = path.clone();
+            let mismatches =
+                tokio::task::spawn_blocking(move || Scenario::load(Path::new(&file))?.run())
+                    .await
+                    .map_err(|e| e.to_string())??;
+            if mismatches.is_empty() {
+                Ok(vec![format!("Scenario {path} passed")])
+            } else {
+                Ok(std::iter::once(format!(
+                    "Scenario {path} failed with {} mismatches:",
+                    mismatches.len()
+                ))
+                .chain(mismatches)
+                .collect())
+            }
+        }
+    }
+}
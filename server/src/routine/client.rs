@@ -1,58 +1,161 @@
-use crate::connection::{AsyncReadWrite, Connection};
-use crate::game_engine::GameEngine;
-use shared::{commands::PlayerCmd, ServerCommandToClient, ZappyError};
-use std::collections::HashMap;
+use crate::connection::{Connection, ConnectionLimits};
+use crate::engine_handle::EngineHandle;
+use crate::game_manager::{Game, GameManager};
+use crate::guard::{Guard, Permit};
+use crate::listener::Listener;
+use crate::security::tls::secure;
+use crate::session::{IdAllocator, Sessions};
+use crate::shutdown::ShutdownSignal;
+use shared::{
+    commands::PlayerCmd, NetworkError, PlayerError, ServerCommandToClient, ZappyError,
+    DEFAULT_GAME, GAME_SEPARATOR, RESUME_PREFIX, TOKEN_PREFIX,
+};
 use std::error::Error;
-use std::pin::Pin;
 use std::sync::Arc;
-use tokio::net::TcpListener;
-use tokio::sync::mpsc::UnboundedSender;
+use std::time::Duration;
 use tokio::sync::{mpsc, Mutex};
+use tokio_rustls::TlsAcceptor;
+
+/// The game of a connected player and its session, if the server keeps them.
+struct Joined {
+    game: Game,
+    /// The token of the session and the generation of the connection.
+    session: Option<(String, u64)>,
+}
 
 pub async fn client_routine(
-    server: Arc<Mutex<GameEngine>>,
-    client_senders: Arc<Mutex<HashMap<u16, UnboundedSender<ServerCommandToClient>>>>,
-    listener: TcpListener,
+    games: Arc<Mutex<GameManager>>,
+    (listener, acceptor): (Listener, Option<TlsAcceptor>),
+    limits: ConnectionLimits,
+    resume_grace: Duration,
+    guard: Guard,
+    shutdown: ShutdownSignal,
 ) -> Result<(), Box<dyn Error>> {
+    let (ids, sessions) = {
+        let games = games.lock().await;
+        (games.ids().clone(), games.sessions().clone())
+    };
     loop {
         let (socket, addr) = listener.accept().await?;
-        let id = addr.port();
-        log::info!("New connection, assigned id: {}", id);
+        let mut permit = match guard.admit(addr.ip()) {
+            Ok(permit) => permit,
+            Err(refusal) => {
+                log::warn!("Refused a connection from {addr}: {refusal}");
+                if acceptor.is_none() {
+                    let _ = socket.try_write(format!("{refusal}\n").as_bytes());
+                }
+                continue;
+            }
+        };
+        let Some(id) = ids.allocate() else {
+            log::warn!("No id left for {addr}, closing the connection");
+            continue;
+        };
+        log::info!("New connection from {addr}, assigned id: {id}");
 
-        let server_clone = Arc::clone(&server);
-        let server_arc_for_disconnect = Arc::clone(&server_clone);
-        let client_senders_clone = Arc::clone(&client_senders);
+        let games = Arc::clone(&games);
+        let ids = ids.clone();
+        let sessions = sessions.clone();
+        let acceptor = acceptor.clone();
+        let mut shutdown = shutdown.clone();
+        let mut banned = permit.ban_signal();
 
         tokio::spawn(async move {
-            let (cmd_tx, cmd_rx) = mpsc::unbounded_channel();
-            let stream: Pin<Box<dyn AsyncReadWrite + Send>> = Box::pin(socket);
-            let mut client = Connection::new(stream, id);
-            let handle_result: Result<(), ZappyError> = async {
+            let stream = match secure(socket, acceptor.as_ref(), limits.tls_timeout).await {
+                Ok(stream) => stream,
+                Err(err) => {
+                    log::info!("TLS negotiation with {id} failed: {err}");
+                    ids.release(id);
+                    return;
+                }
+            };
+            let mut client = Connection::new(stream, id, limits.max_line_length);
+            let mut joined: Option<Joined> = None;
+            let handling = async {
                 client.send_handshake().await?;
-                let team_name = client.read().await?.trim_end().to_string();
-                let (width, height, remaining_clients) = {
-                    let mut server_lock = server_clone.lock().await;
-                    let remaining_clients_count =
-                        server_lock.add_player(client.id(), team_name.clone())?;
-                    (
-                        server_lock.map_width(),
-                        server_lock.map_height(),
-                        remaining_clients_count,
-                    )
+                let handshake = client
+                    .read_within(limits.handshake_timeout, "the team name")
+                    .await?
+                    .trim_end()
+                    .to_string();
+                let (game, remaining_clients, token) = match handshake.strip_prefix(RESUME_PREFIX) {
+                    Some(token) => {
+                        let (game, generation, remaining) =
+                            resume(&games, &sessions, &ids, &mut client, token.trim()).await?;
+                        joined = Some(Joined {
+                            game: game.clone(),
+                            session: Some((token.trim().to_string(), generation)),
+                        });
+                        (game, remaining, Some(token.trim().to_string()))
+                    }
+                    None => {
+                        let (team_name, game_name) = handshake
+                            .split_once(GAME_SEPARATOR)
+                            .unwrap_or((&handshake, DEFAULT_GAME));
+                        let game = games.lock().await.get(game_name)?;
+                        let (id, team_name) = (client.id(), team_name.to_string());
+                        let remaining = game
+                            .engine
+                            .call(move |engine| engine.add_player(id, team_name))
+                            .await??;
+                        let token = (!resume_grace.is_zero())
+                            .then(|| sessions.open(game_name, client.id()));
+                        joined = Some(Joined {
+                            game: game.clone(),
+                            session: token.clone().map(|token| (token, 0)),
+                        });
+                        (game, remaining, token)
+                    }
                 };
+                let (width, height) = game
+                    .engine
+                    .call(|engine| (engine.map_width(), engine.map_height()))
+                    .await?;
                 client.writeln(&remaining_clients.to_string()).await?;
                 client.writeln(&format!("{} {}", width, height)).await?;
-                client_senders_clone.lock().await.insert(id, cmd_tx);
-                return handle_client(server_clone, &mut client, cmd_rx).await;
+                if let Some(token) = token {
+                    client.writeln(&format!("{TOKEN_PREFIX}{token}")).await?;
+                }
+                let cmd_rx = game.connect(client.id()).await;
+                return handle_client(
+                    game.engine,
+                    &mut client,
+                    cmd_rx,
+                    limits.idle_timeout,
+                    &mut permit,
+                )
+                .await;
+            };
+            let handle_result: Result<(), ZappyError> = tokio::select! {
+                biased;
+                result = handling => Ok(result),
+                _ = shutdown.requested() => Err(NetworkError::ShuttingDown(id)),
+                _ = banned.banned() => Err(NetworkError::Banned(id)),
             }
-            .await;
+            .unwrap_or_else(|err| Err(ZappyError::Network(err)));
 
             //Specific client loop ends here, cleanup before quiting async task
-            client_senders_clone.lock().await.remove(&id);
-            server_arc_for_disconnect.lock().await.remove_player(id);
+            let id = client.id();
+            match joined {
+                Some(Joined { game, session }) => {
+                    let lost =
+                        matches!(handle_result, Err(ZappyError::Network(ref err)) if is_lost(err));
+                    leave(game, session, id, lost, resume_grace, ids, sessions).await;
+                }
+                None => ids.release(id),
+            }
             log::debug!("{} has been deleted by server", id);
             if let Err(err) = handle_result {
                 match err {
+                    ZappyError::Network(
+                        err @ (NetworkError::TimedOut(..)
+                        | NetworkError::Flooding(_)
+                        | NetworkError::Banned(_)
+                        | NetworkError::ShuttingDown(_)),
+                    ) => {
+                        let _ = client.writeln(&err.to_string()).await;
+                        log::info!("{err}");
+                    }
                     ZappyError::Network(err) => log::error!("{err}"),
                     ZappyError::Game(err) => log::error!("{err}"),
                     ZappyError::Player(err) => {
@@ -67,20 +170,138 @@ pub async fn client_routine(
     }
 }
 
+/// Whether the connection failed or timed out, rather than being closed by the server, so that
+/// its player is kept for the client to resume.
+fn is_lost(err: &NetworkError) -> bool {
+    matches!(
+        err,
+        NetworkError::ConnectionClosedByClient(_)
+            | NetworkError::ConnectionCorrupted(..)
+            | NetworkError::FailedToWriteToSocket(..)
+            | NetworkError::FailedToReadFromSocket(..)
+            | NetworkError::TimedOut(..)
+    )
+}
+
+/// Gives the connection the player of the session `token`, returns its game, the generation of
+/// the connection and the free slots of its team.
+async fn resume(
+    games: &Mutex<GameManager>,
+    sessions: &Sessions,
+    ids: &IdAllocator,
+    client: &mut Connection,
+    token: &str,
+) -> Result<(Game, u64, u16), ZappyError> {
+    let unknown = ZappyError::Player(PlayerError::UnknownSession);
+    let (game_name, id, generation) = sessions.resume(token).ok_or(unknown)?;
+    let game = games.lock().await.get(&game_name).ok();
+    let remaining = match &game {
+        Some(game) => game
+            .engine
+            .call(move |engine| {
+                engine
+                    .players()
+                    .get(&id)
+                    .map(|player| engine.teams()[player.team()].remaining_members())
+            })
+            .await
+            .ok()
+            .flatten(),
+        None => None,
+    };
+    let (Some(game), Some(remaining)) = (game, remaining) else {
+        sessions.close(token);
+        ids.release(id);
+        return Err(ZappyError::Player(PlayerError::UnknownSession));
+    };
+    log::info!("{}: resumes the session of player {id}", client.id());
+    ids.release(client.id());
+    client.set_id(id);
+    Ok((game, generation, remaining))
+}
+
+/// Removes the player that left, or keeps it for `resume_grace` if its connection was lost and
+/// it has a session. Nothing is done if another connection resumed the session meanwhile.
+async fn leave(
+    game: Game,
+    session: Option<(String, u64)>,
+    id: u16,
+    lost: bool,
+    resume_grace: Duration,
+    ids: IdAllocator,
+    sessions: Sessions,
+) {
+    if let Some((token, generation)) = &session {
+        if !sessions.detach(token, *generation) {
+            return;
+        }
+    }
+    game.player_senders.lock().await.remove(&id);
+    let alive = game
+        .engine
+        .call(move |engine| engine.players().contains_key(&id))
+        .await
+        .unwrap_or(false);
+    match session {
+        Some((token, generation)) if lost && alive => {
+            log::info!("Player {id} disconnected, waiting {resume_grace:?} for a resume");
+            tokio::spawn(async move {
+                tokio::time::sleep(resume_grace).await;
+                if sessions.expire(&token, generation) {
+                    log::info!("Session of player {id} expired");
+                    let _ = game
+                        .engine
+                        .call(move |engine| engine.remove_player(id))
+                        .await;
+                    ids.release(id);
+                }
+            });
+        }
+        session => {
+            if let Some((token, _)) = session {
+                sessions.close(&token);
+            }
+            let _ = game
+                .engine
+                .call(move |engine| engine.remove_player(id))
+                .await;
+            ids.release(id);
+        }
+    }
+}
+
 async fn handle_client(
-    server: Arc<Mutex<GameEngine>>,
+    server: EngineHandle,
     client: &mut Connection,
-    mut cmd_rx: mpsc::UnboundedReceiver<ServerCommandToClient>,
+    mut cmd_rx: mpsc::Receiver<ServerCommandToClient>,
+    idle_timeout: Option<Duration>,
+    permit: &mut Permit,
 ) -> Result<(), ZappyError> {
+    let mut last_line = tokio::time::Instant::now();
     loop {
         tokio::select! {
+            _ = tokio::time::sleep_until(last_line + idle_timeout.unwrap_or_default()), if idle_timeout.is_some() => {
+                return Err(ZappyError::Network(NetworkError::TimedOut(client.id(), "a command".to_string())));
+            }
             result = client.read() => {
-                let n = result?;
+                last_line = tokio::time::Instant::now();
+                let n = match result {
+                    Err(ZappyError::Network(err @ NetworkError::MessageIsTooBig(..))) => {
+                        log::warn!("{err}");
+                        client.writeln(&err.to_string()).await?;
+                        continue;
+                    }
+                    result => result?,
+                };
+                if !permit.allow_command() {
+                    return Err(ZappyError::Network(NetworkError::Flooding(client.id())));
+                }
                 let trimmed = n.trim_end();
                 match PlayerCmd::try_from(trimmed) {
                     Ok(command) => {
                         log::info!("{}: sends command: {:?}", client.id(), command);
-                        if let Some(e)= server.lock().await.take_command(&client.id(), command)? {
+                        let id = client.id();
+                        if let Some(e) = server.call(move |engine| engine.take_command(&id, command)).await?? {
                             log::info!("Player {} tried to push {} in to a full queue.", client.id(), trimmed);
                             client.writeln(&e.to_string()).await?;
                         }
@@ -91,7 +312,10 @@ async fn handle_client(
                     }
                 }
             }
-            Some(cmd) = cmd_rx.recv() => {
+            cmd = cmd_rx.recv() => {
+                let Some(cmd) = cmd else {
+                    return Err(ZappyError::Network(NetworkError::LaggingBehind(client.id())));
+                };
                 match cmd {
                     ServerCommandToClient::Shutdown => {
                         log::debug!("Shutdown command received. Closing connection.");
@@ -108,3 +332,166 @@ async fn handle_client(
         }
     }
 }
+
+#[cfg(test)]
+mod client_tests {
+    use super::*;
+    use crate::archive::Archive;
+    use crate::args::ServerArgs;
+    use crate::guard::GuardLimits;
+    use crate::history::History;
+    use crate::listener::ListenAddress;
+    use crate::ratings::Ratings;
+    use crate::shutdown::Shutdown;
+    use clap::Parser;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
+    use tokio::net::TcpStream;
+
+    /// Sends the handshake `line` and reads the `count` first lines of the server.
+    async fn handshake(
+        port: u16,
+        line: &str,
+        count: usize,
+    ) -> (Lines<BufReader<TcpStream>>, Vec<String>) {
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        stream
+            .write_all(format!("{line}\n").as_bytes())
+            .await
+            .unwrap();
+        let mut lines = BufReader::new(stream).lines();
+        let mut received = Vec::new();
+        for _ in 0..count {
+            received.push(lines.next_line().await.unwrap().unwrap());
+        }
+        (lines, received)
+    }
+
+    #[tokio::test]
+    async fn resumes_player_with_session_token() {
+        // Given
+        let args = ServerArgs::try_parse_from([
+            "server",
+            "-x",
+            "5",
+            "-y",
+            "4",
+            "-n",
+            "anton",
+            "-c",
+            "2",
+            "--resume-grace",
+            "60",
+        ])
+        .unwrap();
+        let mut games = GameManager::new(Archive::new(
+            Ratings::default(),
+            History::open(None).unwrap(),
+            None,
+        ));
+        games.create_game(DEFAULT_GAME.to_string(), &args).unwrap();
+        let games = Arc::new(Mutex::new(games));
+        let listener = ListenAddress::tcp([127, 0, 0, 1], 0).bind().await.unwrap();
+        let ListenAddress::Tcp(addr) = listener.local_address().unwrap() else {
+            panic!("TCP listener bound to a Unix socket");
+        };
+        let port = addr.port();
+        let routine_games = Arc::clone(&games);
+        let signal = Shutdown::new().signal();
+        tokio::spawn(async move {
+            let limits = ConnectionLimits::new(&args);
+            let guard = Guard::load(GuardLimits::new(&args), None).unwrap();
+            let grace = Duration::from_secs(60);
+            let _ = client_routine(
+                routine_games,
+                (listener, None),
+                limits,
+                grace,
+                guard,
+                signal,
+            )
+            .await;
+        });
+
+        // When
+        let (first, joined) = handshake(port, "anton", 4).await;
+        drop(first);
+        let token = joined[3].strip_prefix(TOKEN_PREFIX).unwrap().to_string();
+        let (_second, resumed) = handshake(port, &format!("{RESUME_PREFIX}{token}"), 4).await;
+        let (_, unknown) = handshake(port, &format!("{RESUME_PREFIX}0"), 2).await;
+
+        // Then
+        assert_eq!(joined[..3], ["BIENVENUE", "1", "5 4"]);
+        assert_eq!(resumed, joined);
+        assert_eq!(unknown, ["BIENVENUE", "Unknown or expired session"]);
+        let game = games.lock().await.get(DEFAULT_GAME).unwrap();
+        let (players, remaining) = game
+            .engine
+            .call(|engine| {
+                (
+                    engine.players().keys().copied().collect::<Vec<_>>(),
+                    engine.teams()["anton"].remaining_members(),
+                )
+            })
+            .await
+            .unwrap();
+        assert_eq!(players, vec![1]);
+        assert_eq!(remaining, 1);
+    }
+
+    #[tokio::test]
+    async fn tells_players_the_server_is_shutting_down() {
+        // Given
+        let args =
+            ServerArgs::try_parse_from(["server", "-x", "5", "-y", "4", "-n", "anton", "-c", "2"])
+                .unwrap();
+        let mut games = GameManager::new(Archive::new(
+            Ratings::default(),
+            History::open(None).unwrap(),
+            None,
+        ));
+        games.create_game(DEFAULT_GAME.to_string(), &args).unwrap();
+        let games = Arc::new(Mutex::new(games));
+        let listener = ListenAddress::tcp([127, 0, 0, 1], 0).bind().await.unwrap();
+        let ListenAddress::Tcp(addr) = listener.local_address().unwrap() else {
+            panic!("TCP listener bound to a Unix socket");
+        };
+        let shutdown = Shutdown::new();
+        let routine = tokio::spawn({
+            let games = Arc::clone(&games);
+            let limits = ConnectionLimits::new(&args);
+            let guard = Guard::load(GuardLimits::new(&args), None).unwrap();
+            let signal = shutdown.signal();
+            async move {
+                let _ = client_routine(
+                    games,
+                    (listener, None),
+                    limits,
+                    Duration::ZERO,
+                    guard,
+                    signal,
+                )
+                .await;
+            }
+        });
+        let (mut joined, _) = handshake(addr.port(), "anton", 3).await;
+        let mut pending = BufReader::new(TcpStream::connect(addr).await.unwrap()).lines();
+        pending.next_line().await.unwrap();
+
+        // When
+        routine.abort();
+        games.lock().await.shutdown(None).await.unwrap();
+        let open = shutdown.close_connections(Duration::from_secs(5)).await;
+
+        // Then
+        assert_eq!(open, 0);
+        assert_eq!(
+            joined.next_line().await.unwrap().as_deref(),
+            Some("Server is shutting down the connection.")
+        );
+        assert!(pending
+            .next_line()
+            .await
+            .unwrap()
+            .is_some_and(|line| line.ends_with("server is shutting down")));
+    }
+}
@@ -1,51 +1,146 @@
+use crate::archive::Archive;
+use crate::bot::Bots;
+use crate::engine_handle::EngineMailbox;
 use crate::game_engine::GameEngine;
+use crate::game_manager::Game;
+use crate::metrics::MetricsWriter;
 use shared::{ServerCommandToClient, ServerResponse};
-use std::collections::HashMap;
-use std::sync::Arc;
 use std::time::Duration;
-use tokio::sync::mpsc::UnboundedSender;
-use tokio::sync::Mutex;
 
+/// Owns the engine of the game: ticks it at its pace and runs the calls of the other routines
+/// in between, until the game is removed. Once the game is over it only runs the calls.
 pub async fn game_routine(
-    server: Arc<Mutex<GameEngine>>,
-    client_senders: Arc<Mutex<HashMap<u16, UnboundedSender<ServerCommandToClient>>>>,
+    name: String,
+    game: Game,
+    (mut engine, mut mailbox): (GameEngine, EngineMailbox),
+    archive: Archive,
+    metrics: Option<MetricsWriter>,
+    bots: Option<Bots>,
     tud: u16,
 ) {
+    let Game {
+        player_senders: client_senders,
+        started_at,
+        ..
+    } = game.clone();
     let t0 = tokio::time::Instant::now();
+    let mut target = t0;
+    let mut is_over = false;
     let mut execution_results_buffer: Vec<(u16, ServerResponse)> = Vec::new();
+    let mut metrics = metrics.map(|writer| writer.spawn(name.clone()));
 
     loop {
-        let frame = {
-            let mut server_lock = server.lock().await;
-            server_lock.tick(&mut execution_results_buffer);
-            *server_lock.frame()
-        };
-
-        while let Some((client_id, response)) = execution_results_buffer.pop() {
-            if let Some(connection) = client_senders.lock().await.get(&client_id) {
-                //TODO: investigate is "slow reader" case is possible?
-                //TODO: for example can send take a lot of time to block us here?
-                if let Err(e) =
-                    connection.send(ServerCommandToClient::SendMessage(response.clone()))
-                {
-                    log::error!("Failed to send message to client: {:?}", e);
+        tokio::select! {
+            biased;
+            _ = tokio::time::sleep_until(target), if !is_over => {}
+            call = mailbox.recv() => {
+                match call {
+                    Some(call) => call(&mut engine),
+                    None => return,
                 }
-                if let ServerResponse::Mort = response {
-                    if let Err(e) = connection.send(ServerCommandToClient::Shutdown) {
-                        log::error!("Failed to send message to client: {:?}", e);
-                    }
+                continue;
+            }
+        }
+
+        mailbox.run_pending(&mut engine);
+        if let Some(bots) = &bots {
+            bots.fill(&game, &mut engine);
+        }
+        engine.tick(&mut execution_results_buffer);
+        if metrics.as_ref().is_some_and(|feed| !feed.on_frame(&engine)) {
+            metrics = None;
+        }
+        mailbox.publish(&engine);
+        is_over = engine.outcome().is_some();
+
+        {
+            let mut senders = client_senders.lock().await;
+            for (client_id, response) in execution_results_buffer.drain(..) {
+                let Some(outbox) = senders.get(&client_id) else {
+                    log::warn!("Can't find the player with id {client_id} to send the action execution result. Probably already disconnected.");
+                    continue;
+                };
+                let dead = response == ServerResponse::Mort;
+                let mut sent = outbox.send(ServerCommandToClient::SendMessage(response));
+                if dead {
+                    sent = sent.and(outbox.send(ServerCommandToClient::Shutdown));
+                }
+                if sent.is_err() {
+                    log::warn!("{name}: player {client_id} is too far behind its messages, disconnecting it");
+                    senders.remove(&client_id);
                 }
-            } else {
-                log::warn!("Can't find the player with id {client_id} to send the action execution result. Probably already disconnected.");
             }
         }
 
+        if is_over {
+            if let Some(feed) = metrics.take() {
+                feed.finish().await;
+            }
+            archive.record_game_over(&name, &started_at, &engine).await;
+            for (_, outbox) in client_senders.lock().await.drain() {
+                let _ = outbox.send(ServerCommandToClient::Shutdown);
+            }
+            continue;
+        }
+
         let now = tokio::time::Instant::now();
-        let target = t0 + Duration::from_nanos((1e9 * frame as f64 / tud as f64) as u64);
-        if now < target {
-            tokio::time::sleep(target - now).await;
-        } else {
+        target = t0 + Duration::from_nanos((1e9 * *engine.frame() as f64 / tud as f64) as u64);
+        if now > target {
             log::warn!("Time step took too long. Finished at {now:?} instead of {target:?}");
         }
     }
 }
+
+#[cfg(test)]
+mod game_tests {
+    use crate::archive::Archive;
+    use crate::args::ServerArgs;
+    use crate::game_manager::GameManager;
+    use crate::history::History;
+    use crate::ratings::Ratings;
+    use clap::Parser;
+    use shared::commands::PlayerCmd;
+    use shared::{ServerCommandToClient, ServerResponse};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn sends_responses_of_a_frame_in_command_order() {
+        // Given
+        let args =
+            ServerArgs::try_parse_from(["server", "-x", "5", "-y", "4", "-n", "Axel", "-t", "1"])
+                .unwrap();
+        let mut games = GameManager::new(Archive::new(
+            Ratings::default(),
+            History::open(None).unwrap(),
+            None,
+        ));
+        games.create_game("game".to_string(), &args).unwrap();
+        let game = games.get("game").unwrap();
+        let mut rx = game.connect(1).await;
+
+        // When
+        game.engine
+            .call(|engine| {
+                engine.add_player(1, "Axel".to_string()).unwrap();
+                engine.take_command(&1, PlayerCmd::ConnectNbr).unwrap();
+                engine.take_command(&1, PlayerCmd::Left).unwrap();
+            })
+            .await
+            .unwrap();
+        let mut received = Vec::new();
+        for _ in 0..2 {
+            let message = tokio::time::timeout(Duration::from_secs(5), rx.recv()).await;
+            received.push(message.unwrap().unwrap());
+        }
+
+        // Then
+        assert!(matches!(
+            received[0],
+            ServerCommandToClient::SendMessage(ServerResponse::Value(_))
+        ));
+        assert!(matches!(
+            received[1],
+            ServerCommandToClient::SendMessage(ServerResponse::Ok)
+        ));
+    }
+}
@@ -1,71 +1,216 @@
-use crate::game_engine::GameEngine;
-use shared::GFXData;
+use crate::connection::{AsyncReadWrite, ConnectionLimits};
+use crate::engine_handle::Frame;
+use crate::game_manager::GameManager;
+use crate::guard::Guard;
+use crate::listener::Listener;
+use crate::security::tls::secure;
+use crate::shutdown::ShutdownSignal;
+use shared::{DEFAULT_GAME, GFX_SHUTDOWN_MSG};
 use std::error::Error;
+use std::pin::Pin;
 use std::sync::Arc;
 use std::time::Duration;
-use tokio::io::AsyncWriteExt;
-use tokio::net::{TcpListener, TcpStream};
-use tokio::sync::Mutex;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, WriteHalf};
+use tokio::sync::{watch, Mutex};
+use tokio_rustls::TlsAcceptor;
+
+/// Time between two lookups of a watched game that doesn't exist.
+const GAME_LOOKUP_INTERVAL: Duration = Duration::from_millis(20);
+/// Time given to a viewer to get the shutdown message before its connection is left behind.
+const GOODBYE_TIMEOUT: Duration = Duration::from_millis(500);
 
 pub async fn gfx_routine(
-    server: Arc<Mutex<GameEngine>>,
-    listener: TcpListener,
+    games: Arc<Mutex<GameManager>>,
+    (listener, acceptor): (Listener, Option<TlsAcceptor>),
+    limits: ConnectionLimits,
+    guard: Guard,
+    shutdown: ShutdownSignal,
 ) -> Result<(), Box<dyn Error>> {
     loop {
         let (socket, addr) = listener.accept().await?;
+        let permit = match guard.admit(addr.ip()) {
+            Ok(permit) => permit,
+            Err(refusal) => {
+                log::warn!("Refused a gfx connection from {addr}: {refusal}");
+                continue;
+            }
+        };
         log::debug!("New gfx client connected: {}", addr);
-        let server_clone = Arc::clone(&server);
+        let games = Arc::clone(&games);
+        let acceptor = acceptor.clone();
+        let shutdown = shutdown.clone();
 
         tokio::spawn(async move {
-            if let Err(e) = handle_streaming_client(server_clone, socket).await {
-                log::error!("Error handling streaming client {}: {:?}", addr, e);
+            let socket = match secure(socket, acceptor.as_ref(), limits.tls_timeout).await {
+                Ok(socket) => socket,
+                Err(e) => {
+                    log::info!("TLS negotiation with gfx client {addr} failed: {e}");
+                    return;
+                }
+            };
+            let mut banned = permit.ban_signal();
+            tokio::select! {
+                result = handle_streaming_client(games, socket, shutdown) => {
+                    if let Err(e) = result {
+                        log::error!("Error handling streaming client {}: {:?}", addr, e);
+                    }
+                }
+                _ = banned.banned() => log::info!("Gfx client {addr} banned, closing the connection"),
             }
         });
     }
 }
 
+/// Streams the frames of the watched game, `DEFAULT_GAME` until the client sends the name of
+/// another one, and closes the connection with `GFX_SHUTDOWN_MSG` when the server stops. The frames are written by
+/// another task, so that a slow viewer skips the frames encoded while it is still reading the
+/// previous one and never holds back its subscriptions or the shutdown.
 async fn handle_streaming_client(
-    server: Arc<Mutex<GameEngine>>,
-    mut socket: TcpStream,
+    games: Arc<Mutex<GameManager>>,
+    socket: Pin<Box<dyn AsyncReadWrite + Send>>,
+    mut shutdown: ShutdownSignal,
 ) -> std::io::Result<()> {
-    let mut last_data = GFXData::default();
+    let (reader, writer) = tokio::io::split(socket);
+    let mut subscriptions = BufReader::new(reader).lines();
+    let (outgoing, pending) = watch::channel(Frame::from(""));
+    let mut writer = tokio::spawn(write_frames(writer, pending));
+    let mut game_name = DEFAULT_GAME.to_string();
+    let mut listening = true;
+    // The frames of the watched game, looked up again until it exists.
+    let mut frames: Option<watch::Receiver<Frame>> = None;
 
     loop {
-        tokio::time::sleep(Duration::from_millis(20)).await;
+        if frames.is_none() {
+            let game = games.lock().await.get(&game_name);
+            if let Ok(game) = game {
+                frames = game.engine.frames().await.ok();
+            }
+        }
+        tokio::select! {
+            line = subscriptions.next_line(), if listening => {
+                match line? {
+                    Some(line) => {
+                        log::debug!("Gfx client subscribes to game \"{}\"", line.trim());
+                        game_name = line.trim().to_string();
+                        frames = None;
+                    }
+                    None => listening = false,
+                }
+                continue;
+            }
+            _ = shutdown.requested() => break,
+            written = &mut writer => return written?,
+            _ = tokio::time::sleep(GAME_LOOKUP_INTERVAL), if frames.is_none() => continue,
+            changed = async { frames.as_mut()?.changed().await.ok() }, if frames.is_some() => {
+                if changed.is_none() {
+                    frames = None;
+                    continue;
+                }
+            }
+        }
 
-        let current_data = {
-            let server_lock = server.lock().await;
-            GFXData::new(
-                server_lock.map().clone(),
-                server_lock.players().clone(),
-                server_lock
-                    .teams()
-                    .iter()
-                    .map(|(k, v)| (k.clone(), (v.color(), v.members_count())))
-                    .collect(),
-            )
+        let Some(frame) = frames
+            .as_mut()
+            .map(|frames| frames.borrow_and_update().clone())
+        else {
+            continue;
         };
+        if !frame.is_empty() {
+            outgoing.send_replace(frame);
+        }
+    }
+    // The writer tells the client that the server stops once done with the frame it may be
+    // writing, then closes the connection.
+    outgoing.send_replace(Frame::from(GFX_SHUTDOWN_MSG));
+    drop(outgoing);
+    let _ = tokio::time::timeout(GOODBYE_TIMEOUT, writer).await;
+    Ok(())
+}
 
-        if current_data != last_data {
-            let serialized_state = match serde_json::to_string(&current_data) {
-                Ok(json) => json,
-                Err(err) => {
-                    eprintln!("Failed to serialize current state: {:?}", err);
-                    continue;
-                }
-            };
+/// Writes the latest frame whenever the previous one is written, and closes the connection once
+/// there are no more frames to write.
+async fn write_frames(
+    mut socket: WriteHalf<Pin<Box<dyn AsyncReadWrite + Send>>>,
+    mut frames: watch::Receiver<Frame>,
+) -> std::io::Result<()> {
+    while frames.changed().await.is_ok() {
+        let frame = frames.borrow_and_update().clone();
+        socket.write_all(frame.as_bytes()).await?;
+    }
+    socket.shutdown().await
+}
 
-            if let Err(err) = socket.write_all(serialized_state.as_bytes()).await {
-                eprintln!("Failed to write to socket: {:?}", err);
-                return Err(err);
-            }
+#[cfg(test)]
+mod gfx_tests {
+    use super::*;
+    use crate::archive::Archive;
+    use crate::args::ServerArgs;
+    use crate::history::History;
+    use crate::ratings::Ratings;
+    use crate::shutdown::Shutdown;
+    use clap::Parser;
 
-            if let Err(err) = socket.write_all(b"\n").await {
-                eprintln!("Failed to write delimiter to socket: {:?}", err);
-                return Err(err);
-            }
+    #[tokio::test]
+    async fn stops_stalled_viewer_on_shutdown() {
+        // Given
+        let args = ServerArgs::try_parse_from(["server", "-x", "5", "-y", "4", "-n", "anton"]);
+        let mut games = GameManager::new(Archive::new(
+            Ratings::default(),
+            History::open(None).unwrap(),
+            None,
+        ));
+        games
+            .create_game(DEFAULT_GAME.to_string(), &args.unwrap())
+            .unwrap();
+        let (mut viewer, socket) = tokio::io::duplex(16);
+        let shutdown = Shutdown::new();
+        let streaming = tokio::spawn(handle_streaming_client(
+            Arc::new(Mutex::new(games)),
+            Box::pin(socket),
+            shutdown.signal(),
+        ));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        viewer.write_all(b"other\n").await.unwrap();
 
-            last_data = current_data;
+        // When
+        let open = shutdown.close_connections(Duration::from_secs(5)).await;
+
+        // Then
+        assert_eq!(open, 0);
+        assert!(streaming.await.unwrap().is_ok());
+    }
+
+    #[tokio::test]
+    async fn tells_viewer_the_server_stops() {
+        // Given
+        let args = ServerArgs::try_parse_from(["server", "-x", "5", "-y", "4", "-n", "anton"]);
+        let mut games = GameManager::new(Archive::new(
+            Ratings::default(),
+            History::open(None).unwrap(),
+            None,
+        ));
+        games
+            .create_game(DEFAULT_GAME.to_string(), &args.unwrap())
+            .unwrap();
+        let (viewer, socket) = tokio::io::duplex(1 << 16);
+        let shutdown = Shutdown::new();
+        let streaming = tokio::spawn(handle_streaming_client(
+            Arc::new(Mutex::new(games)),
+            Box::pin(socket),
+            shutdown.signal(),
+        ));
+        let mut lines = BufReader::new(viewer).lines();
+        lines.next_line().await.unwrap();
+
+        // When
+        shutdown.close_connections(Duration::from_secs(5)).await;
+        let mut last = None;
+        while let Some(line) = lines.next_line().await.unwrap() {
+            last = Some(line);
         }
+
+        // Then
+        assert!(streaming.await.unwrap().is_ok());
+        assert_eq!(last.as_deref(), Some(GFX_SHUTDOWN_MSG.trim_end()));
     }
 }
@@ -0,0 +1,94 @@
+use crate::args::ServerArgs;
+use clap::ValueEnum;
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use serde::Serialize;
+
+/// Order in which the commands resolving during the same frame are applied.
+///
+/// Players are stored by id, so without an explicit policy the lowest id (i.e. the lowest client
+/// port) always wins a contested `Take` and is always processed first for `Expel`.
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum ResolutionPolicy {
+    /// Ascending player id. Deterministic but biased, kept for debugging.
+    IdOrder,
+    /// Shuffled every frame with the game random generator (see `--seed`).
+    #[default]
+    Random,
+    /// The first player to act is the one following the previous frame's first player by id.
+    RoundRobin,
+}
+
+/// What a player sees when their field of view is bigger than the map and wraps around it.
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum VisionPolicy {
+    /// Every cell of the cone is reported, the same cell can appear several times.
+    #[default]
+    Repeat,
+    /// A cell already reported is replaced with `=K`, `K` being the index of its first occurrence.
+    Mark,
+    /// The cone stops before the first line containing a cell already reported.
+    Cap,
+}
+
+/// How a broadcast propagates from its sender to the other players.
+/// The default is the classic global and lossless broadcast.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub(crate) struct BroadcastRules {
+    /// Players further than this distance (see `Map::distance`) don't hear the message.
+    pub(crate) hearing_radius: Option<usize>,
+    /// Whether the distance to the sender is reported alongside the direction.
+    pub(crate) report_distance: bool,
+    /// Probability, per cell of distance, that each character of the message is garbled.
+    pub(crate) garble_rate: f64,
+}
+
+impl BroadcastRules {
+    /// The text heard by a player at `distance` from the sender, `None` if out of reach.
+    pub(crate) fn hear<R: Rng>(&self, text: &str, distance: usize, rng: &mut R) -> Option<String> {
+        if self.hearing_radius.is_some_and(|radius| distance > radius) {
+            return None;
+        }
+        let garble_probability = (self.garble_rate * distance as f64).min(1.);
+        if garble_probability <= 0. {
+            return Some(text.to_string());
+        }
+        Some(
+            text.chars()
+                .map(|c| {
+                    if rng.gen_bool(garble_probability) {
+                        rng.sample(Alphanumeric) as char
+                    } else {
+                        c
+                    }
+                })
+                .collect(),
+        )
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub(crate) struct GameRules {
+    pub(crate) resolution: ResolutionPolicy,
+    pub(crate) broadcast: BroadcastRules,
+    pub(crate) vision: VisionPolicy,
+    /// The game ends after this frame even if no team won.
+    pub(crate) max_frames: Option<u64>,
+}
+
+impl From<&ServerArgs> for GameRules {
+    fn from(args: &ServerArgs) -> Self {
+        Self {
+            resolution: args.resolution,
+            broadcast: BroadcastRules {
+                hearing_radius: args.hearing_radius,
+                report_distance: args.broadcast_distance,
+                garble_rate: args.garble_rate,
+            },
+            vision: args.vision,
+            max_frames: args.max_frames,
+        }
+    }
+}
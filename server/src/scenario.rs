@@ -0,0 +1,576 @@
+use crate::args::ServerArgs;
+use crate::game_engine::{GameEngine, GameOutcome};
+use clap::Parser;
+use serde::Deserialize;
+use shared::commands::PlayerCmd;
+use shared::map::Map;
+use shared::position::{Direction, Position};
+use shared::resource::Resource;
+use shared::{ServerResponse, MAX_PLAYER_LVL};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Most frames a scenario can run, for the admin `scenario` command to end in a few seconds.
+const MAX_FRAMES: u64 = 100_000;
+
+/// A game situation and what should happen from it, loaded from a JSON file (see MISC.md).
+///
+/// Checks of a frame are made after the tick reaching it, then the events of the frame are
+/// applied: commands given at frame 0 are executed at frame 1. The seed is 0 unless given in
+/// `args`, so that a scenario always plays the same way.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct Scenario {
+    pub width: usize,
+    pub height: usize,
+    pub teams: Vec<String>,
+    /// Other server arguments, setting the rules of the game.
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Frames to run, at least up to the last event or check, and at most `MAX_FRAMES`.
+    #[serde(default)]
+    pub frames: u64,
+    #[serde(default)]
+    pub cells: Vec<CellSetup>,
+    #[serde(default)]
+    pub players: Vec<PlayerSetup>,
+    #[serde(default)]
+    pub timeline: Vec<TimedEvent>,
+    #[serde(default)]
+    pub expect: Vec<Check>,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct CellSetup {
+    pub x: usize,
+    pub y: usize,
+    /// Count by resource name.
+    #[serde(default)]
+    pub resources: BTreeMap<String, usize>,
+    /// Hatched eggs by team, the free slots of the teams.
+    #[serde(default)]
+    pub eggs: BTreeMap<String, usize>,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct PlayerSetup {
+    pub id: u16,
+    pub team: String,
+    pub x: usize,
+    pub y: usize,
+    #[serde(default = "default_direction")]
+    pub direction: Direction,
+    #[serde(default = "default_level")]
+    pub level: u8,
+    /// Count by stone name.
+    #[serde(default)]
+    pub inventory: BTreeMap<String, usize>,
+    /// Frames left to live, the starting life if not set.
+    pub life: Option<u64>,
+}
+
+fn default_direction() -> Direction {
+    Direction::North
+}
+
+fn default_level() -> u8 {
+    1
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct TimedEvent {
+    pub frame: u64,
+    #[serde(flatten)]
+    pub event: Event,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum Event {
+    /// A command sent by a player, as written by a client.
+    Command { player: u16, command: String },
+    /// A client joining a team, in one of its free slots.
+    Join { player: u16, team: String },
+    /// A client leaving the game.
+    Leave { player: u16 },
+    Resource {
+        x: usize,
+        y: usize,
+        resource: String,
+        #[serde(default = "default_count")]
+        count: usize,
+    },
+    /// A hatched egg, adding a free slot to the team.
+    Egg { x: usize, y: usize, team: String },
+}
+
+fn default_count() -> usize {
+    1
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct Check {
+    pub frame: u64,
+    #[serde(flatten)]
+    pub expectation: Expectation,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "expect", rename_all = "kebab-case")]
+pub enum Expectation {
+    /// A response received by a player during the frame, as written to the client.
+    Response { player: u16, response: String },
+    /// The state of a player, only the given fields are checked.
+    Player {
+        player: u16,
+        #[serde(default = "default_alive")]
+        alive: bool,
+        x: Option<usize>,
+        y: Option<usize>,
+        direction: Option<Direction>,
+        level: Option<u8>,
+        life: Option<u64>,
+        /// Count by stone name, only the given stones are checked.
+        inventory: Option<BTreeMap<String, usize>>,
+    },
+    /// The content of a cell, only the given resources are checked.
+    Cell {
+        x: usize,
+        y: usize,
+        #[serde(default)]
+        resources: BTreeMap<String, usize>,
+        players: Option<usize>,
+    },
+    /// The team that won the game, `null` if none did yet.
+    Winner { team: Option<String> },
+}
+
+fn default_alive() -> bool {
+    true
+}
+
+fn resource(name: &str) -> Result<Resource, String> {
+    Resource::try_from(name).map_err(|_| format!("Unknown resource \"{name}\""))
+}
+
+fn mismatch<T: PartialEq + std::fmt::Debug>(what: &str, expected: T, actual: T) -> Option<String> {
+    (expected != actual).then(|| format!("{what}: expected {expected:?}, got {actual:?}"))
+}
+
+impl Scenario {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let json = std::fs::read_to_string(path)
+            .map_err(|e| format!("Can't read scenario {}: {e}", path.display()))?;
+        serde_json::from_str(&json).map_err(|e| format!("Invalid scenario {}: {e}", path.display()))
+    }
+
+    /// The engine in the situation described by the scenario, at frame 0.
+    pub fn engine(&self) -> Result<GameEngine, String> {
+        let mut args = vec![
+            "scenario".to_string(),
+            "-x".to_string(),
+            self.width.to_string(),
+            "-y".to_string(),
+            self.height.to_string(),
+            "-n".to_string(),
+        ];
+        args.extend(self.teams.iter().cloned());
+        args.extend(self.args.iter().cloned());
+        let mut args = ServerArgs::try_parse_from(args).map_err(|e| e.to_string())?;
+        args.seed = args.seed.or(Some(0));
+
+        let mut map = Map::empty(self.width, self.height);
+        for cell in &self.cells {
+            self.check_position(cell.x, cell.y)?;
+            for (name, &count) in &cell.resources {
+                let resource = resource(name)?;
+                for _ in 0..count {
+                    map.field[cell.y][cell.x].add_resource(resource);
+                }
+            }
+        }
+        let mut engine = GameEngine::with_map(&args, map);
+
+        for setup in &self.players {
+            self.check_position(setup.x, setup.y)?;
+            if !(1..=MAX_PLAYER_LVL).contains(&setup.level) {
+                return Err(format!(
+                    "Player {}: level {} is not between 1 and {MAX_PLAYER_LVL}",
+                    setup.id, setup.level
+                ));
+            }
+            let position = Position {
+                x: setup.x,
+                y: setup.y,
+                dir: setup.direction,
+            };
+            engine
+                .add_egg(&setup.team, position)
+                .and_then(|_| engine.add_player(setup.id, setup.team.clone()))
+                .map_err(|e| format!("Player {}: {e}", setup.id))?;
+            let player = engine.player_mut(setup.id).unwrap();
+            player.set_level(setup.level);
+            if let Some(life) = setup.life {
+                player.set_remaining_life(life);
+            }
+            for (name, &count) in &setup.inventory {
+                let resource = resource(name)?;
+                for _ in 0..count {
+                    player.add_to_inventory(resource);
+                }
+            }
+        }
+        for cell in &self.cells {
+            for (team, &count) in &cell.eggs {
+                for _ in 0..count {
+                    let position = Position {
+                        x: cell.x,
+                        y: cell.y,
+                        dir: default_direction(),
+                    };
+                    engine.add_egg(team, position).map_err(|e| e.to_string())?;
+                }
+            }
+        }
+        Ok(engine)
+    }
+
+    fn check_position(&self, x: usize, y: usize) -> Result<(), String> {
+        if x < self.width && y < self.height {
+            Ok(())
+        } else {
+            Err(format!("({x}, {y}) is outside of the map"))
+        }
+    }
+
+    /// Runs the scenario and returns the failed checks, or an error if it can't be played.
+    pub fn run(&self) -> Result<Vec<String>, String> {
+        let mut engine = self.engine()?;
+        let last_frame = self
+            .timeline
+            .iter()
+            .map(|event| event.frame)
+            .chain(self.expect.iter().map(|check| check.frame))
+            .fold(self.frames, u64::max);
+        if last_frame > MAX_FRAMES {
+            return Err(format!(
+                "The scenario runs {last_frame} frames, more than {MAX_FRAMES}"
+            ));
+        }
+        let mut mismatches = Vec::new();
+        let mut responses = Vec::new();
+        for frame in 0..=last_frame {
+            if frame > 0 {
+                responses.clear();
+                engine.tick(&mut responses);
+            }
+            for check in self.expect.iter().filter(|check| check.frame == frame) {
+                mismatches.extend(
+                    check
+                        .expectation
+                        .check(&engine, &mut responses)
+                        .into_iter()
+                        .map(|e| format!("frame {frame}: {e}")),
+                );
+            }
+            for event in self.timeline.iter().filter(|event| event.frame == frame) {
+                event
+                    .event
+                    .apply(&mut engine)
+                    .map_err(|e| format!("frame {frame}: {e}"))?;
+            }
+        }
+        Ok(mismatches)
+    }
+}
+
+impl Event {
+    fn apply(&self, engine: &mut GameEngine) -> Result<(), String> {
+        match self {
+            Event::Command { player, command } => {
+                let command = PlayerCmd::try_from(command.as_str())?;
+                match engine.take_command(player, command) {
+                    Ok(None) => Ok(()),
+                    Ok(Some(response)) => Err(format!("Player {player}: {response}")),
+                    Err(e) => Err(e.to_string()),
+                }
+            }
+            Event::Join { player, team } => engine
+                .add_player(*player, team.clone())
+                .map(|_| ())
+                .map_err(|e| e.to_string()),
+            Event::Leave { player } => {
+                engine.remove_player(*player);
+                Ok(())
+            }
+            Event::Resource {
+                x,
+                y,
+                resource: name,
+                count,
+            } => {
+                let resource = resource(name)?;
+                let cell = engine
+                    .map_mut()
+                    .field
+                    .get_mut(*y)
+                    .and_then(|row| row.get_mut(*x))
+                    .ok_or(format!("({x}, {y}) is outside of the map"))?;
+                for _ in 0..*count {
+                    cell.add_resource(resource);
+                }
+                Ok(())
+            }
+            Event::Egg { x, y, team } => {
+                if *x >= engine.map_width() || *y >= engine.map_height() {
+                    return Err(format!("({x}, {y}) is outside of the map"));
+                }
+                let position = Position {
+                    x: *x,
+                    y: *y,
+                    dir: default_direction(),
+                };
+                engine.add_egg(team, position).map_err(|e| e.to_string())
+            }
+        }
+    }
+}
+
+impl Expectation {
+    /// The mismatches with the engine, matched responses are removed from `responses`.
+    fn check(
+        &self,
+        engine: &GameEngine,
+        responses: &mut Vec<(u16, ServerResponse)>,
+    ) -> Vec<String> {
+        match self {
+            Expectation::Response { player, response } => {
+                match responses
+                    .iter()
+                    .position(|(id, actual)| id == player && actual.to_string() == *response)
+                {
+                    Some(index) => {
+                        responses.remove(index);
+                        Vec::new()
+                    }
+                    None => {
+                        let received: Vec<String> = responses
+                            .iter()
+                            .filter(|(id, _)| id == player)
+                            .map(|(_, actual)| actual.to_string())
+                            .collect();
+                        vec![format!(
+                            "player {player}: expected response {response:?}, got {received:?}"
+                        )]
+                    }
+                }
+            }
+            Expectation::Player {
+                player: id,
+                alive,
+                x,
+                y,
+                direction,
+                level,
+                life,
+                inventory,
+            } => {
+                let Some(player) = engine.players().get(id) else {
+                    return if *alive {
+                        vec![format!("player {id}: expected alive, got dead")]
+                    } else {
+                        Vec::new()
+                    };
+                };
+                if !alive {
+                    return vec![format!("player {id}: expected dead, got alive")];
+                }
+                let mut mismatches = vec![
+                    x.and_then(|x| mismatch("x", x, player.position().x)),
+                    y.and_then(|y| mismatch("y", y, player.position().y)),
+                    direction.and_then(|dir| mismatch("direction", dir, player.position().dir)),
+                    level.and_then(|level| mismatch("level", level, *player.level())),
+                    life.and_then(|life| mismatch("life", life, *player.remaining_life())),
+                ];
+                for (name, &count) in inventory.iter().flatten() {
+                    mismatches.push(match resource(name) {
+                        Ok(Resource::Stone(stone)) => {
+                            mismatch(name, count, player.inventory()[stone.index()])
+                        }
+                        _ => Some(format!("unknown stone \"{name}\"")),
+                    });
+                }
+                mismatches
+                    .into_iter()
+                    .flatten()
+                    .map(|e| format!("player {id}: {e}"))
+                    .collect()
+            }
+            Expectation::Cell {
+                x,
+                y,
+                resources,
+                players,
+            } => {
+                let Some(cell) = engine.map().field.get(*y).and_then(|row| row.get(*x)) else {
+                    return vec![format!("({x}, {y}) is outside of the map")];
+                };
+                let actual = cell.get_resources_copy();
+                let mut mismatches: Vec<Option<String>> = resources
+                    .iter()
+                    .map(|(name, &count)| match resource(name) {
+                        Ok(resource) => mismatch(
+                            name,
+                            count,
+                            actual.iter().filter(|&&other| other == resource).count(),
+                        ),
+                        Err(e) => Some(e),
+                    })
+                    .collect();
+                mismatches.push(
+                    players.and_then(|players| mismatch("players", players, cell.players.len())),
+                );
+                mismatches
+                    .into_iter()
+                    .flatten()
+                    .map(|e| format!("cell ({x}, {y}): {e}"))
+                    .collect()
+            }
+            Expectation::Winner { team } => {
+                let winner = match engine.outcome() {
+                    Some(GameOutcome::Victory(winner)) => Some(winner),
+                    _ => None,
+                };
+                mismatch("winner", team.as_ref(), winner)
+                    .into_iter()
+                    .collect()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod scenario_tests {
+    use super::*;
+
+    fn scenario(json: &str) -> Scenario {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn runs_scenario_files() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("scenarios");
+        let mut paths: Vec<_> = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| {
+                path.extension()
+                    .is_some_and(|extension| extension == "json")
+            })
+            .collect();
+        paths.sort();
+        assert!(!paths.is_empty());
+        for path in paths {
+            let result = Scenario::load(&path).and_then(|scenario| scenario.run());
+            assert_eq!(result, Ok(Vec::new()), "{}", path.display());
+        }
+    }
+
+    #[test]
+    fn sets_up_players_and_cells() {
+        // Given
+        let scenario = scenario(
+            r#"{
+                "width": 4, "height": 3, "teams": ["anton", "axel"],
+                "cells": [{"x": 3, "y": 2, "resources": {"sibur": 2}, "eggs": {"axel": 1}}],
+                "players": [
+                    {"id": 1, "team": "anton", "x": 1, "y": 2, "direction": "East", "level": 3,
+                     "inventory": {"linemate": 2}, "life": 50},
+                    {"id": 2, "team": "anton", "x": 0, "y": 0}
+                ]
+            }"#,
+        );
+
+        // When
+        let engine = scenario.engine().unwrap();
+
+        // Then
+        let player = &engine.players()[&1];
+        assert_eq!((player.position().x, player.position().y), (1, 2));
+        assert_eq!(player.position().dir, Direction::East);
+        assert_eq!((*player.level(), *player.remaining_life()), (3, 50));
+        assert_eq!(player.inventory()[1], 2);
+        assert_eq!(engine.players()[&2].position().x, 0);
+        assert_eq!(engine.teams()["anton"].remaining_members(), 0);
+        assert_eq!(engine.teams()["axel"].remaining_members(), 1);
+        assert_eq!(engine.map().field[2][3].get_resources_copy().len(), 2);
+    }
+
+    #[test]
+    fn reports_mismatches() {
+        // Given
+        let scenario = scenario(
+            r#"{
+                "width": 3, "height": 3, "teams": ["anton"],
+                "players": [{"id": 1, "team": "anton", "x": 1, "y": 1}],
+                "timeline": [{"frame": 0, "event": "command", "player": 1, "command": "avance"}],
+                "expect": [
+                    {"frame": 1, "expect": "response", "player": 1, "response": "Ko"},
+                    {"frame": 1, "expect": "player", "player": 1, "y": 0, "level": 2}
+                ]
+            }"#,
+        );
+
+        // When
+        let mismatches = scenario.run().unwrap();
+
+        // Then
+        assert_eq!(
+            mismatches,
+            vec![
+                "frame 1: player 1: expected response \"Ko\", got [\"Ok\"]",
+                "frame 1: player 1: level: expected 2, got 1"
+            ]
+        );
+    }
+
+    #[test]
+    fn fails_on_invalid_setup() {
+        let scenario = scenario(
+            r#"{"width": 3, "height": 3, "teams": ["anton"],
+                "players": [{"id": 1, "team": "axel", "x": 1, "y": 1}]}"#,
+        );
+
+        assert!(scenario.engine().is_err());
+    }
+
+    #[test]
+    fn fails_on_too_many_frames() {
+        let scenario = scenario(&format!(
+            r#"{{"width": 3, "height": 3, "teams": ["anton"], "frames": {}}}"#,
+            MAX_FRAMES + 1
+        ));
+
+        assert_eq!(
+            scenario.run(),
+            Err(format!(
+                "The scenario runs {} frames, more than {MAX_FRAMES}",
+                MAX_FRAMES + 1
+            ))
+        );
+    }
+
+    #[test]
+    fn fails_on_invalid_level() {
+        for level in [0, MAX_PLAYER_LVL + 1] {
+            let scenario = scenario(&format!(
+                r#"{{"width": 3, "height": 3, "teams": ["anton"],
+                    "players": [{{"id": 1, "team": "anton", "x": 1, "y": 1, "level": {level}}}]}}"#
+            ));
+
+            assert_eq!(
+                scenario.engine().err(),
+                Some(format!("Player 1: level {level} is not between 1 and 8"))
+            );
+        }
+    }
+}
@@ -1,15 +1,29 @@
+use crate::connection::AsyncReadWrite;
+use crate::listener::Stream;
 use crate::security::{ConfigurationError, CERTIFICATE_FILENAME, CERTIFICATE_PRIVATE_KEY_FILENAME};
+use std::io;
+use std::path::Path;
+use std::pin::Pin;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio_rustls::rustls::pki_types::pem::PemObject;
 use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
 use tokio_rustls::TlsAcceptor;
 
 pub fn setup_tls() -> Result<TlsAcceptor, ConfigurationError> {
-    let certs = CertificateDer::pem_file_iter(CERTIFICATE_FILENAME)
+    acceptor(
+        Path::new(CERTIFICATE_FILENAME),
+        Path::new(CERTIFICATE_PRIVATE_KEY_FILENAME),
+    )
+}
+
+/// The acceptor presenting the certificate chain of the PEM file `certificate`.
+fn acceptor(certificate: &Path, key: &Path) -> Result<TlsAcceptor, ConfigurationError> {
+    let certs = CertificateDer::pem_file_iter(certificate)
         .map_err(|e| ConfigurationError::CertificateError(e.to_string()))?
         .collect::<Result<Vec<_>, _>>()
         .map_err(|e| ConfigurationError::CertificateError(e.to_string()))?;
-    let key = PrivateKeyDer::from_pem_file(CERTIFICATE_PRIVATE_KEY_FILENAME)
+    let key = PrivateKeyDer::from_pem_file(key)
         .map_err(|e| ConfigurationError::PrivateKeyError(e.to_string()))?;
     let config = rustls::ServerConfig::builder()
         .with_no_client_auth()
@@ -17,3 +31,82 @@ pub fn setup_tls() -> Result<TlsAcceptor, ConfigurationError> {
         .map_err(|e| ConfigurationError::TlsConfigurationError(e.to_string()))?;
     Ok(TlsAcceptor::from(Arc::new(config)))
 }
+
+/// Negotiates TLS on an accepted connection if its port uses it, within `timeout`.
+pub async fn secure(
+    stream: Stream,
+    acceptor: Option<&TlsAcceptor>,
+    timeout: Duration,
+) -> io::Result<Pin<Box<dyn AsyncReadWrite + Send>>> {
+    let Some(acceptor) = acceptor else {
+        return Ok(Box::pin(stream));
+    };
+    match tokio::time::timeout(timeout, acceptor.accept(stream)).await {
+        Ok(tls_stream) => Ok(Box::pin(tls_stream?)),
+        Err(_) => Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            "TLS negotiation timed out",
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tls_tests {
+    use super::*;
+    use crate::listener::ListenAddress;
+    use std::path::PathBuf;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+    use tokio_rustls::TlsConnector;
+
+    /// The test CA and the certificate it signs for "localhost", see `generate.sh`.
+    fn fixtures() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/tls")
+    }
+
+    /// Connects a client trusting the test CA to a TLS port, returns the result of both sides.
+    async fn handshake(server_name: &str) -> (io::Result<()>, io::Result<[u8; 5]>) {
+        let acceptor = acceptor(&fixtures().join("cert.pem"), &fixtures().join("key.pem")).unwrap();
+        let listener = ListenAddress::tcp([127, 0, 0, 1], 0).bind().await.unwrap();
+        let ListenAddress::Tcp(addr) = listener.local_address().unwrap() else {
+            panic!("TCP listener bound to a Unix socket");
+        };
+        let (config, name) =
+            shared::tls::client_config(&fixtures().join("ca.pem"), server_name).unwrap();
+        let client = tokio::spawn(async move {
+            let stream = TcpStream::connect(addr).await?;
+            let mut stream = TlsConnector::from(config).connect(name, stream).await?;
+            stream.write_all(b"voir\n").await?;
+            stream.flush().await
+        });
+        let (stream, _) = listener.accept().await.unwrap();
+        let server = async {
+            let mut stream = secure(stream, Some(&acceptor), Duration::from_secs(5)).await?;
+            let mut line = [0; 5];
+            stream.read_exact(&mut line).await?;
+            Ok(line)
+        };
+        let server = server.await;
+        (client.await.unwrap(), server)
+    }
+
+    #[tokio::test]
+    async fn negotiates_tls_with_certificate_signed_by_ca() {
+        // When
+        let (client, server) = handshake("localhost").await;
+
+        // Then
+        assert!(client.is_ok());
+        assert_eq!(&server.unwrap(), b"voir\n");
+    }
+
+    #[tokio::test]
+    async fn fails_tls_for_other_server_name() {
+        // When
+        let (client, server) = handshake("zappy.example.org").await;
+
+        // Then
+        assert!(client.is_err());
+        assert!(server.is_err());
+    }
+}
@@ -0,0 +1,139 @@
+use std::collections::{BTreeSet, HashMap};
+use std::sync::{Arc, Mutex};
+
+/// Hands out the ids of the player, bot and admin connections of every game, reusing the ids of
+/// the ones that left.
+#[derive(Debug, Clone, Default)]
+pub struct IdAllocator {
+    used: Arc<Mutex<BTreeSet<u16>>>,
+}
+
+impl IdAllocator {
+    /// The lowest free id, `None` if all of them are taken.
+    pub fn allocate(&self) -> Option<u16> {
+        let mut used = self.used.lock().unwrap();
+        let id = (1..=u16::MAX).find(|id| !used.contains(id))?;
+        used.insert(id);
+        Some(id)
+    }
+
+    pub fn release(&self, id: u16) {
+        self.used.lock().unwrap().remove(&id);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Session {
+    game: String,
+    id: u16,
+    /// Incremented by every resume, so that only the latest connection of the player can
+    /// detach it.
+    generation: u64,
+    connected: bool,
+}
+
+/// The players that can resume their game with the token they got at the handshake.
+#[derive(Debug, Clone, Default)]
+pub struct Sessions {
+    sessions: Arc<Mutex<HashMap<String, Session>>>,
+}
+
+impl Sessions {
+    /// Opens the session of the player `id` that just joined `game` and returns its token.
+    pub fn open(&self, game: &str, id: u16) -> String {
+        let token = format!("{:032x}", rand::random::<u128>());
+        self.sessions.lock().unwrap().insert(
+            token.clone(),
+            Session {
+                game: game.to_string(),
+                id,
+                generation: 0,
+                connected: true,
+            },
+        );
+        token
+    }
+
+    /// Attaches a new connection to the session, taking it over from the previous one if it is
+    /// still connected. Returns the game, the player id and the generation of the connection.
+    pub fn resume(&self, token: &str) -> Option<(String, u16, u64)> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let session = sessions.get_mut(token)?;
+        session.generation += 1;
+        session.connected = true;
+        Some((session.game.clone(), session.id, session.generation))
+    }
+
+    /// Marks the session as waiting for a resume if `generation` is its latest connection.
+    /// Returns whether it was.
+    pub fn detach(&self, token: &str, generation: u64) -> bool {
+        match self.sessions.lock().unwrap().get_mut(token) {
+            Some(session) if session.generation == generation => {
+                session.connected = false;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Closes the session if it wasn't resumed since the connection `generation` left.
+    /// Returns whether it was closed.
+    pub fn expire(&self, token: &str, generation: u64) -> bool {
+        let mut sessions = self.sessions.lock().unwrap();
+        match sessions.get(token) {
+            Some(session) if session.generation == generation && !session.connected => {
+                sessions.remove(token);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn close(&self, token: &str) {
+        self.sessions.lock().unwrap().remove(token);
+    }
+}
+
+#[cfg(test)]
+mod session_tests {
+    use super::*;
+
+    #[test]
+    fn reuses_released_ids() {
+        // Given
+        let ids = IdAllocator::default();
+
+        // When
+        let first = [ids.allocate(), ids.allocate(), ids.allocate()];
+        ids.release(2);
+
+        // Then
+        assert_eq!(first, [Some(1), Some(2), Some(3)]);
+        assert_eq!(ids.allocate(), Some(2));
+        assert_eq!(ids.allocate(), Some(4));
+    }
+
+    #[test]
+    fn resumes_detached_session_until_it_expires() {
+        // Given
+        let sessions = Sessions::default();
+        let token = sessions.open("default", 7);
+
+        // When
+        let detached = sessions.detach(&token, 0);
+        let resumed = sessions.resume(&token);
+        let stale_expiry = sessions.expire(&token, 0);
+        let stale_detach = sessions.detach(&token, 0);
+        sessions.detach(&token, 1);
+        let expired = sessions.expire(&token, 1);
+
+        // Then
+        assert!(detached);
+        assert_eq!(resumed, Some(("default".to_string(), 7, 1)));
+        assert!(!stale_expiry);
+        assert!(!stale_detach);
+        assert!(expired);
+        assert_eq!(sessions.resume(&token), None);
+        assert_eq!(sessions.resume("unknown"), None);
+    }
+}
@@ -0,0 +1,76 @@
+use std::io;
+use std::time::Duration;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::watch;
+
+/// Tells the open connections that the server stops and waits for them to be closed.
+pub struct Shutdown {
+    sender: watch::Sender<bool>,
+}
+
+/// Held by every open connection until it is closed.
+#[derive(Debug, Clone)]
+pub struct ShutdownSignal {
+    receiver: watch::Receiver<bool>,
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Self {
+            sender: watch::Sender::new(false),
+        }
+    }
+
+    pub fn signal(&self) -> ShutdownSignal {
+        ShutdownSignal {
+            receiver: self.sender.subscribe(),
+        }
+    }
+
+    /// Asks every connection to close and waits for them for at most `deadline`. Returns the
+    /// number of signals still held, the connections left open.
+    pub async fn close_connections(&self, deadline: Duration) -> usize {
+        self.sender.send_replace(true);
+        let _ = tokio::time::timeout(deadline, self.sender.closed()).await;
+        self.sender.receiver_count()
+    }
+}
+
+impl ShutdownSignal {
+    /// Completes once the server stops, never if its `Shutdown` is gone without stopping it.
+    pub async fn requested(&mut self) {
+        if self.receiver.wait_for(|stopping| *stopping).await.is_err() {
+            std::future::pending::<()>().await;
+        }
+    }
+}
+
+/// Completes on SIGINT or SIGTERM, with the name of the signal.
+pub async fn interrupted() -> io::Result<&'static str> {
+    let mut terminate = signal(SignalKind::terminate())?;
+    tokio::select! {
+        result = tokio::signal::ctrl_c() => result.map(|()| "SIGINT"),
+        _ = terminate.recv() => Ok("SIGTERM"),
+    }
+}
+
+#[cfg(test)]
+mod shutdown_tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn waits_for_connections_until_deadline() {
+        // Given
+        let shutdown = Shutdown::new();
+        let mut closing = shutdown.signal();
+        let _stuck = shutdown.signal();
+        let closed = tokio::spawn(async move { closing.requested().await });
+
+        // When
+        let open = shutdown.close_connections(Duration::from_millis(50)).await;
+
+        // Then
+        assert!(closed.await.is_ok());
+        assert_eq!(open, 1);
+    }
+}
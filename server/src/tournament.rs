@@ -0,0 +1,621 @@
+use crate::args::ServerArgs;
+use crate::game_engine::{GameOutcome, TeamScore};
+use crate::game_manager::GameManager;
+use serde::{Deserialize, Serialize};
+use shared::ZappyError;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashSet};
+use std::error::Error;
+use std::fmt::Write as _;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::process::{Child, Command};
+use tokio::sync::{Mutex, Semaphore};
+use tokio::task::JoinSet;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+const POINTS_PER_WIN: usize = 3;
+const POINTS_PER_DRAW: usize = 1;
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Format {
+    /// Every entry plays every other entry once per seed.
+    #[default]
+    RoundRobin,
+    /// Single elimination bracket, a match is made of one game per seed.
+    Elimination,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Entry {
+    pub name: String,
+    /// Shell command launching the bot of the team, `{host}`, `{port}`, `{team}` and `{game}`
+    /// are replaced, `{host}` being the socket path and `{port}` empty when players listen on a
+    /// Unix socket. Without command, the players of the team are expected to join by themselves.
+    pub command: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct TournamentConfig {
+    pub entries: Vec<Entry>,
+    #[serde(default)]
+    pub format: Format,
+    #[serde(default = "default_seeds")]
+    pub seeds: Vec<u64>,
+    /// Number of games played at the same time.
+    #[serde(default = "default_parallel")]
+    pub parallel: usize,
+    /// Standings file, written as CSV if its extension is `.csv` and as JSON otherwise.
+    pub output: PathBuf,
+}
+
+fn default_seeds() -> Vec<u64> {
+    vec![0]
+}
+
+fn default_parallel() -> usize {
+    1
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct GameResult {
+    pub game: String,
+    pub seed: u64,
+    pub teams: [String; 2],
+    pub frames: u64,
+    pub outcome: Option<GameOutcome>,
+    pub winner: Option<String>,
+    pub scores: BTreeMap<String, TeamScore>,
+}
+
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct Standing {
+    pub team: String,
+    pub played: usize,
+    pub won: usize,
+    pub drawn: usize,
+    pub lost: usize,
+    pub points: usize,
+    pub best_level: u8,
+}
+
+#[derive(Serialize, Debug)]
+struct TournamentReport<'a> {
+    format: Format,
+    champion: Option<&'a str>,
+    standings: &'a [Standing],
+    games: &'a [GameResult],
+}
+
+struct Match {
+    game: String,
+    teams: [Entry; 2],
+    seed: u64,
+}
+
+/// Plays the tournament described in `config_path` with `args` as the rules of every game.
+pub async fn tournament_routine(
+    games: Arc<Mutex<GameManager>>,
+    args: &ServerArgs,
+    config_path: &Path,
+) -> Result<(), Box<dyn Error>> {
+    let config: TournamentConfig = serde_json::from_str(&std::fs::read_to_string(config_path)?)?;
+    validate(&config, args)?;
+    log::info!(
+        "Starting {:?} tournament: {} entries, seeds {:?}",
+        config.format,
+        config.entries.len(),
+        config.seeds
+    );
+
+    let (results, champion) = match config.format {
+        Format::RoundRobin => {
+            let matches = round_robin_pairings(config.entries.len())
+                .into_iter()
+                .flat_map(|(a, b)| {
+                    let config = &config;
+                    config.seeds.iter().map(move |&seed| Match {
+                        game: game_name(0, &config.entries[a], &config.entries[b], seed),
+                        teams: [config.entries[a].clone(), config.entries[b].clone()],
+                        seed,
+                    })
+                })
+                .collect();
+            (
+                play_matches(&games, args, matches, config.parallel).await?,
+                None,
+            )
+        }
+        Format::Elimination => play_elimination(&games, args, &config).await?,
+    };
+
+    let standings = standings(&config.entries, &results);
+    let champion = champion.or_else(|| standings.first().map(|s| s.team.clone()));
+    let report = TournamentReport {
+        format: config.format,
+        champion: champion.as_deref(),
+        standings: &standings,
+        games: &results,
+    };
+    let content = if config.output.extension().is_some_and(|ext| ext == "csv") {
+        standings_to_csv(&standings)
+    } else {
+        serde_json::to_string_pretty(&report)?
+    };
+    std::fs::write(&config.output, content)?;
+    log::info!(
+        "Tournament over, champion: {:?}, standings written to {}",
+        champion,
+        config.output.display()
+    );
+    Ok(())
+}
+
+fn validate(config: &TournamentConfig, args: &ServerArgs) -> Result<(), String> {
+    let names: HashSet<&String> = config.entries.iter().map(|entry| &entry.name).collect();
+    if config.entries.len() < 2 {
+        Err("A tournament needs at least 2 entries".to_string())
+    } else if names.len() != config.entries.len() {
+        Err("Tournament entries must have different names".to_string())
+    } else if config.seeds.is_empty() || config.parallel == 0 {
+        Err("A tournament needs at least one seed and one parallel game".to_string())
+    } else if config.seeds.iter().collect::<HashSet<_>>().len() != config.seeds.len() {
+        Err("Tournament seeds must be different".to_string())
+    } else if args.max_frames.is_none() {
+        Err("A tournament needs --max-frames so that every game ends".to_string())
+    } else {
+        Ok(())
+    }
+}
+
+fn game_name(round: usize, a: &Entry, b: &Entry, seed: u64) -> String {
+    format!("r{round}-{}-{}-s{seed}", a.name, b.name)
+}
+
+fn round_robin_pairings(entries: usize) -> Vec<(usize, usize)> {
+    (0..entries)
+        .flat_map(|a| (a + 1..entries).map(move |b| (a, b)))
+        .collect()
+}
+
+async fn play_elimination(
+    games: &Arc<Mutex<GameManager>>,
+    args: &ServerArgs,
+    config: &TournamentConfig,
+) -> Result<(Vec<GameResult>, Option<String>), ZappyError> {
+    let mut results = Vec::new();
+    let mut alive: Vec<&Entry> = config.entries.iter().collect();
+    let mut byes = HashSet::new();
+    let mut round = 0;
+    while alive.len() > 1 {
+        round += 1;
+        give_bye(&mut alive, &mut byes);
+        let matches = alive
+            .chunks(2)
+            .filter(|pair| pair.len() == 2)
+            .flat_map(|pair| {
+                config.seeds.iter().map(move |&seed| Match {
+                    game: game_name(round, pair[0], pair[1], seed),
+                    teams: [pair[0].clone(), pair[1].clone()],
+                    seed,
+                })
+            })
+            .collect();
+        let round_results = play_matches(games, args, matches, config.parallel).await?;
+        alive = alive
+            .chunks(2)
+            .map(|pair| match pair {
+                [a, b] => {
+                    if match_winner(&a.name, &b.name, &round_results) == a.name {
+                        *a
+                    } else {
+                        *b
+                    }
+                }
+                [bye] => *bye,
+                _ => unreachable!(),
+            })
+            .collect();
+        log::info!(
+            "Round {round} over, remaining: {:?}",
+            alive.iter().map(|entry| &entry.name).collect::<Vec<_>>()
+        );
+        results.extend(round_results);
+    }
+    Ok((results, alive.first().map(|entry| entry.name.clone())))
+}
+
+/// Moves the entry getting the bye of a round with an odd number of entries to the end of the
+/// bracket: the last one that hasn't had a bye yet, so that the byes rotate.
+fn give_bye(alive: &mut Vec<&Entry>, byes: &mut HashSet<String>) {
+    if alive.len().is_multiple_of(2) {
+        return;
+    }
+    let index = alive
+        .iter()
+        .rposition(|entry| !byes.contains(&entry.name))
+        .unwrap_or(alive.len() - 1);
+    let entry = alive.remove(index);
+    byes.insert(entry.name.clone());
+    alive.push(entry);
+}
+
+/// The entry that won the most games against the other one. On equality, the one with the best
+/// scores summed over these games: levels, then players at those levels. If they are equal too,
+/// a coin toss drawn from the names of the games, so that the order of the bracket favours nobody.
+fn match_winner<'a>(a: &'a str, b: &'a str, results: &[GameResult]) -> &'a str {
+    let games: Vec<&GameResult> = results
+        .iter()
+        .filter(|result| result.teams.iter().any(|t| t == a) && result.teams.iter().any(|t| t == b))
+        .collect();
+    let record = |team: &str| {
+        let wins = games
+            .iter()
+            .filter(|result| result.winner.as_deref() == Some(team))
+            .count();
+        let (levels, players) = games
+            .iter()
+            .filter_map(|result| result.scores.get(team))
+            .fold((0, 0), |(levels, players), score| {
+                (
+                    levels + score.max_level as usize,
+                    players + score.players_at_max_level,
+                )
+            });
+        (wins, levels, players)
+    };
+    match record(a).cmp(&record(b)) {
+        Ordering::Greater => a,
+        Ordering::Less => b,
+        Ordering::Equal => {
+            let mut toss = DefaultHasher::new();
+            for result in &games {
+                result.game.hash(&mut toss);
+            }
+            if toss.finish().is_multiple_of(2) {
+                a
+            } else {
+                b
+            }
+        }
+    }
+}
+
+async fn play_matches(
+    games: &Arc<Mutex<GameManager>>,
+    args: &ServerArgs,
+    matches: Vec<Match>,
+    parallel: usize,
+) -> Result<Vec<GameResult>, ZappyError> {
+    let semaphore = Arc::new(Semaphore::new(parallel));
+    let mut set = JoinSet::new();
+    for (i, game) in matches.into_iter().enumerate() {
+        let (games, args, semaphore) = (Arc::clone(games), args.clone(), Arc::clone(&semaphore));
+        set.spawn(async move {
+            let _permit = semaphore.acquire_owned().await.unwrap();
+            play_game(games, args, game).await.map(|result| (i, result))
+        });
+    }
+    let mut results = Vec::new();
+    while let Some(result) = set.join_next().await {
+        results.push(result.expect("Tournament game panicked")?);
+    }
+    results.sort_by_key(|(i, _)| *i);
+    Ok(results.into_iter().map(|(_, result)| result).collect())
+}
+
+async fn play_game(
+    games: Arc<Mutex<GameManager>>,
+    mut args: ServerArgs,
+    game: Match,
+) -> Result<GameResult, ZappyError> {
+    args.names = game.teams.iter().map(|entry| entry.name.clone()).collect();
+    args.seed = Some(game.seed);
+    let engine = {
+        let mut games = games.lock().await;
+        games.create_game(game.game.clone(), &args)?;
+        games.get(&game.game)?.engine
+    };
+    let (host, port) = args.client_address().local_target();
+    let mut bots: Vec<Child> = game
+        .teams
+        .iter()
+        .filter_map(|entry| {
+            let command = entry
+                .command
+                .as_ref()?
+                .replace("{host}", &host)
+                .replace("{port}", &port)
+                .replace("{team}", &entry.name)
+                .replace("{game}", &game.game);
+            log::debug!("{}: launching {}", game.game, command);
+            Command::new("sh")
+                .arg("-c")
+                .arg(&command)
+                .kill_on_drop(true)
+                .spawn()
+                .map_err(|e| log::error!("{}: failed to launch {command}: {e}", game.game))
+                .ok()
+        })
+        .collect();
+
+    let result = loop {
+        tokio::time::sleep(POLL_INTERVAL).await;
+        let bots_exited = !bots.is_empty()
+            && bots
+                .iter_mut()
+                .all(|bot| matches!(bot.try_wait(), Ok(Some(_))));
+        let (game_name, seed, teams) = (
+            game.game.clone(),
+            game.seed,
+            game.teams.clone().map(|entry| entry.name),
+        );
+        let finished = engine
+            .call(move |engine| {
+                (engine.outcome().is_some() || (bots_exited && engine.players().is_empty())).then(
+                    || GameResult {
+                        game: game_name,
+                        seed,
+                        teams,
+                        frames: *engine.frame(),
+                        outcome: engine.outcome().clone(),
+                        winner: engine.winner(),
+                        scores: engine.team_scores(),
+                    },
+                )
+            })
+            .await?;
+        if let Some(result) = finished {
+            break result;
+        }
+    };
+    for bot in &mut bots {
+        let _ = bot.start_kill();
+    }
+    games.lock().await.remove_game(&game.game).await?;
+    log::info!(
+        "{}: {:?}, winner: {:?}",
+        result.game,
+        result.outcome,
+        result.winner
+    );
+    Ok(result)
+}
+
+fn standings(entries: &[Entry], results: &[GameResult]) -> Vec<Standing> {
+    let mut standings: Vec<Standing> = entries
+        .iter()
+        .map(|entry| Standing {
+            team: entry.name.clone(),
+            ..Default::default()
+        })
+        .collect();
+    for result in results {
+        for standing in standings
+            .iter_mut()
+            .filter(|standing| result.teams.contains(&standing.team))
+        {
+            standing.played += 1;
+            match &result.winner {
+                Some(winner) if *winner == standing.team => {
+                    standing.won += 1;
+                    standing.points += POINTS_PER_WIN;
+                }
+                Some(_) => standing.lost += 1,
+                None => {
+                    standing.drawn += 1;
+                    standing.points += POINTS_PER_DRAW;
+                }
+            }
+            if let Some(score) = result.scores.get(&standing.team) {
+                standing.best_level = standing.best_level.max(score.max_level);
+            }
+        }
+    }
+    standings.sort_by(|a, b| b.points.cmp(&a.points).then(b.won.cmp(&a.won)));
+    standings
+}
+
+fn standings_to_csv(standings: &[Standing]) -> String {
+    let mut csv = "rank,team,played,won,drawn,lost,points,best_level\n".to_string();
+    for (rank, s) in standings.iter().enumerate() {
+        let _ = writeln!(
+            csv,
+            "{},{},{},{},{},{},{},{}",
+            rank + 1,
+            s.team,
+            s.played,
+            s.won,
+            s.drawn,
+            s.lost,
+            s.points,
+            s.best_level
+        );
+    }
+    csv
+}
+
+#[cfg(test)]
+mod tournament_tests {
+    use super::*;
+    use clap::Parser;
+
+    fn entries(names: &[&str]) -> Vec<Entry> {
+        names
+            .iter()
+            .map(|name| Entry {
+                name: name.to_string(),
+                command: None,
+            })
+            .collect()
+    }
+
+    fn result(a: &str, b: &str, winner: Option<&str>, levels: (u8, u8)) -> GameResult {
+        GameResult {
+            game: format!("{a}-{b}"),
+            seed: 0,
+            teams: [a.to_string(), b.to_string()],
+            frames: 100,
+            outcome: Some(GameOutcome::TimeLimit),
+            winner: winner.map(str::to_string),
+            scores: BTreeMap::from([
+                (
+                    a.to_string(),
+                    TeamScore {
+                        max_level: levels.0,
+                        players_at_max_level: 1,
+                    },
+                ),
+                (
+                    b.to_string(),
+                    TeamScore {
+                        max_level: levels.1,
+                        players_at_max_level: 1,
+                    },
+                ),
+            ]),
+        }
+    }
+
+    #[test]
+    fn schedules_every_pair_once() {
+        assert_eq!(
+            round_robin_pairings(4),
+            vec![(0, 1), (0, 2), (0, 3), (1, 2), (1, 3), (2, 3)]
+        );
+        assert!(round_robin_pairings(1).is_empty());
+    }
+
+    #[test]
+    fn ranks_entries_by_points() {
+        // Given
+        let entries = entries(&["anton", "axel", "victor"]);
+        let results = vec![
+            result("anton", "axel", Some("axel"), (2, 3)),
+            result("anton", "victor", None, (2, 2)),
+            result("axel", "victor", Some("axel"), (4, 1)),
+        ];
+
+        // When
+        let standings = standings(&entries, &results);
+
+        // Then
+        let expected = [
+            ("axel", 2, 0, 0, 6, 4),
+            ("anton", 0, 1, 1, 1, 2),
+            ("victor", 0, 1, 1, 1, 2),
+        ];
+        assert_eq!(standings.len(), expected.len());
+        for (standing, (team, won, drawn, lost, points, best_level)) in
+            standings.iter().zip(expected)
+        {
+            assert_eq!(
+                standing,
+                &Standing {
+                    team: team.to_string(),
+                    played: 2,
+                    won,
+                    drawn,
+                    lost,
+                    points,
+                    best_level,
+                }
+            );
+        }
+        assert_eq!(
+            standings_to_csv(&standings),
+            "rank,team,played,won,drawn,lost,points,best_level\n\
+             1,axel,2,2,0,0,6,4\n\
+             2,anton,2,0,1,1,1,2\n\
+             3,victor,2,0,1,1,1,2\n"
+        );
+    }
+
+    #[test]
+    fn match_goes_to_the_most_game_wins() {
+        let results = vec![
+            result("anton", "axel", Some("axel"), (1, 2)),
+            result("anton", "axel", Some("axel"), (1, 2)),
+            result("anton", "axel", Some("anton"), (2, 1)),
+            result("anton", "victor", Some("victor"), (1, 2)),
+        ];
+
+        assert_eq!(match_winner("anton", "axel", &results), "axel");
+    }
+
+    #[test]
+    fn breaks_match_ties_on_scores_then_by_toss() {
+        // Given
+        let by_level = vec![
+            result("anton", "axel", Some("anton"), (3, 2)),
+            result("anton", "axel", Some("axel"), (2, 4)),
+        ];
+
+        // When
+        let tosses: HashSet<&str> = (0..16)
+            .map(|i| {
+                let mut draw = result("anton", "axel", None, (2, 2));
+                draw.game = format!("draw-{i}");
+                match_winner("anton", "axel", &[draw])
+            })
+            .collect();
+
+        // Then
+        assert_eq!(match_winner("anton", "axel", &by_level), "axel");
+        assert_eq!(match_winner("axel", "anton", &by_level), "axel");
+        assert_eq!(tosses, HashSet::from(["anton", "axel"]));
+    }
+
+    #[test]
+    fn rotates_byes() {
+        // Given
+        let entries = entries(&["anton", "axel", "victor"]);
+        let mut alive: Vec<&Entry> = entries.iter().collect();
+        let mut byes = HashSet::new();
+
+        // When
+        let byes: Vec<String> = (0..4)
+            .map(|_| {
+                give_bye(&mut alive, &mut byes);
+                alive.last().unwrap().name.clone()
+            })
+            .collect();
+
+        // Then
+        assert_eq!(byes, vec!["victor", "axel", "anton", "anton"]);
+    }
+
+    #[test]
+    fn rejects_duplicate_seeds() {
+        // Given
+        let config: TournamentConfig = serde_json::from_str(
+            r#"{"entries": [{"name": "anton"}, {"name": "axel"}], "seeds": [1, 2, 1],
+                "output": "standings.csv"}"#,
+        )
+        .unwrap();
+        let args = ServerArgs::try_parse_from([
+            "server",
+            "-x",
+            "5",
+            "-y",
+            "4",
+            "-n",
+            "anton",
+            "--max-frames",
+            "100",
+        ])
+        .unwrap();
+
+        // When
+        let result = validate(&config, &args);
+
+        // Then
+        assert_eq!(
+            result,
+            Err("Tournament seeds must be different".to_string())
+        );
+    }
+}
@@ -1,7 +1,28 @@
 use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
 
 pub enum AdminCommand {
     ShowOff,
+    /// Name of the game and its arguments, the same as the server ones.
+    CreateGame(String, Vec<String>),
+    RemoveGame(String),
+    ListGames,
+    /// All the teams if no team is given.
+    Ratings(Option<String>),
+    /// `key=value` filters of the finished games to list.
+    History(Vec<String>),
+    /// Statistics of the teams and players of a game.
+    Stats(String),
+    /// Path of a scenario file to play and check.
+    Scenario(String),
+    /// Current map of a game, in the text format of the map files.
+    ExportMap(String),
+    /// Depth of the send queues of the players of a game.
+    Queues(String),
+    Bans,
+    /// For the given seconds, the server ban duration if not set.
+    Ban(IpAddr, Option<u64>),
+    Unban(IpAddr),
 }
 
 impl AdminCommand {
@@ -18,11 +39,49 @@ impl TryFrom<&str> for AdminCommand {
 
         match (parts[0], parts.len()) {
             ("show_off", 1) => Ok(AdminCommand::ShowOff),
+            ("create", 2) => {
+                let mut args = parts[1].split_whitespace().map(str::to_string);
+                match args.next() {
+                    Some(name) => Ok(AdminCommand::CreateGame(name, args.collect())),
+                    None => Err(format!("Missing game name: \"{s}\"")),
+                }
+            }
+            ("remove" | "rm", 2) => Ok(AdminCommand::RemoveGame(parts[1].trim().to_string())),
+            ("games" | "ls", 1) => Ok(AdminCommand::ListGames),
+            ("ratings", 1) => Ok(AdminCommand::Ratings(None)),
+            ("ratings", 2) => Ok(AdminCommand::Ratings(Some(parts[1].trim().to_string()))),
+            ("history", 1) => Ok(AdminCommand::History(Vec::new())),
+            ("stats", 2) => Ok(AdminCommand::Stats(parts[1].trim().to_string())),
+            ("scenario", 2) => Ok(AdminCommand::Scenario(parts[1].trim().to_string())),
+            ("map", 2) => Ok(AdminCommand::ExportMap(parts[1].trim().to_string())),
+            ("queues", 2) => Ok(AdminCommand::Queues(parts[1].trim().to_string())),
+            ("bans", 1) => Ok(AdminCommand::Bans),
+            ("ban", 2) => {
+                let mut args = parts[1].split_whitespace();
+                let ip = parse_ip(args.next().unwrap_or_default())?;
+                let seconds = args
+                    .next()
+                    .map(|seconds| {
+                        seconds
+                            .parse()
+                            .map_err(|_| format!("Invalid duration: \"{seconds}\""))
+                    })
+                    .transpose()?;
+                Ok(AdminCommand::Ban(ip, seconds))
+            }
+            ("unban", 2) => Ok(AdminCommand::Unban(parse_ip(parts[1].trim())?)),
+            ("history", 2) => Ok(AdminCommand::History(
+                parts[1].split_whitespace().map(str::to_string).collect(),
+            )),
             _ => Err(format!("Unknown command: \"{s}\"")),
         }
     }
 }
 
+fn parse_ip(s: &str) -> Result<IpAddr, String> {
+    s.parse().map_err(|_| format!("Invalid address: \"{s}\""))
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum PlayerCmd {
     Move,
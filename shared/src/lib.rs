@@ -6,7 +6,10 @@ pub mod math;
 pub mod player;
 pub mod position;
 pub mod resource;
+pub mod stats;
 pub mod team;
+pub mod tls;
+pub mod vision;
 
 use cell::CellPos;
 use color::ZappyColor;
@@ -14,6 +17,7 @@ use map::Map;
 use player::Player;
 use position::{Direction, Position};
 use serde::{Deserialize, Serialize};
+use stats::TeamStats;
 use std::{
     collections::BTreeMap,
     fmt::{Display, Formatter},
@@ -26,6 +30,8 @@ pub struct GFXData {
     pub map: Map,
     pub players: BTreeMap<u16, Player>,
     pub teams: BTreeMap<String, (ZappyColor, usize)>,
+    /// Statistics of every team, including its dead players.
+    pub stats: BTreeMap<String, TeamStats>,
 }
 
 impl GFXData {
@@ -33,11 +39,13 @@ impl GFXData {
         map: Map,
         players: BTreeMap<u16, Player>,
         teams: BTreeMap<String, (ZappyColor, usize)>,
+        stats: BTreeMap<String, TeamStats>,
     ) -> Self {
         Self {
             map,
             players,
             teams,
+            stats,
         }
     }
 }
@@ -59,21 +67,35 @@ pub enum NetworkError {
     FailedToWriteToSocket(u16, String),
     FailedToReadFromSocket(u16, String),
     MessageCantBeMappedToFromUtf8(u16, String),
-    //TODO: delete when the any message size will be handled (or not :P)
-    MessageIsTooBig(u16),
+    /// A line longer than the maximum length, given second.
+    MessageIsTooBig(u16, usize),
+    /// The client didn't read its messages fast enough.
+    LaggingBehind(u16),
+    /// The client didn't send what was waited for, given second, in time.
+    TimedOut(u16, String),
+    /// The client sent too many lines per second, its address is banned.
+    Flooding(u16),
+    /// The address of the client was banned while it was connected.
+    Banned(u16),
+    /// The server is stopping and closes every connection.
+    ShuttingDown(u16),
 }
 
 #[derive(Debug, PartialEq)]
 pub enum GameError {
     IncreasingLevelButIsAlreadyMax(u16),
     IncreasingLevelWithNoIncantations(u16),
+    GameAlreadyExists(String),
+    InvalidGameArguments(String),
 }
 
 #[derive(Debug, PartialEq)]
 pub enum PlayerError {
     TeamDoesntExist(String),
+    GameDoesntExist(String),
     NoPlaceAvailable(u16, String),
     WrongUsernameOrPassword,
+    UnknownSession,
 }
 
 impl Display for GameError {
@@ -85,6 +107,8 @@ impl Display for GameError {
             GameError::IncreasingLevelButIsAlreadyMax(id) => {
                 format!("{id}: trying to stop incantation, but the max level is already reached")
             }
+            GameError::GameAlreadyExists(name) => format!("Game already exists: {name}"),
+            GameError::InvalidGameArguments(msg) => format!("Invalid game arguments: {msg}"),
         };
         write!(f, "{}", msg)
     }
@@ -102,7 +126,16 @@ impl Display for NetworkError {
             NetworkError::FailedToWriteToSocket(id, msg) => format!("{id}: {msg}"),
             NetworkError::FailedToReadFromSocket(id, msg) => format!("{id}: {msg}"),
             NetworkError::MessageCantBeMappedToFromUtf8(id, msg) => format!("{id}: {msg}"),
-            NetworkError::MessageIsTooBig(id) => format!("{id}: message is too large"),
+            NetworkError::LaggingBehind(id) => format!("{id}: too far behind its messages"),
+            NetworkError::Flooding(id) => format!("{id}: too many commands, banned"),
+            NetworkError::Banned(id) => format!("{id}: address banned"),
+            NetworkError::ShuttingDown(id) => format!("{id}: server is shutting down"),
+            NetworkError::TimedOut(id, waiting_for) => {
+                format!("{id}: timed out waiting for {waiting_for}")
+            }
+            NetworkError::MessageIsTooBig(id, max) => {
+                format!("{id}: line longer than {max} bytes")
+            }
         };
         write!(f, "{}", msg)
     }
@@ -112,15 +145,29 @@ impl Display for PlayerError {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         let msg = match self {
             PlayerError::TeamDoesntExist(team) => format!("Team does not exist: {}", team),
+            PlayerError::GameDoesntExist(game) => format!("Game does not exist: {}", game),
             PlayerError::NoPlaceAvailable(_, team_name) => {
                 format!("No place available on team {team_name}")
             }
             PlayerError::WrongUsernameOrPassword => "Wrong username or password".to_string(),
+            PlayerError::UnknownSession => "Unknown or expired session".to_string(),
         };
         write!(f, "{}", msg)
     }
 }
 
+impl Display for ZappyError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ZappyError::Network(err) => err.fmt(f),
+            ZappyError::Game(err) => err.fmt(f),
+            ZappyError::Player(err) => err.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for ZappyError {}
+
 pub enum ServerCommandToClient {
     Shutdown,
     SendMessage(ServerResponse),
@@ -139,7 +186,7 @@ pub enum ServerResponse {
     Mort,
     ActionQueueIsFull,
     Movement(Direction),
-    Message(u8, String),
+    Message(u8, Option<usize>, String),
 }
 
 impl Display for ServerResponse {
@@ -159,7 +206,10 @@ impl Display for ServerResponse {
                 write!(f, "The action queue is full, please try later.")
             }
             ServerResponse::Movement(from) => write!(f, "deplacement {from}"),
-            ServerResponse::Message(source, text) => write!(f, "message {source},{text}"),
+            ServerResponse::Message(source, None, text) => write!(f, "message {source},{text}"),
+            ServerResponse::Message(source, Some(distance), text) => {
+                write!(f, "message {source},{distance},{text}")
+            }
         }
     }
 }
@@ -172,12 +222,29 @@ pub struct Egg {
 }
 
 pub const HANDSHAKE_MSG: &'static str = "BIENVENUE\n";
-pub const GFX_PORT: u16 = 4343; // TODO configurable port
-pub const ADMIN_PORT: u16 = 4444; // TODO configurable port
+/// Last line sent to the gfx clients, in place of a state, when the server stops.
+pub const GFX_SHUTDOWN_MSG: &str = "Server is shutting down the connection.\n";
+/// Game joined by the players and watched by the gfx clients that don't choose one.
+pub const DEFAULT_GAME: &str = "default";
+/// Separates the team from the game in the handshake: `team@game`.
+pub const GAME_SEPARATOR: char = '@';
+/// Handshake of a player resuming its session: `resume <token>`.
+pub const RESUME_PREFIX: &str = "resume ";
+/// Last handshake line of the servers keeping sessions: `token <token>`.
+pub const TOKEN_PREFIX: &str = "token ";
+/// Default ports, the server listen addresses being configurable.
+pub const GFX_PORT: u16 = 4343;
+pub const ADMIN_PORT: u16 = 4444;
 
 pub const MAX_COMMANDS: usize = 10;
+pub const MAX_COMMANDS_PER_FRAME: usize = MAX_COMMANDS;
 pub const MAX_FIELD_SIZE: usize = 50;
+/// Largest width and height of a map.
+pub const MAX_MAP_SIZE: usize = 100;
+/// Largest number of each resource, and of the eggs of each team, in a cell of a map file.
+pub const MAX_CELL_COUNT: usize = 1000;
 pub const MAX_PLAYER_LVL: u8 = 8;
+pub const WINNING_PLAYERS_AT_MAX_LVL: usize = 6;
 pub const DECREASED_HP_PER_FRAME: u64 = 1;
 pub const MAX_PLAYERS_IN_TEAM: u16 = 1024;
 pub const MAX_TEAMS: usize = 14; // TODO: sync with ZappyColor
@@ -2,6 +2,7 @@ use crate::{
     cell::Cell,
     position::{Direction, Position},
     resource::Resource,
+    MAX_CELL_COUNT, MAX_MAP_SIZE,
 };
 use derive_getters::Getters;
 use rand::Rng;
@@ -27,20 +28,19 @@ impl Map {
     }
 
     // TODO: better procedural generation
-    pub fn generate_resources(&mut self) {
+    pub fn generate_resources(&mut self, rng: &mut impl Rng) {
         let total_resources = self.height * self.width * 13 / 5;
         for _ in 0..total_resources {
-            let Position { x, y, .. } = self.random_position();
-            self.field[y][x].add_resource(Resource::random());
+            let Position { x, y, .. } = self.random_position(rng);
+            self.field[y][x].add_resource(Resource::random(rng));
         }
     }
 
-    pub fn random_position(&self) -> Position {
-        let mut thread_rng = rand::thread_rng();
+    pub fn random_position(&self, rng: &mut impl Rng) -> Position {
         Position {
-            x: thread_rng.gen_range(0..self.width),
-            y: thread_rng.gen_range(0..self.height),
-            dir: Direction::random(),
+            x: rng.gen_range(0..self.width),
+            y: rng.gen_range(0..self.height),
+            dir: Direction::random(rng),
         }
     }
 
@@ -56,6 +56,163 @@ impl Map {
         self.field[position.y][position.x].players.remove(id);
     }
 
+    /// Coordinates of the cell `forward` cells ahead and `lateral` cells to the right of `position`
+    /// (see `vision`).
+    pub fn relative_cell(
+        &self,
+        position: &Position,
+        forward: usize,
+        lateral: isize,
+    ) -> (usize, usize) {
+        let (x, y, forward) = (position.x as isize, position.y as isize, forward as isize);
+        let (x, y) = match position.dir {
+            Direction::North => (x + lateral, y - forward),
+            Direction::East => (x + forward, y + lateral),
+            Direction::South => (x - lateral, y + forward),
+            Direction::West => (x - forward, y - lateral),
+        };
+        (
+            x.rem_euclid(self.width as isize) as usize,
+            y.rem_euclid(self.height as isize) as usize,
+        )
+    }
+
+    /// Resources lying on the map, indexed by `Resource::index`.
+    pub fn resource_totals(&self) -> [usize; Resource::SIZE] {
+        let mut totals = [0; Resource::SIZE];
+        for cell in self.field.iter().flatten() {
+            for (total, stones) in totals.iter_mut().zip(&cell.stones) {
+                *total += stones.len();
+            }
+            totals[Resource::Nourriture.index()] += cell.nourriture.len();
+        }
+        totals
+    }
+
+    /// The map in the text format read by `Map::from_text`. Eggs that haven't hatched yet aren't
+    /// written.
+    pub fn to_text(&self) -> String {
+        let tokens: Vec<Vec<String>> = self
+            .field
+            .iter()
+            .map(|row| row.iter().map(cell_token).collect())
+            .collect();
+        let column_width = tokens.iter().flatten().map(String::len).max().unwrap_or(1);
+        let mut text = format!("{} {}\n", self.width, self.height);
+        for row in &tokens {
+            let row = row
+                .iter()
+                .map(|token| format!("{token:<column_width$}"))
+                .collect::<Vec<_>>()
+                .join(" ");
+            text.push_str(row.trim_end());
+            text.push('\n');
+        }
+        for (y, row) in self.field.iter().enumerate() {
+            for (x, cell) in row.iter().enumerate() {
+                for (team, &(_, hatched)) in &cell.eggs {
+                    match hatched {
+                        0 => {}
+                        1 => text.push_str(&format!("egg {team} {x} {y}\n")),
+                        n => text.push_str(&format!("egg {team} {x} {y} {n}\n")),
+                    }
+                }
+            }
+        }
+        text
+    }
+
+    /// Reads a map written by hand or by `Map::to_text`: a `<width> <height>` line, one line per
+    /// row with one token per cell, then `egg <team> <x> <y> [count]` lines for the hatched eggs.
+    /// A token is `.` for an empty cell or the aliases of its resources, each one optionally
+    /// followed by a count (`N3L` is 3 nourriture and a linemate). `#` starts a comment.
+    /// The size is checked before allocating the map: at most `MAX_MAP_SIZE` a side, and at most
+    /// `MAX_CELL_COUNT` of each resource and of the eggs of each team in a cell.
+    pub fn from_text(text: &str) -> Result<Self, String> {
+        let mut lines = text
+            .lines()
+            .enumerate()
+            .map(|(i, line)| (i + 1, line.split('#').next().unwrap_or_default().trim()))
+            .filter(|(_, line)| !line.is_empty());
+        let (_, size) = lines.next().ok_or("Empty map")?;
+        let (width, height) = match size.split_whitespace().collect::<Vec<_>>()[..] {
+            [width, height] => (
+                width
+                    .parse()
+                    .map_err(|_| format!("Invalid width \"{width}\""))?,
+                height
+                    .parse()
+                    .map_err(|_| format!("Invalid height \"{height}\""))?,
+            ),
+            _ => return Err(format!("Expected \"<width> <height>\", got \"{size}\"")),
+        };
+        if width == 0 || height == 0 {
+            return Err("The map can't be empty".to_string());
+        }
+        if width > MAX_MAP_SIZE || height > MAX_MAP_SIZE {
+            return Err(format!(
+                "The map can't be larger than {MAX_MAP_SIZE}x{MAX_MAP_SIZE}"
+            ));
+        }
+        let mut map = Map::empty(width, height);
+        for y in 0..height {
+            let (number, row) = lines
+                .next()
+                .ok_or(format!("Missing row {y}, expected {height} rows"))?;
+            let tokens: Vec<&str> = row.split_whitespace().collect();
+            if tokens.len() != width {
+                return Err(format!(
+                    "Line {number}: expected {width} cells, got {}",
+                    tokens.len()
+                ));
+            }
+            for (x, token) in tokens.into_iter().enumerate() {
+                parse_token(token, &mut map.field[y][x])
+                    .map_err(|e| format!("Line {number}: {e}"))?;
+            }
+        }
+        for (number, line) in lines {
+            match line.split_whitespace().collect::<Vec<_>>()[..] {
+                ["egg", team, x, y, ref count @ ..] if count.len() <= 1 => {
+                    let parse = |value: &str| {
+                        value
+                            .parse::<usize>()
+                            .map_err(|_| format!("Line {number}: invalid number \"{value}\""))
+                    };
+                    let (x, y) = (parse(x)?, parse(y)?);
+                    let count = count.first().map_or(Ok(1), |count| parse(count))?;
+                    if x >= width || y >= height {
+                        return Err(format!("Line {number}: ({x}, {y}) is outside of the map"));
+                    }
+                    let eggs = &mut map.field[y][x]
+                        .eggs
+                        .entry(team.to_string())
+                        .or_insert((0, 0))
+                        .1;
+                    *eggs = eggs.saturating_add(count);
+                    if *eggs > MAX_CELL_COUNT {
+                        return Err(format!(
+                            "Line {number}: more than {MAX_CELL_COUNT} eggs of {team} in ({x}, {y})"
+                        ));
+                    }
+                }
+                _ => {
+                    return Err(format!(
+                        "Line {number}: expected \"egg <team> <x> <y> [count]\", got \"{line}\""
+                    ))
+                }
+            }
+        }
+        Ok(map)
+    }
+
+    /// Number of steps (diagonals included) between two cells of the torus.
+    pub fn distance(&self, a: &Position, b: &Position) -> usize {
+        let dx = a.x.abs_diff(b.x);
+        let dy = a.y.abs_diff(b.y);
+        dx.min(self.width - dx).max(dy.min(self.height - dy))
+    }
+
     pub fn find_broadcast_source(&self, sender_pos: &Position, receiver_pos: &Position) -> u8 {
         let (width, height, receiver_x, receiver_y, sender_x, sender_y) = (
             *self.width() as isize,
@@ -121,15 +278,92 @@ impl Map {
     }
 }
 
+fn cell_token(cell: &Cell) -> String {
+    let mut counts = [0; Resource::SIZE];
+    for resource in cell.get_resources_copy() {
+        counts[resource.index()] += 1;
+    }
+    let token: String = counts
+        .iter()
+        .enumerate()
+        .filter(|(_, &count)| count > 0)
+        .map(|(i, &count)| {
+            let alias = Resource::try_from(i).unwrap().alias();
+            match count {
+                1 => alias.to_string(),
+                n => format!("{alias}{n}"),
+            }
+        })
+        .collect();
+    if token.is_empty() {
+        ".".to_string()
+    } else {
+        token
+    }
+}
+
+fn parse_token(token: &str, cell: &mut Cell) -> Result<(), String> {
+    if token == "." {
+        return Ok(());
+    }
+    let mut counts = [0usize; Resource::SIZE];
+    let mut chars = token.chars().peekable();
+    while let Some(alias) = chars.next() {
+        let resource = (0..Resource::SIZE)
+            .map(|i| Resource::try_from(i).unwrap())
+            .find(|resource| resource.alias() == alias)
+            .ok_or(format!("Unknown resource \"{alias}\" in \"{token}\""))?;
+        let mut digits = String::new();
+        while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+            digits.push(digit);
+        }
+        let count = if digits.is_empty() {
+            1
+        } else {
+            digits
+                .parse()
+                .map_err(|_| format!("Invalid count in \"{token}\""))?
+        };
+        counts[resource.index()] += count;
+        if counts[resource.index()] > MAX_CELL_COUNT {
+            return Err(format!(
+                "More than {MAX_CELL_COUNT} \"{alias}\" in \"{token}\""
+            ));
+        }
+        for _ in 0..count {
+            cell.add_resource(resource);
+        }
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::resource::Stone;
 
     fn pos(x: usize, y: usize) -> Position {
         Position {
             x,
             y,
-            dir: Direction::random(),
+            dir: Direction::random(&mut rand::thread_rng()),
+        }
+    }
+
+    #[test]
+    fn test_distance() {
+        let map = Map::empty(5, 4);
+        let expected = [
+            [0, 1, 2, 2, 1],
+            [1, 1, 2, 2, 1],
+            [2, 2, 2, 2, 2],
+            [1, 1, 2, 2, 1],
+        ];
+        for (y, row) in expected.iter().enumerate() {
+            for (x, &distance) in row.iter().enumerate() {
+                assert_eq!(map.distance(&pos(0, 0), &pos(x, y)), distance);
+                assert_eq!(map.distance(&pos(x, y), &pos(0, 0)), distance);
+            }
         }
     }
 
@@ -182,4 +416,71 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn reads_and_writes_text_maps() {
+        // Given
+        let text = "\
+            # tournament map\n\
+            3 2\n\
+            .  N3L  .\n\
+            DS . T # thystame in the corner\n\
+            egg anton 0 0\n\
+            egg axel 2 1 2\n";
+
+        // When
+        let map = Map::from_text(text).unwrap();
+
+        // Then
+        assert_eq!((map.width, map.height), (3, 2));
+        assert_eq!(map.field[0][1].nourriture.len(), 3);
+        assert_eq!(map.field[0][1].stones[Stone::Linemate.index()].len(), 1);
+        assert_eq!(map.field[1][2].stones[Stone::Thystame.index()].len(), 1);
+        assert_eq!(map.field[0][0].eggs.get("anton"), Some(&(0, 1)));
+        assert_eq!(map.field[1][2].eggs.get("axel"), Some(&(0, 2)));
+        assert_eq!(
+            map.to_text(),
+            "3 2\n.   LN3 .\nDS  .   T\negg anton 0 0\negg axel 2 1 2\n"
+        );
+        assert_eq!(
+            Map::from_text(&map.to_text()).unwrap().resource_totals(),
+            map.resource_totals()
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_text_maps() {
+        assert_eq!(
+            Map::from_text("2 2\n. .\n.\n"),
+            Err("Line 3: expected 2 cells, got 1".to_string())
+        );
+        assert_eq!(
+            Map::from_text("2 1\n. X\n"),
+            Err("Line 2: Unknown resource \"X\" in \"X\"".to_string())
+        );
+        assert_eq!(
+            Map::from_text("2 1\n. .\negg anton 2 0\n"),
+            Err("Line 3: (2, 0) is outside of the map".to_string())
+        );
+    }
+
+    #[test]
+    fn rejects_oversized_text_maps() {
+        assert_eq!(
+            Map::from_text("99999 99999\n"),
+            Err("The map can't be larger than 100x100".to_string())
+        );
+        assert_eq!(
+            Map::from_text("2 1\nN99999999 .\n"),
+            Err("Line 2: More than 1000 \"N\" in \"N99999999\"".to_string())
+        );
+        assert_eq!(
+            Map::from_text("2 1\nN600N600 .\n"),
+            Err("Line 2: More than 1000 \"N\" in \"N600N600\"".to_string())
+        );
+        assert_eq!(
+            Map::from_text("2 1\n. .\negg anton 0 0 600\negg anton 0 0 600\n"),
+            Err("Line 4: more than 1000 eggs of anton in (0, 0)".to_string())
+        );
+    }
 }
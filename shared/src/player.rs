@@ -1,7 +1,11 @@
 use crate::commands::PlayerCmd;
 use crate::position::{Position, Side};
 use crate::resource::{Stone, StoneSet};
-use crate::{resource::Resource, GameError, DECREASED_HP_PER_FRAME, MAX_COMMANDS, MAX_PLAYER_LVL};
+use crate::stats::Stats;
+use crate::{
+    resource::Resource, GameError, DECREASED_HP_PER_FRAME, MAX_COMMANDS, MAX_COMMANDS_PER_FRAME,
+    MAX_PLAYER_LVL,
+};
 use crate::{LIFE_TICKS, LIVES_START};
 use derive_getters::Getters;
 use serde::{Deserialize, Serialize};
@@ -12,6 +16,7 @@ use std::fmt::{Display, Formatter};
 pub struct Player {
     team: String,
     id: u16,
+    /// First frame at which the next queued command can be executed.
     next_frame: u64,
     commands: VecDeque<PlayerCmd>,
     position: Position,
@@ -19,6 +24,9 @@ pub struct Player {
     level: u8,
     remaining_life: u64,
     is_performing_incantation: bool,
+    /// Left out of the gfx frames, which carry the statistics of the teams.
+    #[serde(skip)]
+    stats: Stats,
 }
 
 impl Player {
@@ -44,6 +52,7 @@ impl Player {
             level: 1,
             remaining_life: LIFE_TICKS * LIVES_START,
             is_performing_incantation: false,
+            stats: Stats::default(),
         }
     }
 
@@ -63,6 +72,11 @@ impl Player {
         self.position = position;
     }
 
+    /// Stones and players of the same level needed to rise from `level`, `None` at the max level.
+    pub fn level_requirements(level: u8) -> Option<&'static (StoneSet, usize)> {
+        Self::LEVEL_REQUIREMENTS.get((level as usize).checked_sub(1)?)
+    }
+
     pub fn nxt_lvl_stone_requirements(&self) -> &'static StoneSet {
         &Self::LEVEL_REQUIREMENTS[self.level as usize - 1].0
     }
@@ -76,6 +90,21 @@ impl Player {
         self.commands.pop_front()
     }
 
+    /// Pops the commands executed at `frame`. A command sets `next_frame` to `frame + delay`, so
+    /// the zero-delay commands (`Incantation`, `ConnectNbr`) don't consume the frame and the
+    /// following command is popped as well, up to `MAX_COMMANDS_PER_FRAME` commands.
+    pub fn pop_commands_for_frame(&mut self, frame: u64) -> Vec<PlayerCmd> {
+        let mut commands = Vec::new();
+        while frame >= self.next_frame && commands.len() < MAX_COMMANDS_PER_FRAME {
+            let Some(command) = self.commands.pop_front() else {
+                break;
+            };
+            self.next_frame = frame + command.delay();
+            commands.push(command);
+        }
+        commands
+    }
+
     pub fn push_command_to_queue(&mut self, command: PlayerCmd) {
         self.commands.push_back(command);
     }
@@ -84,6 +113,14 @@ impl Player {
         self.next_frame = value;
     }
 
+    pub fn set_level(&mut self, level: u8) {
+        self.level = level;
+    }
+
+    pub fn set_remaining_life(&mut self, value: u64) {
+        self.remaining_life = value;
+    }
+
     pub fn add_to_inventory(&mut self, resource: Resource) {
         match resource {
             Resource::Stone(stone) => {
@@ -115,6 +152,10 @@ impl Player {
         }
     }
 
+    pub fn stats_mut(&mut self) -> &mut Stats {
+        &mut self.stats
+    }
+
     pub fn decrease_life(&mut self) {
         self.remaining_life -= DECREASED_HP_PER_FRAME;
     }
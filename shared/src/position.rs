@@ -1,5 +1,5 @@
 use rand::seq::SliceRandom;
-use rand::thread_rng;
+use rand::Rng;
 use serde::{Deserialize, Serialize};
 use std::fmt::{Display, Formatter};
 
@@ -17,7 +17,7 @@ pub enum Side {
 }
 
 impl Direction {
-    pub fn random() -> Self {
+    pub fn random(rng: &mut impl Rng) -> Self {
         static DIRECTIONS: [Direction; 4] = [
             Direction::North,
             Direction::East,
@@ -25,8 +25,7 @@ impl Direction {
             Direction::West,
         ];
 
-        let mut rng = thread_rng();
-        *DIRECTIONS.choose(&mut rng).unwrap()
+        *DIRECTIONS.choose(rng).unwrap()
     }
 
     pub fn turn(&self, side: Side) -> Self {
@@ -1,5 +1,5 @@
 use crate::color::ZappyColor;
-use rand::{seq::SliceRandom as _, thread_rng};
+use rand::{seq::SliceRandom as _, Rng};
 use serde::{Deserialize, Serialize};
 use std::fmt::{Display, Formatter};
 
@@ -80,6 +80,14 @@ pub enum Resource {
 impl Resource {
     pub const SIZE: usize = Stone::SIZE + 1;
 
+    /// Stones first, then nourriture (the inverse of `TryFrom<usize>`).
+    pub fn index(self) -> usize {
+        match self {
+            Resource::Stone(stone) => stone.index(),
+            Resource::Nourriture => Stone::SIZE,
+        }
+    }
+
     pub fn alias(&self) -> char {
         match self {
             Resource::Stone(Stone::Deraumere) => 'D',
@@ -99,7 +107,7 @@ impl Resource {
         }
     }
 
-    pub fn random() -> Self {
+    pub fn random(rng: &mut impl Rng) -> Self {
         static RESOURCES_WEIGHTS: &[Resource] = &[
             Resource::Stone(Stone::Deraumere),
             Resource::Stone(Stone::Linemate),
@@ -111,8 +119,7 @@ impl Resource {
             Resource::Nourriture,
         ];
 
-        let mut rng = thread_rng();
-        *RESOURCES_WEIGHTS.choose(&mut rng).unwrap()
+        *RESOURCES_WEIGHTS.choose(rng).unwrap()
     }
 
     pub fn color(&self) -> ZappyColor {
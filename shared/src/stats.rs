@@ -0,0 +1,156 @@
+use crate::resource::Resource;
+use crate::MAX_PLAYER_LVL;
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+use std::ops::AddAssign;
+
+/// Gameplay counters of a player, or summed over a team.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Stats {
+    /// Cells walked with `Move`, being expelled doesn't count.
+    pub distance_moved: usize,
+    /// Resources taken, indexed by `Resource::index`.
+    pub taken: [usize; Resource::SIZE],
+    /// Resources put, indexed by `Resource::index`.
+    pub put: [usize; Resource::SIZE],
+    pub broadcasts: usize,
+    /// Players pushed away with `Expel`.
+    pub expels_done: usize,
+    /// Times pushed away by another player.
+    pub expels_suffered: usize,
+    /// Frames lived at each level, level 1 first.
+    pub frames_at_level: [u64; MAX_PLAYER_LVL as usize],
+    pub eggs_laid: usize,
+}
+
+impl AddAssign<&Stats> for Stats {
+    fn add_assign(&mut self, other: &Stats) {
+        self.distance_moved += other.distance_moved;
+        for i in 0..Resource::SIZE {
+            self.taken[i] += other.taken[i];
+            self.put[i] += other.put[i];
+        }
+        self.broadcasts += other.broadcasts;
+        self.expels_done += other.expels_done;
+        self.expels_suffered += other.expels_suffered;
+        for (frames, other_frames) in self.frames_at_level.iter_mut().zip(other.frames_at_level) {
+            *frames += other_frames;
+        }
+        self.eggs_laid += other.eggs_laid;
+    }
+}
+
+fn resources(counts: &[usize; Resource::SIZE]) -> String {
+    counts
+        .iter()
+        .enumerate()
+        .map(|(i, count)| format!("{}{count}", Resource::try_from(i).unwrap().alias()))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+impl Display for Stats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "moved {}, taken [{}], put [{}], broadcasts {}, expels {}/{}, eggs {}, frames per level {:?}",
+            self.distance_moved,
+            resources(&self.taken),
+            resources(&self.put),
+            self.broadcasts,
+            self.expels_done,
+            self.expels_suffered,
+            self.eggs_laid,
+            self.frames_at_level
+        )
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum DeathCause {
+    /// No more nourriture.
+    Starvation,
+    /// The client left the game.
+    Disconnection,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Death {
+    pub cause: DeathCause,
+    pub frame: u64,
+}
+
+/// Statistics of a player, alive or not.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PlayerStats {
+    pub id: u16,
+    pub team: String,
+    pub level: u8,
+    pub stats: Stats,
+    pub death: Option<Death>,
+}
+
+/// Statistics of all the players that were part of a team.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct TeamStats {
+    pub players: usize,
+    pub deaths: usize,
+    pub stats: Stats,
+}
+
+impl AddAssign<&PlayerStats> for TeamStats {
+    fn add_assign(&mut self, player: &PlayerStats) {
+        self.players += 1;
+        self.deaths += player.death.is_some() as usize;
+        self.stats += &player.stats;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resource::Stone;
+
+    #[test]
+    fn sums_player_stats_into_team_stats() {
+        // Given
+        let mut stats = Stats {
+            distance_moved: 3,
+            broadcasts: 1,
+            ..Stats::default()
+        };
+        stats.taken[Resource::Stone(Stone::Sibur).index()] = 2;
+        stats.frames_at_level[0] = 10;
+        let alive = PlayerStats {
+            id: 1,
+            team: "anton".to_string(),
+            level: 1,
+            stats: stats.clone(),
+            death: None,
+        };
+        let dead = PlayerStats {
+            id: 2,
+            death: Some(Death {
+                cause: DeathCause::Starvation,
+                frame: 10,
+            }),
+            ..alive.clone()
+        };
+
+        // When
+        let mut team = TeamStats::default();
+        team += &alive;
+        team += &dead;
+
+        // Then
+        assert_eq!((team.players, team.deaths), (2, 1));
+        assert_eq!(team.stats.distance_moved, 6);
+        assert_eq!(team.stats.taken, [0, 0, 0, 0, 4, 0, 0]);
+        assert_eq!(team.stats.frames_at_level[0], 20);
+        assert_eq!(
+            stats.to_string(),
+            "moved 3, taken [D0 L0 M0 P0 S2 T0 N0], put [D0 L0 M0 P0 S0 T0 N0], broadcasts 1, \
+             expels 0/0, eggs 0, frames per level [10, 0, 0, 0, 0, 0, 0, 0]"
+        );
+    }
+}
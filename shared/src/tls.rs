@@ -0,0 +1,33 @@
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, ServerName};
+use rustls::{ClientConfig, RootCertStore};
+use std::error::Error;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+/// A blocking stream, plain or TLS.
+pub trait ReadWrite: Read + Write {}
+impl<T: Read + Write + ?Sized> ReadWrite for T {}
+
+/// An async stream, plain or TLS.
+pub trait AsyncReadWrite: AsyncRead + AsyncWrite {}
+impl<T: AsyncRead + AsyncWrite + ?Sized> AsyncReadWrite for T {}
+
+/// The client side of a TLS port: the configuration trusting the certificates of the PEM file
+/// `ca`, and the name the certificate of the server is checked against.
+pub fn client_config(
+    ca: &Path,
+    server_name: &str,
+) -> Result<(Arc<ClientConfig>, ServerName<'static>), Box<dyn Error>> {
+    let mut roots = RootCertStore::empty();
+    for certificate in CertificateDer::pem_file_iter(ca)? {
+        roots.add(certificate?)?;
+    }
+    let config = ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    let name = ServerName::try_from(server_name.to_string())?;
+    Ok((Arc::new(config), name))
+}
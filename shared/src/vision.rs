@@ -0,0 +1,65 @@
+//! Order of the cells in the `See` response.
+//!
+//! A player of level `L` sees `L + 1` lines in front of them, line `forward` being made of the
+//! `2 * forward + 1` cells from `lateral = -forward` (their left) to `lateral = forward` (their
+//! right). Cells are reported line by line, so the cell `(forward, lateral)` has the index
+//! `forward² + forward + lateral`.
+
+/// Number of cells seen by a player of the given level.
+pub fn cell_count(level: u8) -> usize {
+    (level as usize + 1).pow(2)
+}
+
+/// Index in the `See` response of the cell `forward` lines ahead and `lateral` cells to the right.
+pub fn cell_index(forward: usize, lateral: isize) -> usize {
+    (forward * forward + forward).wrapping_add_signed(lateral)
+}
+
+/// Relative coordinates `(forward, lateral)` of the cell at `index` in the `See` response.
+pub fn relative_cell(index: usize) -> (usize, isize) {
+    let forward = index.isqrt();
+    (
+        forward,
+        index as isize - (forward * forward + forward) as isize,
+    )
+}
+
+/// Relative coordinates of the cells seen by a player of the given level, in response order.
+pub fn cone(level: u8) -> impl Iterator<Item = (usize, isize)> {
+    (0..=level as usize)
+        .flat_map(|forward| (-(forward as isize)..=forward as isize).map(move |l| (forward, l)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cone_order() {
+        assert_eq!(
+            cone(2).collect::<Vec<_>>(),
+            vec![
+                (0, 0),
+                (1, -1),
+                (1, 0),
+                (1, 1),
+                (2, -2),
+                (2, -1),
+                (2, 0),
+                (2, 1),
+                (2, 2)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_index_matches_cone() {
+        for level in 1..=8 {
+            assert_eq!(cone(level).count(), cell_count(level));
+            for (index, (forward, lateral)) in cone(level).enumerate() {
+                assert_eq!(cell_index(forward, lateral), index);
+                assert_eq!(relative_cell(index), (forward, lateral));
+            }
+        }
+    }
+}